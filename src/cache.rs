@@ -0,0 +1,656 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crate::dns::{
+    question::CacheKey, zone, DnsAnswerRecord, DnsQuestion, RecordType, ResponseCode, ZoneError,
+};
+
+/// TTL of the expired answers served when fresh ones cannot be obtained (RFC 8767 section 4).
+const STALE_TTL: u32 = 30;
+
+/// How long answers may still be served once they have expired (RFC 8767 section 5).
+const MAX_STALENESS: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most answers kept for distinct questions, and as many negative answers, so that clients asking
+/// about ever new names cannot make the cache grow without bound.
+const MAX_ENTRIES: usize = 10_000;
+
+/// How often the entries that can no longer be served are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Answers kept for a question until the smallest TTL among them runs out.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    records: Vec<DnsAnswerRecord>,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+impl CacheEntry {
+    fn new(records: Vec<DnsAnswerRecord>, now: Instant) -> Self {
+        let time_to_live = records
+            .iter()
+            .map(|record| record.time_to_live)
+            .min()
+            .unwrap_or(0);
+
+        CacheEntry {
+            records,
+            inserted_at: now,
+            expires_at: now + Duration::from_secs(u64::from(time_to_live)),
+        }
+    }
+}
+
+/// A negative answer (RFC 2308) kept until the SOA record that came with it allows.
+#[derive(Debug, Clone)]
+struct NegativeEntry {
+    response_code: ResponseCode,
+    expires_at: Instant,
+}
+
+/// What the cache knows about a question.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheHit {
+    /// The answers to the question, with their remaining TTLs.
+    Answers(Vec<DnsAnswerRecord>),
+    /// The question is known to have no answer: `NameError` when the name does not exist, and
+    /// `NoError` when it has no record of the requested type.
+    NegativeHit(ResponseCode),
+}
+
+/// Caches the answers obtained from other servers, keyed by question.
+///
+/// Entries are forgotten once they can no longer be served, even stale, and the answers expiring
+/// first make room for new ones when the cache is full.
+#[derive(Debug, Clone, Default)]
+pub struct DnsCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    negative_entries: HashMap<CacheKey, NegativeEntry>,
+    last_pruned: Option<Instant>,
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        DnsCache::default()
+    }
+
+    /// Stores the answers to `question`, replacing any previous ones.
+    pub fn insert(&mut self, question: &DnsQuestion, records: Vec<DnsAnswerRecord>) {
+        self.insert_at(question, records, Instant::now());
+    }
+
+    /// Remembers that `question` has no answer, as told by a reply with `response_code` and the
+    /// given authority section.
+    ///
+    /// The negative answer is kept for the smallest of the TTL and the MINIMUM field of the SOA
+    /// record found in the authority section (RFC 2308 section 5). Without a SOA record it is not
+    /// kept at all.
+    pub fn insert_negative(
+        &mut self,
+        question: &DnsQuestion,
+        response_code: ResponseCode,
+        authorities: &[DnsAnswerRecord],
+    ) {
+        self.insert_negative_at(question, response_code, authorities, Instant::now());
+    }
+
+    /// Returns the answers to `question` with their TTLs reduced by the time spent in the cache,
+    /// or the negative answer it got, unless they have expired.
+    pub fn get(&self, question: &DnsQuestion) -> Option<CacheHit> {
+        self.get_hit_at(question, Instant::now())
+    }
+
+    /// Returns the answers to `question` even if they have expired, as long as they did so less
+    /// than a day ago. Expired answers get a short TTL, so that clients soon ask again.
+    ///
+    /// This is meant for when fresh answers cannot be obtained (RFC 8767).
+    pub fn get_stale(&self, question: &DnsQuestion) -> Option<Vec<DnsAnswerRecord>> {
+        self.get_stale_at(question, Instant::now())
+    }
+
+    /// Writes every live entry as zone file lines carrying the remaining TTLs, so that
+    /// [`crate::dns::zone::load`] can bring them back.
+    pub fn dump_zone<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.dump_zone_at(writer, Instant::now())
+    }
+
+    /// Repopulates the cache with the records of a zone file written by [`DnsCache::dump_zone`].
+    ///
+    /// Records are grouped by owner, type and class. The aliases are also grouped with the records
+    /// their chain leads to, under the name they were asked about and the type at the end of the
+    /// chain, like the answers that followed them were cached.
+    pub fn load_zone(&mut self, text: &str) -> Result<(), ZoneError> {
+        self.load_zone_at(text, Instant::now())
+    }
+
+    fn insert_at(&mut self, question: &DnsQuestion, records: Vec<DnsAnswerRecord>, now: Instant) {
+        self.prune_at(now);
+        let key = question.cache_key();
+        self.negative_entries.remove(&key);
+        make_room(&mut self.entries, &key, |entry| entry.expires_at);
+        self.entries.insert(key, CacheEntry::new(records, now));
+    }
+
+    fn insert_negative_at(
+        &mut self,
+        question: &DnsQuestion,
+        response_code: ResponseCode,
+        authorities: &[DnsAnswerRecord],
+        now: Instant,
+    ) {
+        let Some(time_to_live) = authorities
+            .iter()
+            .find_map(|record| soa_minimum(record).map(|minimum| minimum.min(record.time_to_live)))
+        else {
+            return;
+        };
+
+        self.prune_at(now);
+        let key = question.cache_key();
+        self.entries.remove(&key);
+        make_room(&mut self.negative_entries, &key, |entry| entry.expires_at);
+        self.negative_entries.insert(
+            key,
+            NegativeEntry {
+                response_code,
+                expires_at: now + Duration::from_secs(u64::from(time_to_live)),
+            },
+        );
+    }
+
+    fn load_zone_at(&mut self, text: &str, now: Instant) -> Result<(), ZoneError> {
+        let mut record_sets: HashMap<CacheKey, Vec<DnsAnswerRecord>> = HashMap::new();
+        for record in zone::load(text)? {
+            let question = DnsQuestion {
+                domain_name: record.domain_name.clone(),
+                record_type: record.record_type,
+                class: record.class,
+            };
+            // Answers following aliases repeat the records of their target
+            let records = record_sets.entry(question.cache_key()).or_default();
+            if !records.contains(&record) {
+                records.push(record);
+            }
+        }
+
+        let mut chains: Vec<(CacheKey, Vec<DnsAnswerRecord>)> = Vec::new();
+        for ((owner, record_type, class), aliases) in &record_sets {
+            if *record_type != RecordType::CNAME {
+                continue;
+            }
+
+            let mut chain = aliases.clone();
+            let mut target = aliases.first().and_then(|alias| alias.r_data.as_cname());
+            for _ in 0..zone::MAX_CNAME_CHAIN_LENGTH {
+                let Some(name) = target.take() else {
+                    break;
+                };
+                let name = name.to_ascii_lowercase().wire_format;
+                for ((target_owner, target_type, target_class), records) in &record_sets {
+                    if *target_owner == name
+                        && target_class == class
+                        && *target_type != RecordType::CNAME
+                    {
+                        let key = (owner.clone(), *target_type, *class);
+                        chains.push((key, [chain.clone(), records.clone()].concat()));
+                    }
+                }
+
+                if let Some(next_aliases) = record_sets.get(&(name, RecordType::CNAME, *class)) {
+                    chain.extend(next_aliases.iter().cloned());
+                    target = next_aliases
+                        .first()
+                        .and_then(|alias| alias.r_data.as_cname());
+                }
+            }
+        }
+        for (key, records) in chains {
+            record_sets.entry(key).or_insert(records);
+        }
+
+        self.prune_at(now);
+        for (key, records) in record_sets {
+            make_room(&mut self.entries, &key, |entry| entry.expires_at);
+            self.entries.insert(key, CacheEntry::new(records, now));
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the answers too old to be served even stale and the expired negative answers, at
+    /// most once per [`PRUNE_INTERVAL`], so that the cache does not grow forever.
+    fn prune_at(&mut self, now: Instant) {
+        if self
+            .last_pruned
+            .is_some_and(|last_pruned| now.saturating_duration_since(last_pruned) < PRUNE_INTERVAL)
+        {
+            return;
+        }
+
+        self.entries
+            .retain(|_, entry| now.saturating_duration_since(entry.expires_at) <= MAX_STALENESS);
+        self.negative_entries
+            .retain(|_, entry| now < entry.expires_at);
+        self.last_pruned = Some(now);
+    }
+
+    fn get_at(&self, question: &DnsQuestion, now: Instant) -> Option<Vec<DnsAnswerRecord>> {
+        self.entries
+            .get(&question.cache_key())
+            .and_then(|entry| live_records(entry, now))
+    }
+
+    fn get_hit_at(&self, question: &DnsQuestion, now: Instant) -> Option<CacheHit> {
+        if let Some(records) = self.get_at(question, now) {
+            return Some(CacheHit::Answers(records));
+        }
+
+        self.negative_entries
+            .get(&question.cache_key())
+            .filter(|entry| now < entry.expires_at)
+            .map(|entry| CacheHit::NegativeHit(entry.response_code))
+    }
+
+    fn get_stale_at(&self, question: &DnsQuestion, now: Instant) -> Option<Vec<DnsAnswerRecord>> {
+        let entry = self.entries.get(&question.cache_key())?;
+        if now < entry.expires_at {
+            return live_records(entry, now);
+        }
+        if now.duration_since(entry.expires_at) > MAX_STALENESS {
+            return None;
+        }
+
+        Some(
+            entry
+                .records
+                .iter()
+                .map(|record| DnsAnswerRecord {
+                    time_to_live: STALE_TTL,
+                    ..record.clone()
+                })
+                .collect(),
+        )
+    }
+
+    fn dump_zone_at<W: Write>(&self, writer: &mut W, now: Instant) -> std::io::Result<()> {
+        for records in self
+            .entries
+            .values()
+            .filter_map(|entry| live_records(entry, now))
+        {
+            for record in records {
+                writeln!(writer, "{}", record.to_zone_line())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Evicts the entry expiring first when `entries` is full and has none for `key` yet.
+fn make_room<T>(
+    entries: &mut HashMap<CacheKey, T>,
+    key: &CacheKey,
+    expires_at: impl Fn(&T) -> Instant,
+) {
+    if entries.len() < MAX_ENTRIES || entries.contains_key(key) {
+        return;
+    }
+
+    if let Some(first_expiring) = entries
+        .iter()
+        .min_by_key(|(_, entry)| expires_at(entry))
+        .map(|(key, _)| key.clone())
+    {
+        entries.remove(&first_expiring);
+    }
+}
+
+/// Reads the MINIMUM field of a SOA record, the last of the five 32-bit fields following the
+/// two domain names of its RDATA (RFC 1035 section 3.3.13).
+fn soa_minimum(record: &DnsAnswerRecord) -> Option<u32> {
+    if record.record_type != RecordType::SOA {
+        return None;
+    }
+
+    let r_data = &record.r_data.0;
+    let minimum = r_data.get(r_data.len().checked_sub(4)?..)?;
+
+    Some(u32::from_be_bytes([
+        minimum[0], minimum[1], minimum[2], minimum[3],
+    ]))
+}
+
+fn live_records(entry: &CacheEntry, now: Instant) -> Option<Vec<DnsAnswerRecord>> {
+    if now >= entry.expires_at {
+        return None;
+    }
+
+    let elapsed = now.duration_since(entry.inserted_at);
+
+    Some(
+        entry
+            .records
+            .iter()
+            .map(|record| record.with_decremented_ttl(elapsed))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::{answer_record::RData, Class, DomainName};
+
+    use super::*;
+
+    fn record(name: &str, record_type: RecordType, time_to_live: u32) -> DnsAnswerRecord {
+        DnsAnswerRecord {
+            domain_name: name.parse().unwrap(),
+            record_type,
+            class: Class::IN,
+            time_to_live,
+            r_data_length: 4,
+            r_data: RData(vec![1, 2, 3, 4]),
+        }
+    }
+
+    fn question(name: &str, record_type: RecordType) -> DnsQuestion {
+        DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type,
+            class: Class::IN,
+        }
+    }
+
+    #[test]
+    fn test_cache_dump_zone_roundtrip() {
+        let now = Instant::now();
+        let mut cache = DnsCache::new();
+        cache.insert_at(
+            &question("www.example.com", RecordType::A),
+            vec![
+                record("www.example.com", RecordType::A, 300),
+                record("www.example.com", RecordType::A, 600),
+            ],
+            now,
+        );
+        cache.insert_at(
+            &question("short.example.com", RecordType::A),
+            vec![record("short.example.com", RecordType::A, 5)],
+            now,
+        );
+
+        // Ten seconds later the second entry has expired and the others have aged
+        let later = now + Duration::from_secs(10);
+        let mut zone_file: Vec<u8> = Vec::new();
+        cache.dump_zone_at(&mut zone_file, later).unwrap();
+        let zone_file = String::from_utf8(zone_file).unwrap();
+
+        assert_eq!(
+            zone::load(&zone_file),
+            Ok(vec![
+                record("www.example.com", RecordType::A, 290),
+                record("www.example.com", RecordType::A, 590),
+            ])
+        );
+
+        // Reloading the dump into a new cache gives back the same answers
+        let mut restored = DnsCache::new();
+        restored.load_zone_at(&zone_file, later).unwrap();
+        assert_eq!(
+            restored.get_at(&question("WWW.example.com", RecordType::A), later),
+            cache.get_at(&question("www.example.com", RecordType::A), later)
+        );
+        assert_eq!(
+            restored.get_at(&question("short.example.com", RecordType::A), later),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_dump_zone_roundtrip_with_cname_chain() {
+        let now = Instant::now();
+        let cname = |name: &str, target: &str| {
+            let target = target.parse::<DomainName>().unwrap().wire_format;
+            DnsAnswerRecord {
+                domain_name: name.parse().unwrap(),
+                record_type: RecordType::CNAME,
+                class: Class::IN,
+                time_to_live: 300,
+                r_data_length: target.len(),
+                r_data: RData(target),
+            }
+        };
+        let www_example_com = question("www.example.com", RecordType::A);
+        let web_example_com = question("web.example.com", RecordType::A);
+        let mut cache = DnsCache::new();
+        cache.insert_at(
+            &www_example_com,
+            vec![
+                cname("www.example.com", "web.example.com"),
+                cname("web.example.com", "host.example.com"),
+                record("host.example.com", RecordType::A, 300),
+            ],
+            now,
+        );
+        cache.insert_at(
+            &web_example_com,
+            vec![
+                cname("web.example.com", "host.example.com"),
+                record("host.example.com", RecordType::A, 300),
+            ],
+            now,
+        );
+
+        let mut zone_file: Vec<u8> = Vec::new();
+        cache.dump_zone_at(&mut zone_file, now).unwrap();
+        let mut restored = DnsCache::new();
+        restored
+            .load_zone_at(&String::from_utf8(zone_file).unwrap(), now)
+            .unwrap();
+
+        // The chains come back under the names they were asked about
+        assert!(restored.get_at(&www_example_com, now).is_some());
+        assert_eq!(
+            restored.get_at(&www_example_com, now),
+            cache.get_at(&www_example_com, now)
+        );
+        assert_eq!(
+            restored.get_at(&web_example_com, now),
+            cache.get_at(&web_example_com, now)
+        );
+        assert_eq!(
+            restored.get_at(&question("host.example.com", RecordType::A), now),
+            Some(vec![record("host.example.com", RecordType::A, 300)])
+        );
+    }
+
+    #[test]
+    fn test_cache_get_stale() {
+        let now = Instant::now();
+        let www_example_com = question("www.example.com", RecordType::A);
+        let mut cache = DnsCache::new();
+        cache.insert_at(
+            &www_example_com,
+            vec![record("www.example.com", RecordType::A, 60)],
+            now,
+        );
+
+        // Fresh answers are served as usual
+        let fresh = now + Duration::from_secs(10);
+        assert_eq!(
+            cache.get_stale_at(&www_example_com, fresh),
+            cache.get_at(&www_example_com, fresh)
+        );
+
+        // Expired answers are only served on request, with a short TTL
+        let expired = now + Duration::from_secs(120);
+        assert_eq!(cache.get_at(&www_example_com, expired), None);
+        assert_eq!(
+            cache.get_stale_at(&www_example_com, expired),
+            Some(vec![record("www.example.com", RecordType::A, STALE_TTL)])
+        );
+
+        // Until they are too old
+        assert_eq!(
+            cache.get_stale_at(&www_example_com, expired + MAX_STALENESS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_prunes_unservable_entries() {
+        let now = Instant::now();
+        let www_example_com = question("www.example.com", RecordType::A);
+        let mut cache = DnsCache::new();
+        cache.insert_at(
+            &www_example_com,
+            vec![record("www.example.com", RecordType::A, 60)],
+            now,
+        );
+
+        // Expired answers are kept while they may still be served stale
+        let ftp_example_com = question("ftp.example.com", RecordType::A);
+        let expired = now + Duration::from_secs(120);
+        cache.insert_at(
+            &ftp_example_com,
+            vec![record("ftp.example.com", RecordType::A, 60)],
+            expired,
+        );
+        assert_eq!(cache.entries.len(), 2);
+
+        // And forgotten once they are too old for that
+        let mail_example_com = question("mail.example.com", RecordType::A);
+        cache.insert_at(
+            &mail_example_com,
+            vec![record("mail.example.com", RecordType::A, 60)],
+            expired + MAX_STALENESS,
+        );
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(
+            cache.get_stale_at(&www_example_com, expired + MAX_STALENESS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_evicts_first_expiring_entry_when_full() {
+        let now = Instant::now();
+        let mut cache = DnsCache::new();
+        for index in 0..MAX_ENTRIES {
+            let name = format!("host{}.example.com", index);
+            let time_to_live = if index == 42 { 10 } else { 60 };
+            cache.insert_at(
+                &question(&name, RecordType::A),
+                vec![record(&name, RecordType::A, time_to_live)],
+                now,
+            );
+        }
+
+        // Replacing an entry needs no room
+        let host0 = question("host0.example.com", RecordType::A);
+        cache.insert_at(
+            &host0,
+            vec![record("host0.example.com", RecordType::A, 60)],
+            now,
+        );
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+
+        // A new one takes the place of the entry expiring first
+        let extra = question("extra.example.com", RecordType::A);
+        cache.insert_at(
+            &extra,
+            vec![record("extra.example.com", RecordType::A, 60)],
+            now,
+        );
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+        assert!(cache.get_at(&extra, now).is_some());
+        assert_eq!(
+            cache.get_at(&question("host42.example.com", RecordType::A), now),
+            None
+        );
+        assert!(cache.get_at(&host0, now).is_some());
+    }
+
+    #[test]
+    fn test_cache_negative_entries() {
+        let now = Instant::now();
+        let missing = question("missing.example.com", RecordType::A);
+        let soa = DnsAnswerRecord {
+            domain_name: "example.com".parse().unwrap(),
+            record_type: RecordType::SOA,
+            class: Class::IN,
+            time_to_live: 300,
+            r_data_length: 0,
+            r_data: RData(
+                [
+                    "ns.example.com".parse::<DomainName>().unwrap().wire_format,
+                    "admin.example.com"
+                        .parse::<DomainName>()
+                        .unwrap()
+                        .wire_format,
+                    [1u32, 7200, 3600, 1209600, 30]
+                        .iter()
+                        .flat_map(|field| field.to_be_bytes())
+                        .collect(),
+                ]
+                .concat(),
+            ),
+        };
+
+        // Without a SOA record the negative answer is not kept
+        let mut cache = DnsCache::new();
+        cache.insert_negative_at(&missing, ResponseCode::NameError, &[], now);
+        assert_eq!(cache.get_hit_at(&missing, now), None);
+
+        // With one, it is kept for the SOA minimum, smaller than the SOA TTL
+        cache.insert_negative_at(
+            &missing,
+            ResponseCode::NameError,
+            std::slice::from_ref(&soa),
+            now,
+        );
+        assert_eq!(
+            cache.get_hit_at(&missing, now + Duration::from_secs(29)),
+            Some(CacheHit::NegativeHit(ResponseCode::NameError))
+        );
+        assert_eq!(
+            cache.get_hit_at(&missing, now + Duration::from_secs(30)),
+            None
+        );
+
+        // A SOA TTL smaller than its minimum wins
+        let short_lived_soa = DnsAnswerRecord {
+            time_to_live: 5,
+            ..soa
+        };
+        cache.insert_negative_at(&missing, ResponseCode::NoError, &[short_lived_soa], now);
+        assert_eq!(
+            cache.get_hit_at(&missing, now + Duration::from_secs(4)),
+            Some(CacheHit::NegativeHit(ResponseCode::NoError))
+        );
+        assert_eq!(
+            cache.get_hit_at(&missing, now + Duration::from_secs(5)),
+            None
+        );
+
+        // Positive answers replace negative ones
+        cache.insert_at(
+            &missing,
+            vec![record("missing.example.com", RecordType::A, 60)],
+            now,
+        );
+        assert_eq!(
+            cache.get_hit_at(&missing, now),
+            Some(CacheHit::Answers(vec![record(
+                "missing.example.com",
+                RecordType::A,
+                60
+            )]))
+        );
+    }
+}
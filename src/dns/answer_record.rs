@@ -1,4 +1,21 @@
-use crate::dns::{Class, DomainName, RecordType};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use crate::dns::{
+    domain_name::NameCompressor, Class, DnsError, DnsQuestion, DomainName, RecordType,
+};
+
+/// Maximum length of a character string, prefixed by a length octet (RFC 1035 section 3.3).
+const MAX_CHARACTER_STRING_LENGTH: usize = 255;
+
+/// Maximum length of the property tag of a CAA record (RFC 8659 section 4.1).
+const MAX_CAA_TAG_LENGTH: usize = 15;
+
+/// Size of the smallest possible record: the root name followed by the type, class, TTL and an
+/// RDLENGTH of zero.
+pub(crate) const MIN_RECORD_SIZE: usize = 11;
 
 /// Represents the raw resource data (RDATA) of a DNS resource record.
 ///
@@ -8,31 +25,388 @@ use crate::dns::{Class, DomainName, RecordType};
 pub struct RData(pub Vec<u8>);
 
 impl RData {
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet_slice: &[u8]) -> Result<Self, ()> {
-        if packet_slice.len() < 3 {
-            return Err(());
+        let r_data_length = match packet_slice.get(..2) {
+            Some(&[first_byte, second_byte]) => {
+                usize::from(u16::from_be_bytes([first_byte, second_byte]))
+            }
+            _ => return Err(()),
+        };
+
+        // The whole RDATA must be within the slice, checked before copying any of it
+        match packet_slice.get(2..r_data_length + 2) {
+            Some(bytes) => Ok(RData(bytes.to_vec())),
+            None => Err(()),
         }
+    }
 
-        let r_data_length = u16::from_be_bytes([packet_slice[0], packet_slice[1]]) as usize;
-        let mut wire_format: Vec<u8> = Vec::new();
+    /// Interprets the data as the IPv4 address of an A record.
+    pub fn as_a(&self) -> Option<Ipv4Addr> {
+        <[u8; 4]>::try_from(self.0.as_slice())
+            .ok()
+            .map(Ipv4Addr::from)
+    }
 
-        for idx in 2..r_data_length + 2 {
-            match packet_slice.get(idx) {
-                Some(byte) => {
-                    wire_format.push(*byte);
-                }
-                None => break,
+    /// Interprets the data as the IPv6 address of an AAAA record.
+    pub fn as_aaaa(&self) -> Option<Ipv6Addr> {
+        <[u8; 16]>::try_from(self.0.as_slice())
+            .ok()
+            .map(Ipv6Addr::from)
+    }
+
+    /// Interprets the data as the name server domain name of an NS record.
+    pub fn as_ns(&self) -> Option<DomainName> {
+        self.as_single_name()
+    }
+
+    /// Interprets the data as the canonical name an alias points to, in a CNAME record.
+    pub fn as_cname(&self) -> Option<DomainName> {
+        self.as_ns()
+    }
+
+    /// Interprets the data as a single uncompressed domain name taking it up whole, as in NS and
+    /// CNAME records and in the obsolete MB, MD, MF, MG and MR mail records (RFC 1035 section 3.3).
+    pub fn as_single_name(&self) -> Option<DomainName> {
+        DomainName::new(&self.0)
+            .ok()
+            .filter(|domain_name| domain_name.wire_format.len() == self.0.len())
+    }
+
+    /// Interprets the data of an MINFO record: the uncompressed names of the mailbox responsible
+    /// for a mailing list and of the mailbox receiving its errors (RFC 1035 section 3.3.7).
+    pub fn as_minfo(&self) -> Option<(DomainName, DomainName)> {
+        let responsible_mailbox = DomainName::new(&self.0).ok()?;
+        let error_mailbox =
+            RData(self.0[responsible_mailbox.encoded_len()..].to_vec()).as_single_name()?;
+
+        Some((responsible_mailbox, error_mailbox))
+    }
+
+    /// Interprets the data as the domain name pointed to by a PTR record, which may be compressed
+    /// against `packet`, the message the record was found in.
+    pub fn as_ptr(&self, packet: &[u8]) -> Option<DomainName> {
+        DomainName::new_in_packet(&self.0, packet).ok()
+    }
+
+    /// Interprets the data as the character strings of a TXT record, each prefixed by its length.
+    pub fn as_txt(&self) -> Option<Vec<String>> {
+        let mut strings: Vec<String> = Vec::new();
+        let mut rest = self.0.as_slice();
+
+        while let Some((&length, tail)) = rest.split_first() {
+            let string = tail.get(..usize::from(length))?;
+            strings.push(String::from_utf8_lossy(string).into_owned());
+            rest = &tail[usize::from(length)..];
+        }
+
+        Some(strings).filter(|strings| !strings.is_empty())
+    }
+
+    /// Interprets the data of a CAA record: a flags octet, a length-prefixed tag, then the value
+    /// taking up the rest of the data.
+    pub fn as_caa(&self) -> Option<Caa> {
+        let flags = *self.0.first()?;
+        let tag_length = usize::from(*self.0.get(1)?);
+        if tag_length == 0 {
+            return None;
+        }
+        let tag = self.0.get(2..2 + tag_length)?;
+        let value = &self.0[2 + tag_length..];
+
+        Some(Caa {
+            flags,
+            tag: String::from_utf8(tag.to_vec()).ok()?,
+            value: String::from_utf8(value.to_vec()).ok()?,
+        })
+    }
+
+    /// Encodes a CAA record's data, the reverse of [`RData::as_caa`].
+    ///
+    /// Fails when the tag is not made of 1 to 15 ASCII letters and digits (RFC 8659 section 4.1).
+    pub fn from_caa(caa: &Caa) -> Result<Self, DnsError> {
+        if !(1..=MAX_CAA_TAG_LENGTH).contains(&caa.tag.len())
+            || !caa.tag.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        {
+            return Err(DnsError::InvalidCaaTag(caa.tag.clone()));
+        }
+
+        Ok(RData(
+            [
+                &[caa.flags, caa.tag.len() as u8][..],
+                caa.tag.as_bytes(),
+                caa.value.as_bytes(),
+            ]
+            .concat(),
+        ))
+    }
+
+    /// Interprets the data of a HINFO record: the CPU and the operating system of the host, as two
+    /// character strings (RFC 1035 section 3.3.2).
+    pub fn as_hinfo(&self) -> Option<(String, String)> {
+        let [cpu, os] = <[String; 2]>::try_from(self.as_txt()?).ok()?;
+
+        Some((cpu, os))
+    }
+
+    /// Encodes a HINFO record's data, the reverse of [`RData::as_hinfo`].
+    ///
+    /// Fails when either string is longer than 255 bytes.
+    pub fn from_hinfo(cpu: &str, os: &str) -> Result<Self, DnsError> {
+        for string in [cpu, os] {
+            if string.len() > MAX_CHARACTER_STRING_LENGTH {
+                return Err(DnsError::CharacterStringTooLong(string.len()));
             }
         }
 
-        if wire_format.len() == r_data_length {
-            Ok(RData(wire_format))
-        } else {
-            Err(())
+        Ok(RData(
+            [
+                &[cpu.len() as u8][..],
+                cpu.as_bytes(),
+                &[os.len() as u8],
+                os.as_bytes(),
+            ]
+            .concat(),
+        ))
+    }
+
+    /// Interprets the data of a DNSKEY record: flags, protocol and algorithm, then the public key
+    /// taking up the rest of the data (RFC 4034 section 2.1).
+    pub fn as_dnskey(&self) -> Option<Dnskey> {
+        let fixed_fields = self.0.get(..4)?;
+
+        Some(Dnskey {
+            flags: u16::from_be_bytes([fixed_fields[0], fixed_fields[1]]),
+            protocol: fixed_fields[2],
+            algorithm: fixed_fields[3],
+            public_key: self.0[4..].to_vec(),
+        })
+    }
+
+    /// Interprets the data of a RRSIG record: the fixed-size fields describing the signature,
+    /// the uncompressed name of the signer, then the signature taking up the rest of the data
+    /// (RFC 4034 section 3.1).
+    pub fn as_rrsig(&self) -> Option<Rrsig> {
+        let fixed_fields = self.0.get(..18)?;
+        let u16_at = |idx: usize| u16::from_be_bytes([fixed_fields[idx], fixed_fields[idx + 1]]);
+        let u32_at = |idx: usize| {
+            u32::from_be_bytes([
+                fixed_fields[idx],
+                fixed_fields[idx + 1],
+                fixed_fields[idx + 2],
+                fixed_fields[idx + 3],
+            ])
+        };
+        let type_covered = u16_at(0);
+        let signer_name = DomainName::new(&self.0[18..]).ok()?;
+        let signature = self.0[18 + signer_name.encoded_len()..].to_vec();
+
+        Some(Rrsig {
+            type_covered: RecordType::try_from(type_covered)
+                .unwrap_or(RecordType::Unknown(type_covered)),
+            algorithm: fixed_fields[2],
+            labels: fixed_fields[3],
+            original_ttl: u32_at(4),
+            signature_expiration: u32_at(8),
+            signature_inception: u32_at(12),
+            key_tag: u16_at(16),
+            signer_name,
+            signature,
+        })
+    }
+
+    /// Interprets the data of a SOA record: the uncompressed names of the primary server and of
+    /// the mailbox of the zone administrator, then five 32-bit fields (RFC 1035 section 3.3.13).
+    pub fn as_soa(&self) -> Option<Soa> {
+        let primary_server = DomainName::new(&self.0).ok()?;
+        let rest = &self.0[primary_server.encoded_len()..];
+        let administrator = DomainName::new(rest).ok()?;
+        let fields = rest.get(administrator.encoded_len()..)?;
+        if fields.len() != 20 {
+            return None;
+        }
+        let u32_at = |idx: usize| {
+            u32::from_be_bytes([
+                fields[idx],
+                fields[idx + 1],
+                fields[idx + 2],
+                fields[idx + 3],
+            ])
+        };
+
+        Some(Soa {
+            primary_server,
+            administrator,
+            serial: u32_at(0),
+            refresh: u32_at(4),
+            retry: u32_at(8),
+            expire: u32_at(12),
+            minimum: u32_at(16),
+        })
+    }
+
+    /// Encodes a SOA record's data, the reverse of [`RData::as_soa`].
+    pub fn from_soa(soa: &Soa) -> Self {
+        RData(
+            [
+                &soa.primary_server.wire_format[..],
+                &soa.administrator.wire_format,
+                &soa.serial.to_be_bytes(),
+                &soa.refresh.to_be_bytes(),
+                &soa.retry.to_be_bytes(),
+                &soa.expire.to_be_bytes(),
+                &soa.minimum.to_be_bytes(),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Interprets the data of a WKS record: an IPv4 address, an IP protocol number, then a bitmap
+    /// with a bit per port, the most significant bit of the first byte for port 0 (RFC 1035
+    /// section 3.4.2).
+    pub fn as_wks(&self) -> Option<Wks> {
+        let address = <[u8; 4]>::try_from(self.0.get(..4)?).ok()?;
+        let protocol = *self.0.get(4)?;
+        let ports = self.0[5..]
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (0b10000000 >> bit) != 0)
+                    .filter_map(move |bit| u16::try_from(idx * 8 + bit).ok())
+            })
+            .collect();
+
+        Some(Wks {
+            address: Ipv4Addr::from(address),
+            protocol,
+            ports,
+        })
+    }
+
+    /// Encodes a WKS record's data, the reverse of [`RData::as_wks`], with the bitmap ending at the
+    /// byte of the highest port.
+    pub fn from_wks(wks: &Wks) -> Self {
+        let bitmap_length = wks
+            .ports
+            .iter()
+            .max()
+            .map_or(0, |&port| usize::from(port) / 8 + 1);
+        let mut bitmap = vec![0; bitmap_length];
+        for &port in &wks.ports {
+            bitmap[usize::from(port) / 8] |= 0b10000000 >> (port % 8);
+        }
+
+        RData([&wks.address.octets()[..], &[wks.protocol], &bitmap].concat())
+    }
+
+    /// Reads data written as hexadecimal digits, two per byte.
+    pub fn from_hex(hex: &str) -> Result<Self, DnsError> {
+        let invalid = || DnsError::InvalidHex(hex.to_string());
+        if hex.len() % 2 != 0 {
+            return Err(invalid());
         }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|idx| {
+                hex.get(idx..idx + 2)
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(invalid)
+            })
+            .collect::<Result<Vec<u8>, DnsError>>()
+            .map(RData)
+    }
+
+    /// Renders the data as lowercase hexadecimal digits, two per byte.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Returns the raw data, as carried on the wire, e.g. for the record types that are not
+    /// decoded (RFC 3597).
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Serialize the resource data as a hex string, which is easier to read than an array of numbers
+#[cfg(feature = "serde")]
+impl serde::Serialize for RData {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
     }
 }
 
+/// Deserialize the resource data from a hex string
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RData {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = <String as serde::Deserialize>::deserialize(deserializer)?;
+        RData::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Certification Authority Authorization, the RDATA of a CAA record (RFC 8659 section 4.1).
+///
+/// For example `0 issue "letsencrypt.org"` allows Let's Encrypt to issue certificates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caa {
+    pub flags: u8,
+    pub tag: String,
+    pub value: String,
+}
+
+/// The RDATA of a WKS record (RFC 1035 section 3.4.2), the services a host offers over an IP
+/// protocol, e.g. 6 for TCP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wks {
+    pub address: Ipv4Addr,
+    pub protocol: u8,
+    /// The ports with a service, in increasing order.
+    pub ports: Vec<u16>,
+}
+
+/// The RDATA of a DNSKEY record (RFC 4034 section 2.1), the public key a zone signs its records
+/// with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+/// The RDATA of a RRSIG record (RFC 4034 section 3.1), the signature of the records of a type.
+///
+/// Times are in seconds since the Unix epoch, modulo 2^32.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rrsig {
+    pub type_covered: RecordType,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub signature_expiration: u32,
+    pub signature_inception: u32,
+    pub key_tag: u16,
+    pub signer_name: DomainName,
+    pub signature: Vec<u8>,
+}
+
+/// The RDATA of a SOA record (RFC 1035 section 3.3.13), which starts a zone.
+///
+/// `minimum` is the TTL of the negative answers about the zone (RFC 2308 section 4).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Soa {
+    pub primary_server: DomainName,
+    pub administrator: DomainName,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
 /// Represents a single DNS answer record (Resource Record) in a DNS packet.
 ///
 /// A DNS answer record provides information in response to a DNS query. It includes:
@@ -43,6 +417,7 @@ impl RData {
 /// - `r_data_length`: The length of the resource data (RDATA) field in bytes.
 /// - `r_data`: The resource data of the answer, which contains the content specific to the record type.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsAnswerRecord {
     pub domain_name: DomainName,
     pub record_type: RecordType,
@@ -53,13 +428,74 @@ pub struct DnsAnswerRecord {
 }
 
 impl DnsAnswerRecord {
+    /// Builds the A record of `name` holding `address`.
+    pub fn new_a(name: &str, address: Ipv4Addr, time_to_live: u32) -> Result<Self, DnsError> {
+        Self::from_parts(name, RecordType::A, time_to_live, address.octets().to_vec())
+    }
+
+    /// Builds the AAAA record of `name` holding `address`.
+    pub fn new_aaaa(name: &str, address: Ipv6Addr, time_to_live: u32) -> Result<Self, DnsError> {
+        Self::from_parts(
+            name,
+            RecordType::AAAA,
+            time_to_live,
+            address.octets().to_vec(),
+        )
+    }
+
+    /// Builds the CNAME record making `name` an alias of `target`.
+    pub fn new_cname(name: &str, target: &str, time_to_live: u32) -> Result<Self, DnsError> {
+        let target: DomainName = target
+            .parse()
+            .map_err(|_| DnsError::InvalidDomainName(target.to_string()))?;
+
+        Self::from_parts(name, RecordType::CNAME, time_to_live, target.wire_format)
+    }
+
+    /// Builds the TXT record of `name` holding `text`, split into as many character strings as
+    /// needed.
+    pub fn new_txt(name: &str, text: &str, time_to_live: u32) -> Result<Self, DnsError> {
+        let r_data: Vec<u8> = if text.is_empty() {
+            vec![0]
+        } else {
+            text.as_bytes()
+                .chunks(MAX_CHARACTER_STRING_LENGTH)
+                .flat_map(|chunk| [&[chunk.len() as u8][..], chunk].concat())
+                .collect()
+        };
+
+        Self::from_parts(name, RecordType::TXT, time_to_live, r_data)
+    }
+
+    /// Builds an IN record of `name`, given in its textual form.
+    fn from_parts(
+        name: &str,
+        record_type: RecordType,
+        time_to_live: u32,
+        r_data: Vec<u8>,
+    ) -> Result<Self, DnsError> {
+        let domain_name = name
+            .parse()
+            .map_err(|_| DnsError::InvalidDomainName(name.to_string()))?;
+
+        Ok(DnsAnswerRecord {
+            domain_name,
+            record_type,
+            class: Class::IN,
+            time_to_live,
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        })
+    }
+
     fn get_ttl_from_packet(packet_slice: &[u8], domain_name_len: usize) -> Result<u32, ()> {
         let ttl_start_index = domain_name_len + 4;
         let ttl_end_index = ttl_start_index + 4;
-        match packet_slice.get(ttl_start_index..ttl_end_index) {
-            None => Err(()),
-            Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
-        }
+        packet_slice
+            .get(ttl_start_index..ttl_end_index)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_be_bytes)
+            .ok_or(())
     }
 
     fn get_r_data_from_packet(packet_slice: &[u8], domain_name_len: usize) -> Result<RData, ()> {
@@ -70,87 +506,394 @@ impl DnsAnswerRecord {
         }
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet_slice: &[u8]) -> Result<Self, ()> {
-        let domain_name = DomainName::new(packet_slice)?;
-        let domain_name_len = domain_name.wire_format.len();
-        let record_type = RecordType::new(packet_slice, domain_name_len)?;
-        let class = Class::new(packet_slice, domain_name_len)?;
-        let time_to_live = Self::get_ttl_from_packet(packet_slice, domain_name_len)?;
-        let r_data = Self::get_r_data_from_packet(packet_slice, domain_name_len)?;
-        let r_data_length = r_data.0.len();
+        Self::parse_at(packet_slice, 0)
+            .map(|(answer, _)| answer)
+            .map_err(|_| ())
+    }
 
-        Ok(DnsAnswerRecord {
-            domain_name,
-            record_type,
-            class,
-            time_to_live,
-            r_data_length,
-            r_data,
+    /// Parses the record starting at `offset` in `packet`, whose owner name may be compressed,
+    /// returning it along with the number of bytes it takes.
+    ///
+    /// An RDLENGTH reaching past the end of the packet is reported as
+    /// [`DnsError::RDataLengthMismatch`], any other truncation as [`DnsError::MalformedPacket`].
+    fn parse_at(packet: &[u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let malformed = |_| DnsError::MalformedPacket;
+        let (domain_name, name_length) = DomainName::parse_at(packet, offset)?;
+        let record_slice = &packet[offset..];
+        let record_type = RecordType::new(record_slice, name_length).map_err(malformed)?;
+        let class = match record_type {
+            // OPT pseudo-records use the CLASS field for the requestor's UDP payload size
+            RecordType::OPT => Class::Unknown(Class::new_raw(record_slice, name_length)?),
+            _ => Class::new(record_slice, name_length).map_err(malformed)?,
+        };
+        let time_to_live =
+            Self::get_ttl_from_packet(record_slice, name_length).map_err(malformed)?;
+        let r_data_length = record_slice
+            .get(name_length + 8..name_length + 10)
+            .map(|bytes| usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+            .ok_or(DnsError::MalformedPacket)?;
+        if name_length + 10 + r_data_length > record_slice.len() {
+            return Err(DnsError::RDataLengthMismatch {
+                record_type,
+                length: r_data_length,
+            });
+        }
+        let r_data = Self::get_r_data_from_packet(record_slice, name_length).map_err(malformed)?;
+        let wire_length = name_length + 10 + r_data.0.len();
+
+        // The names of the RDATA may point back into the packet, where the record will no longer
+        // be once serialized again, so they are kept expanded
+        let r_data_start = offset + name_length + 10;
+        let r_data_end = r_data_start + r_data.0.len();
+        let r_data = split_embedded_names(record_type, &r_data.0, |name_offset| {
+            let name_slice = packet.get(r_data_start + name_offset..r_data_end)?;
+            DomainName::parse_in_packet(name_slice, packet).ok()
         })
+        .map(|(prefix, names, suffix)| {
+            let names = names.iter().map(|name| name.wire_format.as_slice());
+            RData(
+                [prefix]
+                    .into_iter()
+                    .chain(names)
+                    .chain([suffix])
+                    .collect::<Vec<_>>()
+                    .concat(),
+            )
+        })
+        .unwrap_or(r_data);
+
+        Ok((
+            DnsAnswerRecord {
+                domain_name,
+                record_type,
+                class,
+                time_to_live,
+                r_data_length: r_data.0.len(),
+                r_data,
+            },
+            wire_length,
+        ))
     }
 
-    fn parse_and_return_next_slice(packet_slice: &[u8]) -> Result<(Self, &[u8]), ()> {
-        let answer = Self::new(packet_slice)?;
-        let domain_name_len = answer.domain_name.wire_format.len();
-        let r_data_length = answer.r_data_length;
+    /// Returns a copy of the record whose TTL is reduced by the `elapsed` time it has been held,
+    /// e.g. in a cache, down to 0.
+    pub fn with_decremented_ttl(&self, elapsed: Duration) -> Self {
+        let elapsed = u32::try_from(elapsed.as_secs()).unwrap_or(u32::MAX);
 
-        packet_slice
-            .get(domain_name_len + r_data_length + 10..)
-            .ok_or(())
-            .map(|next_slice| (answer, next_slice))
+        DnsAnswerRecord {
+            time_to_live: self.time_to_live.saturating_sub(elapsed),
+            ..self.clone()
+        }
     }
 
+    /// Returns the resource data of the record.
+    pub fn rdata(&self) -> &RData {
+        &self.r_data
+    }
+
+    /// Whether the record can be part of the answer to `question`: its owner is the name of the
+    /// question, compared case-insensitively (RFC 4343), and it is of the requested type, of any
+    /// type for ANY questions, or a CNAME leading to the requested records.
+    pub fn answers_question(&self, question: &DnsQuestion) -> bool {
+        self.domain_name
+            .wire_format
+            .eq_ignore_ascii_case(&question.domain_name.wire_format)
+            && self.class == question.class
+            && (self.record_type == question.record_type
+                || self.record_type == RecordType::CNAME
+                || question.record_type == RecordType::ANY)
+    }
+
+    /// Checks that the RDATA length makes sense for the record type: 4 bytes for A, 16 for AAAA,
+    /// and a non-empty domain name for CNAME, NS and PTR. Other types are accepted as is.
+    ///
+    /// Records of class ANY or NONE are accepted as is too: dynamic updates use them with an empty
+    /// RDATA to delete RRsets or state prerequisites (RFC 2136 section 2.4).
+    pub fn validate(&self) -> Result<(), DnsError> {
+        if matches!(self.class, Class::ANY | Class::NONE) {
+            return Ok(());
+        }
+
+        let valid = match self.record_type {
+            RecordType::A => self.r_data_length == 4,
+            RecordType::AAAA => self.r_data_length == 16,
+            RecordType::CNAME | RecordType::NS | RecordType::PTR => self.r_data_length > 0,
+            _ => true,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(DnsError::RDataLengthMismatch {
+                record_type: self.record_type,
+                length: self.r_data_length,
+            })
+        }
+    }
+
+    /// Parses `number_of_answers` consecutive records starting at `offset` in `packet`, returning
+    /// them along with the offset following the last one.
     pub fn parse_all_answers(
-        packet_slice: &[u8],
+        packet: &[u8],
+        offset: usize,
         number_of_answers: usize,
-    ) -> Result<(Vec<Self>, &[u8]), ()> {
-        let mut answers: Vec<Self> = Vec::new();
-        let mut current_slice = packet_slice;
+    ) -> Result<(Vec<Self>, usize), DnsError> {
+        let remaining_length = packet.len().saturating_sub(offset);
+        if number_of_answers.saturating_mul(MIN_RECORD_SIZE) > remaining_length {
+            return Err(DnsError::PacketTooShort);
+        }
+
+        let mut answers: Vec<Self> = Vec::with_capacity(number_of_answers);
+        let mut offset = offset;
 
         for _ in 0..number_of_answers {
-            match Self::parse_and_return_next_slice(current_slice) {
-                Err(_) => {
-                    return Err(());
-                }
-                Ok((question, next_slice)) => {
-                    answers.push(question);
-                    current_slice = next_slice;
-                }
-            }
+            let (answer, length) = Self::parse_at(packet, offset)?;
+            answers.push(answer);
+            offset += length;
         }
 
-        Ok((answers, current_slice))
+        Ok((answers, offset))
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let domain_name_bytes = self.domain_name.wire_format.clone();
-        let record_type_bytes = (self.record_type as u16).to_be_bytes().to_vec();
-        let class_bytes = (self.class as u16).to_be_bytes().to_vec();
-        let time_to_live_bytes = self.time_to_live.to_be_bytes().to_vec();
-        let r_data_length_bytes = (self.r_data_length as u16).to_be_bytes().to_vec();
-        let r_data_bytes = self.r_data.0.clone();
+        let mut bytes =
+            Vec::with_capacity(self.domain_name.encoded_len() + 10 + self.r_data.0.len());
+        self.write_into(&mut bytes);
+        bytes
+    }
+
+    /// Appends the record to `out`, its names uncompressed.
+    pub fn write_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.domain_name.wire_format);
+        self.write_fields_after_name(out);
+    }
+
+    /// Appends what follows the owner name to `message`, like [`Self::write_fields_after_name`],
+    /// with the names of the RDATA compressed by `compressor` when the record type allows it.
+    pub(crate) fn write_compressed_fields_after_name(
+        &self,
+        compressor: &mut NameCompressor,
+        message: &mut Vec<u8>,
+    ) {
+        let Some((prefix, names, suffix)) =
+            split_embedded_names(self.record_type, &self.r_data.0, |name_offset| {
+                let name = DomainName::new(self.r_data.0.get(name_offset..)?).ok()?;
+                let length = name.encoded_len();
+                Some((name, length))
+            })
+        else {
+            self.write_fields_after_name(message);
+            return;
+        };
+
+        message.extend(u16::from(self.record_type).to_be_bytes());
+        message.extend(u16::from(self.class).to_be_bytes());
+        message.extend(self.time_to_live.to_be_bytes());
+        let r_data_length_position = message.len();
+        message.extend([0, 0]);
+
+        message.extend_from_slice(prefix);
+        for name in &names {
+            compressor.write(name, message);
+        }
+        message.extend_from_slice(suffix);
+
+        let r_data_length = (message.len() - r_data_length_position - 2) as u16;
+        message[r_data_length_position..r_data_length_position + 2]
+            .copy_from_slice(&r_data_length.to_be_bytes());
+    }
+
+    /// Appends what follows the owner name to `out`: the type, class, TTL and RDATA.
+    fn write_fields_after_name(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&u16::from(self.record_type).to_be_bytes());
+        out.extend_from_slice(&u16::from(self.class).to_be_bytes());
+        out.extend_from_slice(&self.time_to_live.to_be_bytes());
+        out.extend_from_slice(&(self.r_data_length as u16).to_be_bytes());
+        out.extend_from_slice(&self.r_data.0);
+    }
+
+    /// Renders the record as a zone file line: owner name, TTL, class, type and RDATA.
+    ///
+    /// The RDATA of A, AAAA, MX and single-name record types is written in its usual presentation form;
+    /// anything else uses the generic `\# <length> <hex>` form of RFC 3597 section 5.
+    pub fn to_zone_line(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            self.domain_name.to_text(),
+            self.time_to_live,
+            self.class,
+            self.record_type,
+            self.r_data_text()
+        )
+    }
+
+    /// Renders the record as a line of an answer section of `dig`: the same columns as
+    /// [`Self::to_zone_line`], separated by tabs, with TXT and HINFO strings written out quoted.
+    pub fn to_dig_line(&self) -> String {
+        let r_data_text = match self.record_type {
+            RecordType::TXT => self.r_data.as_txt().map(|strings| {
+                strings
+                    .iter()
+                    .map(|string| format!("{:?}", string))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            }),
+            RecordType::HINFO => self
+                .r_data
+                .as_hinfo()
+                .map(|(cpu, os)| format!("{:?} {:?}", cpu, os)),
+            _ => None,
+        };
+
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.domain_name.to_text(),
+            self.time_to_live,
+            self.class,
+            self.record_type,
+            r_data_text.unwrap_or_else(|| self.r_data_text())
+        )
+    }
+
+    fn r_data_text(&self) -> String {
+        let presentation = match self.record_type {
+            RecordType::A => self.r_data.as_a().map(|address| address.to_string()),
+            RecordType::AAAA => self.r_data.as_aaaa().map(|address| address.to_string()),
+            // These records hold a single domain name, laid out like the one of an NS record
+            RecordType::NS
+            | RecordType::MD
+            | RecordType::MF
+            | RecordType::CNAME
+            | RecordType::MB
+            | RecordType::MG
+            | RecordType::MR
+            | RecordType::PTR => self.r_data.as_ns().map(|name| name.to_text()),
+            RecordType::MX => self.r_data.0.get(..2).and_then(|preference| {
+                RData(self.r_data.0[2..].to_vec()).as_ns().map(|exchange| {
+                    let preference = u16::from_be_bytes([preference[0], preference[1]]);
+                    format!("{} {}", preference, exchange.to_text())
+                })
+            }),
+            _ => None,
+        };
+
+        presentation.unwrap_or_else(|| {
+            format!("\\# {} {}", self.r_data.0.len(), self.r_data.to_hex())
+                .trim_end()
+                .to_string()
+        })
+    }
+
+    /// Serializes the record in the canonical form of RFC 4034 section 6.2, as used when signing
+    /// or hashing records.
+    ///
+    /// The owner name and the domain names embedded in the RDATA of the RFC 1035 record types are
+    /// lowercased, and names are always written uncompressed.
+    pub fn canonical_wire(&self) -> Vec<u8> {
+        let r_data_bytes = self.canonical_r_data();
 
         [
-            domain_name_bytes,
-            record_type_bytes,
-            class_bytes,
-            time_to_live_bytes,
-            r_data_length_bytes,
+            self.domain_name.wire_format.to_ascii_lowercase(),
+            u16::from(self.record_type).to_be_bytes().to_vec(),
+            u16::from(self.class).to_be_bytes().to_vec(),
+            self.time_to_live.to_be_bytes().to_vec(),
+            (r_data_bytes.len() as u16).to_be_bytes().to_vec(),
             r_data_bytes,
         ]
         .concat()
     }
+
+    fn canonical_r_data(&self) -> Vec<u8> {
+        let Some((names_offset, names_count)) = embedded_names(self.record_type) else {
+            return self.r_data.0.clone();
+        };
+
+        let mut canonical = self.r_data.0.clone();
+        let mut offset = names_offset;
+
+        for _ in 0..names_count {
+            match self
+                .r_data
+                .0
+                .get(offset..)
+                .and_then(|name_bytes| DomainName::new(name_bytes).ok())
+            {
+                Some(domain_name) => {
+                    let name_end = offset + domain_name.encoded_len();
+                    canonical[offset..name_end].make_ascii_lowercase();
+                    offset = name_end;
+                }
+                None => break,
+            }
+        }
+
+        canonical
+    }
+}
+
+/// Where the domain names sit in the RDATA of the RFC 1035 record types, the only ones whose
+/// names may be compressed (RFC 3597 section 4): the offset of the first name within the RDATA,
+/// and the number of consecutive names.
+fn embedded_names(record_type: RecordType) -> Option<(usize, usize)> {
+    match record_type {
+        RecordType::NS
+        | RecordType::MD
+        | RecordType::MF
+        | RecordType::CNAME
+        | RecordType::MB
+        | RecordType::MG
+        | RecordType::MR
+        | RecordType::PTR => Some((0, 1)),
+        RecordType::SOA | RecordType::MINFO => Some((0, 2)),
+        RecordType::MX => Some((2, 1)),
+        _ => None,
+    }
+}
+
+/// Splits `r_data` into the bytes before its embedded names, the names, and the bytes after them,
+/// the names being parsed with `parse_name`, which returns a name along with the number of bytes it
+/// takes.
+///
+/// Returns `None` for the types without names and for RDATA whose names do not parse.
+fn split_embedded_names<F>(
+    record_type: RecordType,
+    r_data: &[u8],
+    mut parse_name: F,
+) -> Option<(&[u8], Vec<DomainName>, &[u8])>
+where
+    F: FnMut(usize) -> Option<(DomainName, usize)>,
+{
+    let (names_offset, names_count) = embedded_names(record_type)?;
+    let prefix = r_data.get(..names_offset)?;
+    let mut names: Vec<DomainName> = Vec::new();
+    let mut offset = names_offset;
+
+    for _ in 0..names_count {
+        let (name, length) = parse_name(offset)?;
+        names.push(name);
+        offset += length;
+    }
+
+    Some((prefix, names, r_data.get(offset..)?))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use super::*;
 
     #[test]
     fn test_r_data_new() {
-        // If packet slice has 2 elements or less, the parsing should fail
+        // If packet slice is too short to hold the r_data_length, the parsing should fail
+        assert_eq!(RData::new(&[0x08]), Err(()));
         assert_eq!(RData::new(&[0x08, 0x08]), Err(()));
 
+        // Empty data, as in OPT records without options, may end the packet
+        assert_eq!(RData::new(&[0x00, 0x00]), Ok(RData(Vec::new())));
+
         // If the packet slice has fewer elements than what the r_data_length portion says,
         // then the parsing should also fail
         assert_eq!(RData::new(&[0x00, 0x02, 0x08]), Err(()));
@@ -162,6 +905,225 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_r_data_hex() {
+        assert_eq!(RData::from_hex("0a00ff"), Ok(RData(vec![0x0a, 0x00, 0xff])));
+        assert_eq!(RData::from_hex("0a00ff").unwrap().to_hex(), "0a00ff");
+        assert_eq!(
+            RData::from_hex("0a0"),
+            Err(DnsError::InvalidHex("0a0".to_string()))
+        );
+        assert_eq!(
+            RData::from_hex("zz"),
+            Err(DnsError::InvalidHex("zz".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_r_data_typed_accessors() {
+        assert_eq!(
+            RData(vec![192, 168, 1, 1]).as_a(),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(RData(vec![192, 168, 1]).as_a(), None);
+        assert_eq!(
+            RData([&[0x20, 0x01, 0x0d, 0xb8][..], &[0; 11], &[1]].concat()).as_aaaa(),
+            Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+        assert_eq!(RData(vec![192, 168, 1, 1]).as_aaaa(), None);
+
+        let ns_bytes = [0x02, b'n', b's', 0x03, b'c', b'o', b'm', 0x00];
+        assert_eq!(
+            RData(ns_bytes.to_vec()).as_ns(),
+            DomainName::new(&ns_bytes).ok()
+        );
+        // Extra bytes after the name mean the data is not a single domain name
+        assert_eq!(RData([&ns_bytes[..], &[0x00]].concat()).as_ns(), None);
+
+        // A PTR name may point back into the packet
+        let packet = [&[0xab, 0xcd][..], &ns_bytes[..]].concat();
+        assert_eq!(
+            RData(vec![0xc0, 0x02]).as_ptr(&packet),
+            DomainName::new(&ns_bytes).ok()
+        );
+        assert_eq!(
+            RData(ns_bytes.to_vec()).as_ptr(&packet),
+            DomainName::new(&ns_bytes).ok()
+        );
+        assert_eq!(RData(vec![0xc0, 0x20]).as_ptr(&packet), None);
+
+        assert_eq!(
+            RData(vec![0x02, b'h', b'i', 0x00]).as_txt(),
+            Some(vec!["hi".to_string(), String::new()])
+        );
+        // The length octet announces more bytes than there are left
+        assert_eq!(RData(vec![0x03, b'h', b'i']).as_txt(), None);
+    }
+
+    #[test]
+    fn test_r_data_caa() {
+        let lets_encrypt = Caa {
+            flags: 0,
+            tag: "issue".to_string(),
+            value: "letsencrypt.org".to_string(),
+        };
+        let r_data = RData::from_caa(&lets_encrypt).unwrap();
+
+        assert_eq!(
+            r_data,
+            RData([&[0x00, 0x05][..], b"issue", b"letsencrypt.org"].concat())
+        );
+        assert_eq!(r_data.as_caa(), Some(lets_encrypt.clone()));
+
+        // The tag length exceeds the data
+        assert_eq!(RData([&[0x00, 0x09][..], b"issue"].concat()).as_caa(), None);
+        // Tags cannot be empty
+        assert_eq!(RData(vec![0x80, 0x00]).as_caa(), None);
+        assert_eq!(RData(vec![0x80]).as_caa(), None);
+
+        // Tags are 1 to 15 letters and digits, so their length always fits its octet
+        for tag in [
+            "x".repeat(256),
+            "x".repeat(16),
+            String::new(),
+            "is-sue".to_string(),
+        ] {
+            let caa = Caa {
+                tag: tag.clone(),
+                ..lets_encrypt.clone()
+            };
+            assert_eq!(RData::from_caa(&caa), Err(DnsError::InvalidCaaTag(tag)));
+        }
+    }
+
+    #[test]
+    fn test_r_data_hinfo() {
+        let r_data = RData::from_hinfo("AMD64", "LINUX").unwrap();
+
+        assert_eq!(
+            r_data,
+            RData([&[0x05][..], b"AMD64", &[0x05], b"LINUX"].concat())
+        );
+        assert_eq!(
+            r_data.as_hinfo(),
+            Some(("AMD64".to_string(), "LINUX".to_string()))
+        );
+
+        // The second string is cut short
+        assert_eq!(
+            RData([&[0x05][..], b"AMD64", &[0x05], b"LIN"].concat()).as_hinfo(),
+            None
+        );
+        // There must be exactly two strings
+        assert_eq!(RData([&[0x05][..], b"AMD64"].concat()).as_hinfo(), None);
+        assert_eq!(
+            RData::from_hinfo(&"x".repeat(256), "LINUX"),
+            Err(DnsError::CharacterStringTooLong(256))
+        );
+    }
+
+    #[test]
+    fn test_r_data_soa() {
+        let soa = Soa {
+            primary_server: "ns.example.com".parse().unwrap(),
+            administrator: "admin.example.com".parse().unwrap(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        };
+        let r_data = RData::from_soa(&soa);
+
+        assert_eq!(r_data.0.len(), 16 + 19 + 20);
+        assert_eq!(r_data.as_soa(), Some(soa));
+        // One of the five fields is missing
+        assert_eq!(
+            RData(r_data.0[..r_data.0.len() - 4].to_vec()).as_soa(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_r_data_wks() {
+        // SMTP and HTTP over TCP
+        let wks = Wks {
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            protocol: 6,
+            ports: vec![25, 80],
+        };
+        let r_data = RData::from_wks(&wks);
+
+        let mut bitmap = vec![0; 11];
+        bitmap[3] = 0b01000000;
+        bitmap[10] = 0b10000000;
+        assert_eq!(r_data, RData([&[192, 0, 2, 1, 6][..], &bitmap].concat()));
+        assert_eq!(r_data.as_wks(), Some(wks));
+        assert_eq!(RData(vec![192, 0, 2, 1]).as_wks(), None);
+    }
+
+    #[test]
+    fn test_r_data_mail_names() {
+        let mailbox: DomainName = "madname.example.com".parse().unwrap();
+        let mb = RData(mailbox.wire_format.clone());
+        assert_eq!(mb.as_single_name(), Some(mailbox.clone()));
+        assert_eq!(
+            RData([&mailbox.wire_format[..], &[0]].concat()).as_single_name(),
+            None
+        );
+
+        let errors: DomainName = "errors.example.com".parse().unwrap();
+        let minfo = RData([&mailbox.wire_format[..], &errors.wire_format].concat());
+        assert_eq!(minfo.as_minfo(), Some((mailbox.clone(), errors)));
+        // The error mailbox is missing
+        assert_eq!(RData(mailbox.wire_format).as_minfo(), None);
+    }
+
+    #[test]
+    fn test_r_data_dnssec() {
+        // A zone-signing key (flag 256) for algorithm 13, ECDSA P-256 with SHA-256
+        let dnskey = RData([&[0x01, 0x00, 0x03, 0x0d][..], &[0xab; 64]].concat());
+        assert_eq!(
+            dnskey.as_dnskey(),
+            Some(Dnskey {
+                flags: 256,
+                protocol: 3,
+                algorithm: 13,
+                public_key: vec![0xab; 64],
+            })
+        );
+        assert_eq!(RData(vec![0x01, 0x00, 0x03]).as_dnskey(), None);
+
+        let rrsig = RData(
+            [
+                &[0x00, 0x01][..],         // type covered = A
+                &[0x0d, 0x02],             // algorithm 13, 2 labels
+                &[0x00, 0x00, 0x0e, 0x10], // original TTL = 3600
+                &[0x65, 0x00, 0x00, 0x00], // signature expiration
+                &[0x64, 0x00, 0x00, 0x00], // signature inception
+                &[0x30, 0x39],             // key tag = 12345
+                &[
+                    0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+                ],
+                &[0xcd; 64], // signature
+            ]
+            .concat(),
+        );
+        let rrsig = rrsig.as_rrsig().unwrap();
+        assert_eq!(rrsig.type_covered, RecordType::A);
+        assert_eq!(rrsig.labels, 2);
+        assert_eq!(rrsig.original_ttl, 3600);
+        assert_eq!(rrsig.key_tag, 12345);
+        assert_eq!(rrsig.signer_name.to_text(), "example.com.");
+        assert_eq!(rrsig.signature, vec![0xcd; 64]);
+
+        // The signer name runs past the end of the data
+        assert_eq!(
+            RData([&[0; 18][..], &[0x07, b'e']].concat()).as_rrsig(),
+            None
+        );
+    }
+
     #[test]
     fn test_dns_answer_record_new() {
         // Helper to create a full valid answer packet:
@@ -212,19 +1174,25 @@ mod tests {
         let too_short = domain_bytes.to_vec();
         assert_eq!(DnsAnswerRecord::new(&too_short), Err(()));
 
-        // Error: bad record type
-        let mut bad_type = full_packet.clone();
+        // Record types that are not defined in RecordType are kept as is
+        let mut unknown_type = full_packet.clone();
         let dom_len = domain_bytes.len();
-        bad_type[dom_len] = 0xFF; // Not defined in RecordType
-        bad_type[dom_len + 1] = 0xFF;
-        assert_eq!(DnsAnswerRecord::new(&bad_type), Err(()));
+        unknown_type[dom_len] = 0xFF;
+        unknown_type[dom_len + 1] = 0xFF;
+        assert_eq!(
+            DnsAnswerRecord::new(&unknown_type).map(|record| record.record_type),
+            Ok(RecordType::Unknown(0xFFFF))
+        );
 
-        // Error: bad class
-        let mut bad_class = full_packet.clone();
+        // Classes that are not defined are kept as is
+        let mut unknown_class = full_packet.clone();
         let class_offset = domain_bytes.len() + 2;
-        bad_class[class_offset] = 0xFF;
-        bad_class[class_offset + 1] = 0xFF; // Not defined
-        assert_eq!(DnsAnswerRecord::new(&bad_class), Err(()));
+        unknown_class[class_offset] = 0xFF;
+        unknown_class[class_offset + 1] = 0xFF;
+        assert_eq!(
+            DnsAnswerRecord::new(&unknown_class).map(|record| record.class),
+            Ok(Class::Unknown(0xFFFF))
+        );
 
         // Error: not enough bytes for TTL
         let mut bad_ttl = full_packet.clone();
@@ -295,7 +1263,7 @@ mod tests {
         ];
 
         assert_eq!(
-            DnsAnswerRecord::parse_all_answers(&answers, 2),
+            DnsAnswerRecord::parse_all_answers(&answers, 0, 2),
             Ok((
                 vec![
                     DnsAnswerRecord {
@@ -330,12 +1298,270 @@ mod tests {
                         r_data: RData(vec![8, 8, 8, 8]),
                     }
                 ],
-                &answers[answers.len() - 4..]
+                answers.len() - 4
             ))
         );
 
         // Truncated packet should fail
         let truncated = &answers[..answers.len() - 10];
-        assert_eq!(DnsAnswerRecord::parse_all_answers(truncated, 2), Err(()));
+        assert_eq!(
+            DnsAnswerRecord::parse_all_answers(truncated, 0, 2),
+            Err(DnsError::MalformedPacket)
+        );
+
+        // An RDLENGTH reaching past the end is reported as such
+        let oversized = &answers[..answers.len() - 5];
+        assert_eq!(
+            DnsAnswerRecord::parse_all_answers(oversized, 0, 2),
+            Err(DnsError::RDataLengthMismatch {
+                record_type: RecordType::A,
+                length: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_write_into() {
+        let record = DnsAnswerRecord::new_cname("www.example.com", "example.com", 300).unwrap();
+        let mut out = vec![0xff];
+        record.write_into(&mut out);
+        assert_eq!(out[0], 0xff);
+        assert_eq!(out[1..], record.to_bytes());
+        assert_eq!(DnsAnswerRecord::new(&out[1..]), Ok(record));
+    }
+
+    #[test]
+    fn test_dns_answer_record_rdata_bytes() {
+        let a = DnsAnswerRecord::new_a("example.com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+        assert_eq!(a.rdata().as_bytes(), [1, 2, 3, 4]);
+
+        // Types from the private use range are only known by their bytes
+        let private =
+            DnsAnswerRecord::from_parts("example.com", RecordType::Unknown(65280), 60, vec![7, 8])
+                .unwrap();
+        assert_eq!(private.rdata().as_bytes(), [7, 8]);
+    }
+
+    #[test]
+    fn test_dns_answer_record_constructors() {
+        assert_eq!(
+            DnsAnswerRecord::new_a("example.com", Ipv4Addr::new(1, 2, 3, 4), 60)
+                .map(|record| record.to_bytes()),
+            Ok(vec![
+                0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', // "example"
+                0x03, b'c', b'o', b'm', // "com"
+                0x00, // end of name
+                0x00, 0x01, // RecordType::A
+                0x00, 0x01, // Class::IN
+                0x00, 0x00, 0x00, 0x3c, // TTL = 60
+                0x00, 0x04, // RData length = 4
+                1, 2, 3, 4, // RData (IPv4)
+            ])
+        );
+        assert_eq!(
+            DnsAnswerRecord::new_aaaa("example.com", Ipv6Addr::LOCALHOST, 60)
+                .map(|record| record.r_data.as_aaaa()),
+            Ok(Some(Ipv6Addr::LOCALHOST))
+        );
+        assert_eq!(
+            DnsAnswerRecord::new_cname("www.example.com", "example.com", 60)
+                .map(|record| record.r_data.as_cname()),
+            Ok("example.com".parse().ok())
+        );
+
+        // Long texts are split into character strings of up to 255 bytes
+        let text = "a".repeat(300);
+        let txt = DnsAnswerRecord::new_txt("example.com", &text, 60).unwrap();
+        assert_eq!(txt.r_data_length, 302);
+        assert_eq!(txt.r_data.0[0], 255);
+        assert_eq!(txt.r_data.0[256], 45);
+
+        assert_eq!(
+            DnsAnswerRecord::new_a("bad..name", Ipv4Addr::LOCALHOST, 60),
+            Err(DnsError::InvalidDomainName("bad..name".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_unknown_type() {
+        let packet = [
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+            0x41, // RecordType = 65 (HTTPS)
+            0x00, 0x01, // Class::IN
+            0x00, 0x00, 0x01, 0x2c, // TTL = 300
+            0x00, 0x03, // RData length = 3
+            0x00, 0x01, 0x00, // RData (priority 1, target name ".")
+        ];
+
+        let record = DnsAnswerRecord::new(&packet).unwrap();
+        assert_eq!(record.record_type, RecordType::Unknown(65));
+        assert_eq!(record.r_data, RData(vec![0x00, 0x01, 0x00]));
+        assert_eq!(record.to_bytes(), packet);
+        assert_eq!(
+            record.to_zone_line(),
+            "example.com. 300 IN TYPE65 \\# 3 000100"
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_with_decremented_ttl() {
+        let record = DnsAnswerRecord::new_a("example.com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+
+        let decremented = record.with_decremented_ttl(Duration::from_secs(10));
+        assert_eq!(decremented.time_to_live, 50);
+        assert_eq!(decremented.r_data, record.r_data);
+        assert_eq!(
+            record
+                .with_decremented_ttl(Duration::from_secs(100))
+                .time_to_live,
+            0
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_answers_question() {
+        let question =
+            DnsQuestion::from_parts("WWW.example.com", RecordType::A, Class::IN).unwrap();
+
+        let direct =
+            DnsAnswerRecord::new_a("www.EXAMPLE.com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+        assert!(direct.answers_question(&question));
+
+        let alias = DnsAnswerRecord::new_cname("www.example.com", "web.example.com", 60).unwrap();
+        assert!(alias.answers_question(&question));
+
+        let other_name =
+            DnsAnswerRecord::new_a("mail.example.com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+        assert!(!other_name.answers_question(&question));
+        let other_type =
+            DnsAnswerRecord::new_aaaa("www.example.com", Ipv6Addr::LOCALHOST, 60).unwrap();
+        assert!(!other_type.answers_question(&question));
+    }
+
+    #[test]
+    fn test_dns_answer_record_validate() {
+        let a = DnsAnswerRecord::new_a("example.com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+        assert_eq!(a.validate(), Ok(()));
+
+        let mut packet = a.to_bytes();
+        packet[22] = 16; // RData length = 16
+        packet.extend([0; 12]);
+        let oversized_a = DnsAnswerRecord::new(&packet).unwrap();
+        assert_eq!(
+            oversized_a.validate(),
+            Err(DnsError::RDataLengthMismatch {
+                record_type: RecordType::A,
+                length: 16
+            })
+        );
+
+        // An empty A record deleting an RRset in a dynamic update
+        let delete_rrset = DnsAnswerRecord {
+            class: Class::ANY,
+            time_to_live: 0,
+            r_data_length: 0,
+            r_data: RData(Vec::new()),
+            ..a.clone()
+        };
+        assert_eq!(delete_rrset.validate(), Ok(()));
+        let rrset_missing = DnsAnswerRecord {
+            class: Class::NONE,
+            ..delete_rrset
+        };
+        assert_eq!(rrset_missing.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_dns_answer_record_to_zone_line() {
+        let record = |record_type: RecordType, r_data: Vec<u8>| DnsAnswerRecord {
+            domain_name: "www.example.com".parse().unwrap(),
+            record_type,
+            class: Class::IN,
+            time_to_live: 300,
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        };
+        let mail_example_com = DomainName::from_str("mail.example.com")
+            .unwrap()
+            .wire_format;
+
+        assert_eq!(
+            record(RecordType::A, vec![1, 2, 3, 4]).to_zone_line(),
+            "www.example.com. 300 IN A 1.2.3.4"
+        );
+        assert_eq!(
+            record(RecordType::CNAME, mail_example_com.clone()).to_zone_line(),
+            "www.example.com. 300 IN CNAME mail.example.com."
+        );
+        assert_eq!(
+            record(
+                RecordType::MX,
+                [&[0x00, 0x0a][..], &mail_example_com].concat()
+            )
+            .to_zone_line(),
+            "www.example.com. 300 IN MX 10 mail.example.com."
+        );
+        assert_eq!(
+            record(RecordType::TXT, vec![0x02, b'h', b'i']).to_zone_line(),
+            "www.example.com. 300 IN TXT \\# 3 026869"
+        );
+        assert_eq!(
+            record(RecordType::NULL, Vec::new()).to_zone_line(),
+            "www.example.com. 300 IN NULL \\# 0"
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_to_dig_line() {
+        assert_eq!(
+            DnsAnswerRecord::new_a("example.com", Ipv4Addr::new(1, 2, 3, 4), 60)
+                .unwrap()
+                .to_dig_line(),
+            "example.com.\t60\tIN\tA\t1.2.3.4"
+        );
+        assert_eq!(
+            DnsAnswerRecord::new_txt("example.com", "v=spf1 -all", 300)
+                .unwrap()
+                .to_dig_line(),
+            "example.com.\t300\tIN\tTXT\t\"v=spf1 -all\""
+        );
+    }
+
+    #[test]
+    fn test_dns_answer_record_canonical_wire() {
+        let lowercase_name = [0x03, b'w', b'w', b'w', 0x03, b'c', b'o', b'm', 0x00];
+        let mixed_case_name = [0x03, b'W', b'w', b'W', 0x03, b'C', b'o', b'M', 0x00];
+
+        let record = |name: &[u8], record_type: RecordType, r_data: Vec<u8>| DnsAnswerRecord {
+            domain_name: DomainName::new(name).unwrap(),
+            record_type,
+            class: Class::IN,
+            time_to_live: 60,
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        };
+
+        // Records differing only in the case of their owner name share the same canonical form
+        let lowercase_a = record(&lowercase_name, RecordType::A, vec![1, 2, 3, 4]);
+        let mixed_case_a = record(&mixed_case_name, RecordType::A, vec![1, 2, 3, 4]);
+        assert_ne!(lowercase_a.to_bytes(), mixed_case_a.to_bytes());
+        assert_eq!(lowercase_a.canonical_wire(), mixed_case_a.canonical_wire());
+        assert_eq!(lowercase_a.canonical_wire(), lowercase_a.to_bytes());
+
+        // Names within the RDATA are lowercased too, leaving other fields untouched
+        let mixed_case_mx = record(
+            &mixed_case_name,
+            RecordType::MX,
+            [&[0x00, b'A'][..], &mixed_case_name].concat(),
+        );
+        assert_eq!(
+            mixed_case_mx.canonical_wire(),
+            record(
+                &lowercase_name,
+                RecordType::MX,
+                [&[0x00, b'A'][..], &lowercase_name].concat()
+            )
+            .to_bytes()
+        );
     }
 }
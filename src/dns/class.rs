@@ -1,25 +1,45 @@
+use crate::dns::DnsError;
+
 /// DNS Class types as defined in RFC 1035 section 3.2.4.
 ///
 /// This enum represents the CLASS field in a DNS question or resource record,
 /// indicating the protocol family (such as Internet, Chaos, etc.) being used.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// `NONE` (254) and `ANY` (255) only appear in questions and in dynamic updates (RFC 2136
+/// section 1.1). `Unknown` keeps the raw value of the field when it does not name a class, or for
+/// EDNS0 OPT pseudo-records that reuse it to carry the requestor's UDP payload size.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Class {
-    IN = 1,
-    CS = 2,
-    CH = 3,
-    HS = 4,
+    IN,
+    CS,
+    CH,
+    HS,
+    NONE,
+    ANY,
+    Unknown(u16),
 }
 
 impl Class {
+    /// Reads the CLASS field following the domain name, keeping values that do not name a class
+    /// as [`Class::Unknown`].
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet: &[u8], domain_name_len: usize) -> Result<Self, ()> {
+        Self::new_raw(packet, domain_name_len)
+            .map(|value| Class::try_from(value).unwrap_or(Class::Unknown(value)))
+            .map_err(|_| ())
+    }
+
+    /// Reads the CLASS field following the domain name without interpreting it.
+    pub fn new_raw(packet: &[u8], domain_name_len: usize) -> Result<u16, DnsError> {
         match (
             packet.get(domain_name_len + 2),
             packet.get(domain_name_len + 3),
         ) {
             (Some(first_byte), Some(second_byte)) => {
-                Class::try_from(u16::from_be_bytes([*first_byte, *second_byte]))
+                Ok(u16::from_be_bytes([*first_byte, *second_byte]))
             }
-            _ => Err(()),
+            _ => Err(DnsError::MalformedPacket),
         }
     }
 }
@@ -33,11 +53,88 @@ impl TryFrom<u16> for Class {
             2 => Ok(Class::CS),
             3 => Ok(Class::CH),
             4 => Ok(Class::HS),
+            254 => Ok(Class::NONE),
+            255 => Ok(Class::ANY),
             _ => Err(()),
         }
     }
 }
 
+impl From<Class> for u16 {
+    fn from(class: Class) -> Self {
+        match class {
+            Class::IN => 1,
+            Class::CS => 2,
+            Class::CH => 3,
+            Class::HS => 4,
+            Class::NONE => 254,
+            Class::ANY => 255,
+            Class::Unknown(value) => value,
+        }
+    }
+}
+
+/// Compare classes by their numeric value, so that `Unknown` of a known value equals that type
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        u16::from(*self) == u16::from(*other)
+    }
+}
+
+impl Eq for Class {}
+
+impl std::hash::Hash for Class {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        u16::from(*self).hash(state);
+    }
+}
+
+/// Order classes by their numeric value, e.g. to sort records deterministically
+impl PartialOrd for Class {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Class {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        u16::from(*self).cmp(&u16::from(*other))
+    }
+}
+
+/// Render a class by its mnemonic, or as `CLASS<n>` (RFC 3597 section 5) when it has none
+impl std::fmt::Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Class::Unknown(value) => write!(f, "CLASS{}", value),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Parse a class from its mnemonic or its `CLASS<n>` form, ignoring case
+impl std::str::FromStr for Class {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.to_ascii_uppercase();
+
+        match text.as_str() {
+            "IN" => Ok(Class::IN),
+            "CS" => Ok(Class::CS),
+            "CH" => Ok(Class::CH),
+            "HS" => Ok(Class::HS),
+            "NONE" => Ok(Class::NONE),
+            "ANY" => Ok(Class::ANY),
+            _ => text
+                .strip_prefix("CLASS")
+                .and_then(|value| value.parse::<u16>().ok())
+                .map(|value| Class::try_from(value).unwrap_or(Class::Unknown(value)))
+                .ok_or(()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,6 +149,35 @@ mod tests {
         assert_eq!(Class::try_from(0), Err(()));
         assert_eq!(Class::try_from(5), Err(()));
         assert_eq!(Class::try_from(123), Err(()));
+
+        assert_eq!(u16::from(Class::IN), 1);
+        assert_eq!(u16::from(Class::HS), 4);
+        assert_eq!(u16::from(Class::Unknown(4096)), 4096);
+    }
+
+    #[test]
+    fn test_class_order() {
+        assert!(Class::IN < Class::CH);
+        assert!(Class::HS < Class::Unknown(5));
+        assert!(Class::Unknown(5) < Class::NONE);
+
+        // An unknown class of a known value is that class
+        assert_eq!(Class::Unknown(1), Class::IN);
+        assert_eq!(Class::Unknown(1).cmp(&Class::IN), std::cmp::Ordering::Equal);
+        let classes: std::collections::HashSet<_> = [Class::IN, Class::Unknown(1)].into();
+        assert_eq!(classes.len(), 1);
+    }
+
+    #[test]
+    fn test_class_text() {
+        assert_eq!(Class::IN.to_string(), "IN");
+        assert_eq!(Class::ANY.to_string(), "ANY");
+        assert_eq!("none".parse(), Ok(Class::NONE));
+        assert_eq!(Class::Unknown(4096).to_string(), "CLASS4096");
+        assert_eq!("in".parse(), Ok(Class::IN));
+        assert_eq!("CLASS4096".parse(), Ok(Class::Unknown(4096)));
+        assert_eq!("CLASS1".parse(), Ok(Class::IN));
+        assert_eq!("INTERNET".parse::<Class>(), Err(()));
     }
 
     #[test]
@@ -73,5 +199,13 @@ mod tests {
         // domain_name_len is position after domain name (should be 17 for above)
         let domain_name_len = 16;
         assert_eq!(Class::new(packet, domain_name_len), Ok(Class::IN));
+
+        // The query classes are known, other undefined classes are kept as is
+        assert_eq!(Class::new(&[0x00, 0x01, 0x00, 0xff], 0), Ok(Class::ANY));
+        assert_eq!(Class::new(&[0x00, 0x01, 0x00, 0xfe], 0), Ok(Class::NONE));
+        assert_eq!(
+            Class::new(&[0x00, 0x01, 0x10, 0x00], 0),
+            Ok(Class::Unknown(4096))
+        );
     }
 }
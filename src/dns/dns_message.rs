@@ -1,38 +1,190 @@
+use std::{fmt::Write, io::Read, net::IpAddr};
+
 use crate::dns::{
-    answer_record::RData, Class, DnsAnswerRecord, DnsHeader, DnsQuestion, RecordType, ResponseCode,
+    answer_record::RData,
+    domain_name::NameCompressor,
+    header::random_id,
+    opt_record::{MAX_UDP_PAYLOAD_SIZE, MIN_UDP_PAYLOAD_SIZE},
+    Class, DnsAnswerRecord, DnsError, DnsHeader, DnsHeaderBuilder, DnsQuestion, DomainName, OpCode,
+    OptRecord, RecordType, ResponseCode,
 };
 
-/// Represents a complete DNS message consisting of a header, questions, and answer records.
+/// Represents a complete DNS message consisting of a header, questions, and resource records.
 ///
 /// This struct models the structure of a standard DNS message as defined in RFC 1035, comprising:
 /// - `header`: The DNS message header, which contains metadata such as ID, flags, and section counts.
 /// - `questions`: The list of DNS questions that the client is querying for.
 /// - `answers`: The list of answer records that respond to the queries.
+/// - `authorities`: The list of records pointing toward an authority (e.g. NS or SOA records).
+/// - `additionals`: The list of records holding additional information, including EDNS0 OPT records.
+/// - `trailing`: Any bytes found after the last record, kept so that re-serializing loses nothing.
 ///
 /// This struct is commonly used for parsing and constructing DNS packets in binary form.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsMessage {
-    header: DnsHeader,
-    questions: Vec<DnsQuestion>,
-    answers: Vec<DnsAnswerRecord>,
+    pub(crate) header: DnsHeader,
+    pub(crate) questions: Vec<DnsQuestion>,
+    pub(crate) answers: Vec<DnsAnswerRecord>,
+    pub(crate) authorities: Vec<DnsAnswerRecord>,
+    pub(crate) additionals: Vec<DnsAnswerRecord>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) trailing: Vec<u8>,
 }
 
 impl DnsMessage {
+    /// Returns a builder for a message, starting from an empty query.
+    pub fn builder() -> DnsMessageBuilder {
+        DnsMessageBuilder::new()
+    }
+
+    /// Parses a message received in a 512-byte buffer.
+    ///
+    /// The buffer is zero-padded past the end of the message, so the zero bytes ending it are not
+    /// kept as trailing bytes.
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet: &[u8; 512]) -> Result<Self, ()> {
-        let header = DnsHeader::new(packet)?;
-        let (questions, answers_slice) =
-            DnsQuestion::parse_all_questions(&packet[12..], header.question_count)?;
-        let (answers, _) =
-            DnsAnswerRecord::parse_all_answers(answers_slice, header.answer_record_count)?;
+        let mut message = Self::try_parse(packet).map_err(|_| ())?;
+        let padding = message.trailing.iter().rev().take_while(|&&byte| byte == 0);
+        message
+            .trailing
+            .truncate(message.trailing.len() - padding.count());
+
+        Ok(message)
+    }
+
+    /// Reads a message of `len` bytes from `reader` and parses it, e.g. after reading the length
+    /// prefix of a message received over TCP.
+    pub fn from_reader<R: Read>(reader: &mut R, len: usize) -> Result<Self, DnsError> {
+        let mut packet = vec![0; len];
+        reader.read_exact(&mut packet)?;
+
+        Self::try_parse(&packet).map_err(|_| DnsError::MalformedPacket)
+    }
+
+    /// Parses messages sent back to back, each prefixed by its length on two bytes, as over TCP
+    /// (RFC 1035 section 4.2.2).
+    ///
+    /// Fails when a message is cut short or does not parse, the whole of `buf` having to be made of
+    /// complete messages.
+    pub fn parse_stream(buf: &[u8]) -> Result<Vec<Self>, DnsError> {
+        let mut messages: Vec<Self> = Vec::new();
+        let mut rest = buf;
+
+        while !rest.is_empty() {
+            let length = match rest.get(..2) {
+                Some(&[first_byte, second_byte]) => {
+                    usize::from(u16::from_be_bytes([first_byte, second_byte]))
+                }
+                _ => return Err(DnsError::PacketTooShort),
+            };
+            let packet = rest.get(2..2 + length).ok_or(DnsError::PacketTooShort)?;
+
+            messages.push(Self::try_parse(packet)?);
+            rest = &rest[2 + length..];
+        }
+
+        Ok(messages)
+    }
+
+    /// Parses a message of any length, e.g. straight from untrusted network input.
+    ///
+    /// Every byte is read with bounds-checked accesses, so any input yields either a message or an
+    /// error, never a panic. Records whose RDATA does not fit their type are rejected, see
+    /// [`DnsAnswerRecord::validate`].
+    pub fn try_parse(packet: &[u8]) -> Result<Self, DnsError> {
+        let header = DnsHeader::new(packet).map_err(|_| DnsError::PacketTooShort)?;
+        let (questions, answers_offset) =
+            DnsQuestion::parse_all_questions(packet, 12, header.question_count)?;
+        let (answers, authorities_offset) =
+            DnsAnswerRecord::parse_all_answers(packet, answers_offset, header.answer_record_count)?;
+        let (authorities, additionals_offset) = DnsAnswerRecord::parse_all_answers(
+            packet,
+            authorities_offset,
+            header.authority_record_count,
+        )?;
+        let (additionals, trailing_offset) = DnsAnswerRecord::parse_all_answers(
+            packet,
+            additionals_offset,
+            header.additional_record_count,
+        )?;
+        answers
+            .iter()
+            .chain(&authorities)
+            .chain(&additionals)
+            .try_for_each(DnsAnswerRecord::validate)?;
+        let trailing = packet.get(trailing_offset..).unwrap_or_default();
 
         Ok(DnsMessage {
             header,
             questions,
             answers,
+            authorities,
+            additionals,
+            trailing: trailing.to_vec(),
         })
     }
 
+    /// Returns the EDNS0 OPT record from the additional section, if the message carries one.
+    pub fn opt(&self) -> Option<OptRecord> {
+        self.additionals
+            .iter()
+            .find_map(|record| OptRecord::try_from(record).ok())
+    }
+
+    /// Returns the largest UDP response the sender of this message is willing to accept.
+    ///
+    /// Without an OPT record this is the classic 512 bytes; with one it is the advertised size,
+    /// clamped between 512 bytes and the maximum this server supports.
+    pub fn max_udp_payload_size(&self) -> usize {
+        let advertised_size = self
+            .opt()
+            .map_or(MIN_UDP_PAYLOAD_SIZE, |opt| opt.udp_payload_size);
+
+        usize::from(advertised_size.clamp(MIN_UDP_PAYLOAD_SIZE, MAX_UDP_PAYLOAD_SIZE))
+    }
+
+    /// Builds a reply answering every question with the placeholder `8.8.8.8` A record.
+    ///
+    /// Questions for the root name get no answer (NODATA) instead, since the root has no address,
+    /// and a query without questions gets an empty reply.
     pub fn build_reply(&self) -> Self {
+        self.build_reply_with(|question| {
+            if question.domain_name.is_root() {
+                return Vec::new();
+            }
+
+            vec![DnsAnswerRecord {
+                domain_name: question.domain_name.clone(),
+                record_type: RecordType::A,
+                class: Class::IN,
+                time_to_live: 60,
+                r_data_length: 4,
+                r_data: RData(vec![8, 8, 8, 8]),
+            }]
+        })
+    }
+
+    /// Builds a reply to this query, using `resolve` to obtain the answers of each question.
+    ///
+    /// The answers of every question are concatenated in question order, and the section counts
+    /// of the reply header always match the sections it carries. When the query carries an OPT
+    /// record, so does the reply; see [`DnsMessage::answer_cookie`] for its cookie.
+    pub fn build_reply_with<F>(&self, resolve: F) -> Self
+    where
+        F: FnMut(&DnsQuestion) -> Vec<DnsAnswerRecord>,
+    {
+        let answers: Vec<DnsAnswerRecord> = self.questions.iter().flat_map(resolve).collect();
+        let additionals: Vec<DnsAnswerRecord> = self
+            .opt()
+            .map(|query_opt| {
+                let mut reply_opt = OptRecord::new(MAX_UDP_PAYLOAD_SIZE);
+                reply_opt.do_bit = query_opt.do_bit;
+                DnsAnswerRecord::from(&reply_opt)
+            })
+            .into_iter()
+            .collect();
+
         DnsMessage {
             header: DnsHeader {
                 packet_identifier: self.header.packet_identifier,
@@ -43,83 +195,652 @@ impl DnsMessage {
                 recursion_desired: self.header.recursion_desired,
                 recursion_available: false,
                 reserved: 0,
-                response_code: if self.header.operation_code == 0 {
-                    ResponseCode::NoError
-                } else {
-                    ResponseCode::NotImplemented
+                authentic_data: false,
+                // Echoed from the query (RFC 4035 section 3.1.6)
+                checking_disabled: self.header.checking_disabled,
+                response_code: match self.header.operation_code {
+                    OpCode::Query => ResponseCode::NoError,
+                    _ => ResponseCode::NotImplemented,
                 },
                 question_count: self.questions.len(),
-                answer_record_count: 1,
+                answer_record_count: answers.len(),
                 authority_record_count: 0,
-                additional_record_count: 0,
+                additional_record_count: additionals.len(),
             },
             questions: self.questions.clone(),
-            answers: vec![DnsAnswerRecord {
-                domain_name: self.questions[0].domain_name.clone(),
-                record_type: RecordType::A,
-                class: Class::IN,
-                time_to_live: 60,
-                r_data_length: 4,
-                r_data: RData(vec![8, 8, 8, 8]),
-            }],
+            answers,
+            authorities: Vec::new(),
+            additionals,
+            trailing: Vec::new(),
         }
     }
 
-    pub fn build_error_reply() -> Self {
+    /// Answers the DNS Cookie of `query`, sent from `client` (RFC 7873 section 5.2): the OPT
+    /// record of this reply echoes the client cookie along with a server cookie bound to that
+    /// address.
+    ///
+    /// Nothing changes when the query carries no cookie or this reply no OPT record.
+    pub fn answer_cookie(&mut self, query: &DnsMessage, client: IpAddr) {
+        let Some(cookie) = query.opt().and_then(|opt| opt.cookie()) else {
+            return;
+        };
+
+        for record in self.additionals.iter_mut() {
+            if let Ok(mut opt) = OptRecord::try_from(&*record) {
+                opt.set_cookie(&cookie.answer(client));
+                *record = DnsAnswerRecord::from(&opt);
+            }
+        }
+    }
+
+    /// Builds the acknowledgment of this NOTIFY message (RFC 1996 section 4.7): the same header
+    /// and question section, flagged as a reply with no error, and no records.
+    pub fn build_notify_acknowledgment(&self, authoritative_answer: bool) -> Self {
+        let builder = DnsMessage::builder().header(DnsHeader {
+            query_response_indicator: super::QRIndicator::Reply,
+            authoritative_answer,
+            response_code: ResponseCode::NoError,
+            ..self.header.clone()
+        });
+
+        self.questions
+            .iter()
+            .cloned()
+            .fold(builder, DnsMessageBuilder::add_question)
+            .build()
+    }
+
+    /// Builds a query for the records of type `record_type` of `name`, asking for recursion, with a
+    /// random packet identifier.
+    ///
+    /// Fails when `name` is not a valid domain name.
+    pub fn query(name: &str, record_type: RecordType) -> Result<Self, DnsError> {
+        let question = DnsQuestion::from_parts(name, record_type, Class::IN)?;
+
+        Ok(DnsMessage::builder()
+            .header(
+                DnsHeaderBuilder::new()
+                    .id(random_id())
+                    .recursion_desired(true)
+                    .build(),
+            )
+            .add_question(question)
+            .build())
+    }
+
+    /// Builds a query for the transfer of the whole of `zone` (RFC 5936), to be sent over TCP.
+    ///
+    /// Fails when `zone` is not a valid domain name.
+    pub fn axfr_query(zone: &str) -> Result<Self, DnsError> {
+        let question = DnsQuestion::from_parts(zone, RecordType::AXFR, Class::IN)?;
+
+        Ok(DnsMessage::builder()
+            .header(DnsHeaderBuilder::new().id(random_id()).build())
+            .add_question(question)
+            .build())
+    }
+
+    /// Builds a SERVFAIL reply to the query with the given identifier and RD flag, for when the
+    /// query could not be answered at all.
+    pub fn build_error_reply(packet_identifier: u16, recursion_desired: bool) -> Self {
         DnsMessage {
-            header: DnsHeader {
-                packet_identifier: 1234,
-                query_response_indicator: super::QRIndicator::Reply,
-                operation_code: 0,
-                authoritative_answer: false,
-                truncation: false,
-                recursion_desired: false,
-                recursion_available: false,
-                reserved: 0,
-                response_code: ResponseCode::ServerFailure,
-                question_count: 0,
-                answer_record_count: 0,
-                authority_record_count: 0,
-                additional_record_count: 0,
-            },
+            header: DnsHeaderBuilder::new()
+                .id(packet_identifier)
+                .response()
+                .recursion_desired(recursion_desired)
+                .response_code(ResponseCode::ServerFailure)
+                .build(),
             questions: Vec::new(),
             answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            trailing: Vec::new(),
         }
     }
 
-    pub fn to_bytes(&self) -> [u8; 512] {
-        let header_bytes = self.header.to_bytes().to_vec();
-        let questions_bytes: Vec<u8> = self
+    /// Builds a FORMERR reply to a packet that could not be parsed as a whole (RFC 1035 section
+    /// 4.1.1), echoing its identifier, opcode, RD flag and, when it could be parsed, its question
+    /// section.
+    ///
+    /// Fails when not even the header of the packet is there.
+    pub fn build_format_error_reply(packet: &[u8]) -> Result<Self, DnsError> {
+        let header = DnsHeader::new(packet).map_err(|_| DnsError::PacketTooShort)?;
+        let questions = DnsQuestion::parse_all_questions(packet, 12, header.question_count)
+            .map(|(questions, _)| questions)
+            .unwrap_or_default();
+
+        Ok(DnsMessage {
+            header: DnsHeaderBuilder::new()
+                .id(header.packet_identifier)
+                .response()
+                .operation_code(header.operation_code)
+                .recursion_desired(header.recursion_desired)
+                .response_code(ResponseCode::FormatError)
+                .questions(questions.len() as u16)
+                .build(),
+            questions,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            trailing: Vec::new(),
+        })
+    }
+
+    /// Replaces the answer section of the message with `answers`, e.g. to fill in a reply built
+    /// from the query.
+    pub fn with_answers(mut self, answers: Vec<DnsAnswerRecord>) -> Self {
+        self.header.answer_record_count = answers.len();
+        self.answers = answers;
+        self
+    }
+
+    /// Returns the header of the message.
+    pub fn header(&self) -> &DnsHeader {
+        &self.header
+    }
+
+    /// Returns the questions of the message.
+    pub fn questions(&self) -> &[DnsQuestion] {
+        &self.questions
+    }
+
+    /// Returns the first question of the message, the only one of nearly every query.
+    pub fn first_question(&self) -> Option<&DnsQuestion> {
+        self.questions.first()
+    }
+
+    /// Returns the records of the answer section of the message.
+    pub fn answers(&self) -> &[DnsAnswerRecord] {
+        &self.answers
+    }
+
+    /// Takes the records of the answer section out of the message.
+    pub fn into_answers(self) -> Vec<DnsAnswerRecord> {
+        self.answers
+    }
+
+    /// Returns the records of the answer section that answer `question`, see
+    /// [`DnsAnswerRecord::answers_question`], following the CNAME chain from the name of the
+    /// question so that the records of the aliased names are included (RFC 1034 section 3.6.2).
+    pub fn answers_for(&self, question: &DnsQuestion) -> Vec<&DnsAnswerRecord> {
+        let mut current = question.clone();
+        let mut matching: Vec<&DnsAnswerRecord> = Vec::new();
+
+        // Each alias leads to a new name, so the chain has at most as many links as answers
+        for _ in 0..=self.answers.len() {
+            let mut target = None;
+            for record in &self.answers {
+                if !record.answers_question(&current)
+                    || matching.iter().any(|found| std::ptr::eq(*found, record))
+                {
+                    continue;
+                }
+                if record.record_type == RecordType::CNAME
+                    && question.record_type != RecordType::CNAME
+                {
+                    target = target.or_else(|| record.r_data.as_cname());
+                }
+                matching.push(record);
+            }
+
+            match target {
+                Some(domain_name) => current.domain_name = domain_name,
+                None => break,
+            }
+        }
+
+        matching
+    }
+
+    /// Sorts the answer section by name, compared case-insensitively, then type and class, so
+    /// that the same records always come out in the same order. Records with equal keys keep
+    /// their relative order.
+    pub fn sort_answers(&mut self) {
+        self.answers.sort_by_cached_key(|record| {
+            (
+                record.domain_name.to_ascii_lowercase().wire_format,
+                record.record_type,
+                record.class,
+            )
+        });
+    }
+
+    /// Iterates over the records of the answer section of type `record_type`.
+    pub fn answers_of_type(
+        &self,
+        record_type: RecordType,
+    ) -> impl Iterator<Item = &DnsAnswerRecord> {
+        records_of_type(&self.answers, record_type)
+    }
+
+    /// Iterates over the records of the authority section of type `record_type`.
+    pub fn authorities_of_type(
+        &self,
+        record_type: RecordType,
+    ) -> impl Iterator<Item = &DnsAnswerRecord> {
+        records_of_type(&self.authorities, record_type)
+    }
+
+    /// Iterates over the records of the additional section of type `record_type`.
+    pub fn additionals_of_type(
+        &self,
+        record_type: RecordType,
+    ) -> impl Iterator<Item = &DnsAnswerRecord> {
+        records_of_type(&self.additionals, record_type)
+    }
+
+    /// Whether the message is a query, see [`DnsHeader::is_query`].
+    pub fn is_query(&self) -> bool {
+        self.header.is_query()
+    }
+
+    /// Whether the message is a response, see [`DnsHeader::is_response`].
+    pub fn is_response(&self) -> bool {
+        self.header.is_response()
+    }
+
+    /// Returns the response code carried in the message header.
+    #[doc(alias = "response_code")]
+    pub fn rcode(&self) -> ResponseCode {
+        self.header.response_code
+    }
+
+    /// Overwrites the response code carried in the message header, e.g. to refuse a query after
+    /// its reply was built.
+    #[doc(alias = "set_response_code")]
+    pub fn set_rcode(&mut self, code: ResponseCode) {
+        self.header.response_code = code;
+    }
+
+    /// Compares the parts of two messages that are meaningful to a client: the kind of message,
+    /// its response code, and its questions and answers, whose names are compared regardless of
+    /// case.
+    ///
+    /// The packet identifier and the AA, TC, RD, RA, AD and CD bits, which vary from one exchange
+    /// to the next, are ignored, as are the authority and additional sections.
+    pub fn semantically_eq(&self, other: &DnsMessage) -> bool {
+        let same_name = |first: &DomainName, second: &DomainName| {
+            first.wire_format.eq_ignore_ascii_case(&second.wire_format)
+        };
+
+        self.header.query_response_indicator == other.header.query_response_indicator
+            && self.header.operation_code == other.header.operation_code
+            && self.header.response_code == other.header.response_code
+            && self.questions.len() == other.questions.len()
+            && self
+                .questions
+                .iter()
+                .zip(&other.questions)
+                .all(|(first, second)| {
+                    same_name(&first.domain_name, &second.domain_name)
+                        && first.record_type == second.record_type
+                        && first.class == second.class
+                })
+            && self.answers.len() == other.answers.len()
+            && self
+                .answers
+                .iter()
+                .zip(&other.answers)
+                .all(|(first, second)| {
+                    same_name(&first.domain_name, &second.domain_name)
+                        && DnsAnswerRecord {
+                            domain_name: second.domain_name.clone(),
+                            ..first.clone()
+                        } == *second
+                })
+    }
+
+    /// Returns the full 12-bit response code: the upper 8 bits come from the OPT record, if any,
+    /// and the lower 4 from the header (RFC 6891 section 6.1.3).
+    pub fn extended_rcode(&self) -> u16 {
+        let upper_bits = self.opt().map_or(0, |opt| opt.extended_rcode);
+
+        (u16::from(upper_bits) << 4) | u16::from(u8::from(self.header.response_code) & 0b1111)
+    }
+
+    /// Sets the full 12-bit response code, splitting it across the header and the OPT record.
+    ///
+    /// An OPT record is added when the code does not fit in the header alone.
+    pub fn set_extended_rcode(&mut self, code: u16) {
+        self.header.response_code = ResponseCode::from((code & 0b1111) as u8);
+
+        let upper_bits = (code >> 4) as u8;
+        let mut opt = match self.opt() {
+            Some(opt) => opt,
+            None if upper_bits == 0 => return,
+            None => OptRecord::new(MAX_UDP_PAYLOAD_SIZE),
+        };
+        opt.extended_rcode = upper_bits;
+
+        self.additionals
+            .retain(|record| record.record_type != RecordType::OPT);
+        self.additionals.push(DnsAnswerRecord::from(&opt));
+    }
+
+    /// Checks that the message can be put on the wire as it is, returning the first problem found:
+    /// section counts of the header that differ from the sections, names longer than 255 bytes,
+    /// RDATA whose length is off or does not suit its record type, or an opcode past 4 bits.
+    pub fn validate(&self) -> Result<(), DnsError> {
+        let sections = [
+            ("question", self.header.question_count, self.questions.len()),
+            (
+                "answer",
+                self.header.answer_record_count,
+                self.answers.len(),
+            ),
+            (
+                "authority",
+                self.header.authority_record_count,
+                self.authorities.len(),
+            ),
+            (
+                "additional",
+                self.header.additional_record_count,
+                self.additionals.len(),
+            ),
+        ];
+        for (section, declared, actual) in sections {
+            if declared != actual {
+                return Err(DnsError::SectionCountMismatch {
+                    section,
+                    declared,
+                    actual,
+                });
+            }
+        }
+
+        if let OpCode::Unknown(value) = self.header.operation_code {
+            if value > 0b1111 {
+                return Err(DnsError::OpCodeOutOfRange(value));
+            }
+        }
+
+        let mut records = self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals);
+        let mut names = self
             .questions
             .iter()
-            .flat_map(|question| question.to_bytes())
-            .collect();
-        let answer_records_bytes: Vec<u8> = self
+            .map(|question| &question.domain_name)
+            .chain(records.clone().map(|record| &record.domain_name));
+        if let Some(name) = names.find(|name| name.wire_format.len() > 255) {
+            return Err(DnsError::InvalidDomainName(name.to_text()));
+        }
+
+        records.try_for_each(|record| {
+            if record.r_data_length != record.r_data.0.len()
+                || u16::try_from(record.r_data_length).is_err()
+            {
+                return Err(DnsError::RDataLengthMismatch {
+                    record_type: record.record_type,
+                    length: record.r_data_length,
+                });
+            }
+            record.validate()
+        })
+    }
+
+    /// Renders the message the way `dig` prints it: the header, then every non-empty section with
+    /// a line per entry.
+    pub fn to_dig_string(&self) -> String {
+        let flags: Vec<&str> = [
+            (self.header.is_response(), "qr"),
+            (self.header.authoritative_answer, "aa"),
+            (self.header.truncation, "tc"),
+            (self.header.recursion_desired, "rd"),
+            (self.header.recursion_available, "ra"),
+            (self.header.authentic_data, "ad"),
+            (self.header.checking_disabled, "cd"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+        let mut output = String::new();
+
+        // Writing to a `String` cannot fail
+        let _ = writeln!(
+            output,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            opcode_mnemonic(self.header.operation_code),
+            rcode_mnemonic(self.header.response_code),
+            self.header.packet_identifier
+        );
+        let _ = writeln!(
+            output,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len()
+        );
+
+        if !self.questions.is_empty() {
+            let _ = writeln!(output, "\n;; QUESTION SECTION:");
+        }
+        for question in &self.questions {
+            let _ = writeln!(
+                output,
+                ";{}\t\t{}\t{}",
+                question.domain_name.to_text(),
+                question.class,
+                question.record_type
+            );
+        }
+
+        for (title, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ] {
+            if !records.is_empty() {
+                let _ = writeln!(output, "\n;; {} SECTION:", title);
+            }
+            for record in records {
+                let _ = writeln!(output, "{}", record.to_dig_line());
+            }
+        }
+
+        output
+    }
+
+    /// Serializes the message into its wire format.
+    ///
+    /// The section counts written in the header are those of the sections the message carries,
+    /// whatever `header` says. The names of the questions, the owner names of the records and the
+    /// names inside the RDATA of the RFC 1035 types that carry some (NS, CNAME, SOA, MX, PTR,
+    /// MINFO and the obsolete mailbox types) are compressed against each other, as RFC 3597
+    /// section 4 allows; the RDATA of the other types is written as is.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_into(&mut bytes);
+        bytes
+    }
+
+    /// Same as [`DnsMessage::to_vec`], writing into `bytes`, which is cleared first, so that a
+    /// single buffer can serve every message without allocating anew.
+    pub fn serialize_into(&self, bytes: &mut Vec<u8>) {
+        let header = DnsHeader {
+            question_count: self.questions.len(),
+            answer_record_count: self.answers.len(),
+            authority_record_count: self.authorities.len(),
+            additional_record_count: self.additionals.len(),
+            ..self.header.clone()
+        };
+        bytes.clear();
+        header.write_into(bytes);
+        let mut compressor = NameCompressor::new();
+
+        for question in &self.questions {
+            compressor.write(&question.domain_name, bytes);
+            question.write_fields_after_name(bytes);
+        }
+        for record in self
             .answers
             .iter()
-            .flat_map(|answer| answer.to_bytes())
-            .collect();
+            .chain(self.authorities.iter())
+            .chain(self.additionals.iter())
+        {
+            compressor.write(&record.domain_name, bytes);
+            record.write_compressed_fields_after_name(&mut compressor, bytes);
+        }
+        bytes.extend_from_slice(&self.trailing);
+    }
 
-        let mut buffer = [0u8; 512];
-        let mut offset = 0;
+    /// Serializes the message into a response fitting in a UDP datagram of `max_size` bytes.
+    ///
+    /// If the full message does not fit, the answer and authority sections are dropped and the
+    /// truncation (TC) flag is set so the client knows to retry over TCP (RFC 2181 section 9).
+    pub fn to_udp_bytes(&self, max_size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_udp_into(max_size, &mut bytes);
+        bytes
+    }
+
+    /// Same as [`DnsMessage::to_udp_bytes`], writing into `bytes` like
+    /// [`DnsMessage::serialize_into`].
+    pub fn serialize_udp_into(&self, max_size: usize, bytes: &mut Vec<u8>) {
+        self.serialize_into(bytes);
+        if bytes.len() <= max_size {
+            return;
+        }
 
-        // Copy first vec
-        buffer[offset..offset + header_bytes.len()].copy_from_slice(&header_bytes);
-        offset += header_bytes.len();
+        let mut truncated = self.clone();
+        truncated.header.truncation = true;
+        truncated.answers.clear();
+        truncated.authorities.clear();
+        truncated.trailing.clear();
 
-        // Copy second vec
-        buffer[offset..offset + questions_bytes.len()].copy_from_slice(&questions_bytes);
-        offset += questions_bytes.len();
+        truncated.serialize_into(bytes);
+    }
+
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let bytes = self.to_vec();
+        let length = bytes.len().min(512);
 
-        // Copy third vec
-        buffer[offset..offset + answer_records_bytes.len()].copy_from_slice(&answer_records_bytes);
+        let mut buffer = [0u8; 512];
+        buffer[..length].copy_from_slice(&bytes[..length]);
 
         buffer
     }
 }
 
+/// Iterates over the records of the answer section.
+impl<'a> IntoIterator for &'a DnsMessage {
+    type Item = &'a DnsAnswerRecord;
+    type IntoIter = std::slice::Iter<'a, DnsAnswerRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.answers.iter()
+    }
+}
+
+fn records_of_type(
+    records: &[DnsAnswerRecord],
+    record_type: RecordType,
+) -> impl Iterator<Item = &DnsAnswerRecord> {
+    records
+        .iter()
+        .filter(move |record| record.record_type == record_type)
+}
+
+/// Builds a `DnsMessage` section by section, with header counts derived from the sections.
+#[derive(Debug, Clone)]
+pub struct DnsMessageBuilder {
+    message: DnsMessage,
+}
+
+impl DnsMessageBuilder {
+    pub fn new() -> Self {
+        DnsMessageBuilder {
+            message: DnsMessage {
+                header: DnsHeaderBuilder::new().build(),
+                questions: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                trailing: Vec::new(),
+            },
+        }
+    }
+
+    /// Sets the header of the message. Its section counts are overwritten by `build`.
+    pub fn header(mut self, header: DnsHeader) -> Self {
+        self.message.header = header;
+        self
+    }
+
+    pub fn add_question(mut self, question: DnsQuestion) -> Self {
+        self.message.questions.push(question);
+        self
+    }
+
+    pub fn add_answer(mut self, answer: DnsAnswerRecord) -> Self {
+        self.message.answers.push(answer);
+        self
+    }
+
+    pub fn add_authority(mut self, authority: DnsAnswerRecord) -> Self {
+        self.message.authorities.push(authority);
+        self
+    }
+
+    pub fn add_additional(mut self, additional: DnsAnswerRecord) -> Self {
+        self.message.additionals.push(additional);
+        self
+    }
+
+    /// Returns the message, with the section counts of its header matching its sections.
+    pub fn build(mut self) -> DnsMessage {
+        let header = &mut self.message.header;
+        header.question_count = self.message.questions.len();
+        header.answer_record_count = self.message.answers.len();
+        header.authority_record_count = self.message.authorities.len();
+        header.additional_record_count = self.message.additionals.len();
+
+        self.message
+    }
+}
+
+impl Default for DnsMessageBuilder {
+    fn default() -> Self {
+        DnsMessageBuilder::new()
+    }
+}
+
+/// The name `dig` gives to `operation_code`.
+fn opcode_mnemonic(operation_code: OpCode) -> String {
+    match operation_code {
+        OpCode::Query => "QUERY".to_string(),
+        OpCode::IQuery => "IQUERY".to_string(),
+        OpCode::Status => "STATUS".to_string(),
+        OpCode::Notify => "NOTIFY".to_string(),
+        OpCode::Update => "UPDATE".to_string(),
+        OpCode::Unknown(value) => format!("RESERVED{}", value),
+    }
+}
+
+/// The name `dig` gives to `response_code`.
+fn rcode_mnemonic(response_code: ResponseCode) -> String {
+    match response_code {
+        ResponseCode::NoError => "NOERROR".to_string(),
+        ResponseCode::FormatError => "FORMERR".to_string(),
+        ResponseCode::ServerFailure => "SERVFAIL".to_string(),
+        ResponseCode::NameError => "NXDOMAIN".to_string(),
+        ResponseCode::NotImplemented => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        ResponseCode::Unknown(value) => format!("RCODE{}", value),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::dns::opt_record::{Cookie, BADVERS};
+
     use crate::dns::{answer_record::RData, Class, DomainName, QRIndicator, RecordType};
 
     use super::*;
@@ -179,12 +900,14 @@ mod tests {
                 header: DnsHeader {
                     packet_identifier: 0x1234,
                     query_response_indicator: QRIndicator::Question,
-                    operation_code: 0,
+                    operation_code: OpCode::Query,
                     authoritative_answer: false,
                     truncation: false,
                     recursion_desired: true,
                     recursion_available: false,
                     reserved: 0,
+                    authentic_data: false,
+                    checking_disabled: false,
                     response_code: ResponseCode::NoError,
                     question_count: 1,
                     answer_record_count: 1,
@@ -224,6 +947,9 @@ mod tests {
                     r_data_length: 4,
                     r_data: RData(vec![1, 2, 3, 4]),
                 }],
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+                trailing: Vec::new(),
             })
         );
 
@@ -235,6 +961,13 @@ mod tests {
         bad_packet[bad_rdata_pos + 1] = 0xFF; // RDLEN=511 but only 4 bytes present
 
         assert!(DnsMessage::new(&bad_packet).is_err());
+        assert_eq!(
+            DnsMessage::try_parse(&bad_packet[..bad_rdata_pos + 6]),
+            Err(DnsError::RDataLengthMismatch {
+                record_type: RecordType::A,
+                length: 511,
+            })
+        );
     }
 
     #[test]
@@ -242,12 +975,14 @@ mod tests {
         let header = DnsHeader {
             packet_identifier: 0xBEEF,
             query_response_indicator: crate::dns::QRIndicator::Reply,
-            operation_code: 0,
+            operation_code: OpCode::Query,
             authoritative_answer: false,
             truncation: false,
             recursion_desired: true,
             recursion_available: false,
             reserved: 0,
+            authentic_data: false,
+            checking_disabled: false,
             response_code: crate::dns::ResponseCode::NoError,
             question_count: 1,
             answer_record_count: 1,
@@ -280,6 +1015,9 @@ mod tests {
             header: header.clone(),
             questions: vec![question.clone()],
             answers: vec![answer.clone()],
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            trailing: Vec::new(),
         };
 
         let bytes = message.to_bytes();
@@ -287,4 +1025,933 @@ mod tests {
 
         assert_eq!(parsed, Ok(message));
     }
+
+    #[test]
+    fn test_dns_message_from_reader() {
+        let query_bytes = [
+            0x12, 0x34,       // packet_identifier
+            0b00000001, // QR=0, Opcode=0, AA=0, TC=0, RD=1
+            0b00000000, // RA=0, Z=0, RCODE=0
+            0x00, 0x01, // QDCOUNT = 1
+            0x00, 0x00, // ANCOUNT = 0
+            0x00, 0x00, // NSCOUNT = 0
+            0x00, 0x00, // ARCOUNT = 0
+            // Question: com A IN
+            0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        let mut packet = [0u8; 512];
+        packet[..query_bytes.len()].copy_from_slice(&query_bytes);
+
+        // Two messages back to back, followed by a truncated one
+        let stream = [&query_bytes[..], &query_bytes[..], &query_bytes[..15]].concat();
+        let mut reader = std::io::Cursor::new(stream);
+
+        for _ in 0..2 {
+            assert_eq!(
+                DnsMessage::from_reader(&mut reader, query_bytes.len()),
+                DnsMessage::new(&packet).map_err(|_| DnsError::MalformedPacket)
+            );
+        }
+        assert_eq!(
+            DnsMessage::from_reader(&mut reader, query_bytes.len()),
+            Err(DnsError::Io(std::io::ErrorKind::UnexpectedEof))
+        );
+
+        // Reading a part of the message does not give a valid message
+        let mut reader = std::io::Cursor::new(query_bytes);
+        assert_eq!(
+            DnsMessage::from_reader(&mut reader, 15),
+            Err(DnsError::MalformedPacket)
+        );
+    }
+
+    #[test]
+    fn test_dns_message_accessors() {
+        let question = DnsQuestion {
+            domain_name: "codecrafters.io".parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let query = DnsMessage {
+            header: DnsHeaderBuilder::new().id(1234).questions(1).build(),
+            questions: vec![question.clone()],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            trailing: Vec::new(),
+        };
+        let reply = query.build_reply();
+
+        assert_eq!(reply.header().packet_identifier, 1234);
+        assert!(reply.header().is_response());
+        assert_eq!(reply.questions(), [question]);
+        assert_eq!(reply.answers().len(), 1);
+        assert_eq!(
+            reply.answers()[0].r_data.as_a(),
+            Some("8.8.8.8".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_dns_message_builder_derives_counts() {
+        let question = DnsQuestion {
+            domain_name: "codecrafters.io".parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let answer = DnsAnswerRecord {
+            domain_name: question.domain_name.clone(),
+            record_type: RecordType::A,
+            class: Class::IN,
+            time_to_live: 60,
+            r_data_length: 4,
+            r_data: RData(vec![8, 8, 8, 8]),
+        };
+
+        // The counts given in the header are not trusted
+        let message = DnsMessage::builder()
+            .header(
+                DnsHeaderBuilder::new()
+                    .id(1234)
+                    .response()
+                    .answers(5)
+                    .build(),
+            )
+            .add_question(question.clone())
+            .add_answer(answer.clone())
+            .add_answer(answer.clone())
+            .build();
+
+        assert_eq!(message.header().packet_identifier, 1234);
+        assert_eq!(message.header().question_count, 1);
+        assert_eq!(message.header().answer_record_count, 2);
+        assert_eq!(message.header().authority_record_count, 0);
+        assert_eq!(message.questions(), [question]);
+        assert_eq!(message.answers(), [answer.clone(), answer]);
+        assert_eq!(DnsMessage::new(&message.to_bytes()), Ok(message));
+    }
+
+    #[test]
+    fn test_dns_message_to_vec_corrects_counts() {
+        let mut message = DnsMessage::builder()
+            .add_question(DnsQuestion {
+                domain_name: "codecrafters.io".parse().unwrap(),
+                record_type: RecordType::A,
+                class: Class::IN,
+            })
+            .build();
+        message.header.question_count = 3;
+        message.header.answer_record_count = 1;
+
+        let bytes = message.to_vec();
+        assert_eq!(bytes[4..12], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            DnsMessage::new(&message.to_bytes())
+                .unwrap()
+                .questions
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_dns_message_try_parse() {
+        assert_eq!(DnsMessage::try_parse(&[]), Err(DnsError::PacketTooShort));
+        assert_eq!(
+            DnsMessage::try_parse(&[0x12, 0x34, 0x01, 0x00]),
+            Err(DnsError::PacketTooShort)
+        );
+
+        // One question announced, but the name runs past the end of the packet
+        let header = DnsHeaderBuilder::new().questions(1).build().to_bytes();
+        let packet = [&header[..], &[0x03, b'c', b'o', b'm', 0x00, 0x00]].concat();
+        assert_eq!(
+            DnsMessage::try_parse(&packet),
+            Err(DnsError::MalformedPacket)
+        );
+
+        // An A record carrying an IPv6 address
+        let mut answer =
+            DnsAnswerRecord::new_aaaa("codecrafters.io", Ipv6Addr::LOCALHOST, 60).unwrap();
+        answer.record_type = RecordType::A;
+        let message = DnsMessage::builder().add_answer(answer).build();
+        assert_eq!(
+            DnsMessage::try_parse(&message.to_vec()),
+            Err(DnsError::RDataLengthMismatch {
+                record_type: RecordType::A,
+                length: 16
+            })
+        );
+
+        // A dynamic update deleting the A records of a name (RFC 2136 section 2.5.2)
+        let delete_rrset = DnsAnswerRecord {
+            domain_name: "www.example.com".parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::ANY,
+            time_to_live: 0,
+            r_data_length: 0,
+            r_data: RData(Vec::new()),
+        };
+        let update = DnsMessage::builder()
+            .header(
+                DnsHeaderBuilder::new()
+                    .operation_code(OpCode::Update)
+                    .build(),
+            )
+            .add_authority(delete_rrset.clone())
+            .build();
+        assert_eq!(
+            DnsMessage::try_parse(&update.to_vec()).map(|update| update.authorities),
+            Ok(vec![delete_rrset])
+        );
+    }
+
+    #[test]
+    fn test_dns_message_answers_without_questions() {
+        #[rustfmt::skip]
+        let packet = [
+            0x12, 0x34, 0x84, 0x00, // ID, QR=1, AA=1
+            0x00, 0x00, 0x00, 0x01, // QDCOUNT = 0, ANCOUNT = 1
+            0x00, 0x00, 0x00, 0x00, // NSCOUNT = 0, ARCOUNT = 0
+            0x03, b'c', b'o', b'm', 0x00, // "com"
+            0x00, 0x01, 0x00, 0x01, // TYPE = A, CLASS = IN
+            0x00, 0x00, 0x00, 0x3c, // TTL = 60
+            0x00, 0x04, 1, 2, 3, 4, // RDLEN = 4, RDATA = 1.2.3.4
+        ];
+        let answer = DnsAnswerRecord::new_a("com", Ipv4Addr::new(1, 2, 3, 4), 60).unwrap();
+
+        let message = DnsMessage::try_parse(&packet).unwrap();
+        assert!(message.questions().is_empty());
+        assert_eq!(message.answers(), std::slice::from_ref(&answer));
+        assert!(message.trailing.is_empty());
+        assert_eq!(message.to_vec(), packet);
+
+        let mut buffer = [0; 512];
+        buffer[..packet.len()].copy_from_slice(&packet);
+        assert_eq!(DnsMessage::new(&buffer).unwrap().answers(), [answer]);
+    }
+
+    #[test]
+    fn test_dns_message_to_vec_compresses_names() {
+        let question =
+            DnsQuestion::from_parts("codecrafters.io", RecordType::A, Class::IN).unwrap();
+        let answers = [
+            DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 21), 60).unwrap(),
+            DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 22), 60).unwrap(),
+            DnsAnswerRecord::new_a("www.codecrafters.io", Ipv4Addr::new(76, 76, 21, 23), 60)
+                .unwrap(),
+        ];
+        let reply = answers
+            .iter()
+            .cloned()
+            .fold(
+                DnsMessage::builder().add_question(question.clone()),
+                DnsMessageBuilder::add_answer,
+            )
+            .build();
+
+        let bytes = reply.to_vec();
+        let naive_length = 12
+            + question.to_bytes().len()
+            + answers
+                .iter()
+                .map(|answer| answer.to_bytes().len())
+                .sum::<usize>();
+        // Both repeated names become 2-byte pointers, and "www" is followed by one
+        assert_eq!(bytes.len(), naive_length - 2 * 15 - 15);
+        // The answers point back at the name of the question, right after the header
+        assert_eq!(bytes[33..35], [0xC0, 12]);
+
+        let parsed = DnsMessage::try_parse(&bytes).unwrap();
+        assert_eq!(parsed.questions(), &[question]);
+        assert_eq!(parsed.answers(), &answers);
+    }
+
+    #[test]
+    fn test_dns_message_compressed_names_in_r_data() {
+        let packet = [
+            &[
+                0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            ][..],
+            // Question at offset 12: www.example.com A IN
+            &[
+                3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+            ],
+            &[3, b'c', b'o', b'm', 0, 0x00, 0x01, 0x00, 0x01],
+            // Answer: www.example.com CNAME web + a pointer to example.com, at offset 16
+            &[
+                0xC0, 12, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x06,
+            ],
+            &[3, b'w', b'e', b'b', 0xC0, 16],
+        ]
+        .concat();
+
+        let parsed = DnsMessage::try_parse(&packet).unwrap();
+        let cname = parsed.answers()[0].clone();
+        assert_eq!(
+            cname.r_data.as_cname().map(|name| name.to_text()),
+            Some("web.example.com.".to_string())
+        );
+        assert_eq!(cname.r_data_length, 17);
+
+        // Behind another question, the record moves and its target points elsewhere
+        let moved = DnsMessage::builder()
+            .add_question(
+                DnsQuestion::from_parts("codecrafters.io", RecordType::A, Class::IN).unwrap(),
+            )
+            .add_answer(cname.clone())
+            .build();
+        let reparsed = DnsMessage::try_parse(&moved.to_vec()).unwrap();
+        assert_eq!(reparsed.answers(), &[cname]);
+        // In place, the target is compressed again
+        assert_eq!(parsed.to_vec(), packet);
+    }
+
+    #[test]
+    fn test_dns_message_to_dig_string() {
+        let question =
+            DnsQuestion::from_parts("codecrafters.io", RecordType::A, Class::IN).unwrap();
+        let reply = DnsMessage::builder()
+            .header(
+                DnsHeaderBuilder::new()
+                    .id(1234)
+                    .response()
+                    .recursion_desired(true)
+                    .build(),
+            )
+            .add_question(question)
+            .add_answer(
+                DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 21), 60)
+                    .unwrap(),
+            )
+            .build();
+
+        let output = reply.to_dig_string();
+        assert!(output.starts_with(";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 1234\n"));
+        assert!(output.contains(";; flags: qr rd; QUERY: 1, ANSWER: 1, AUTHORITY: 0"));
+        assert!(output.contains(";codecrafters.io.\t\tIN\tA\n"));
+        assert!(output.contains(";; ANSWER SECTION:\ncodecrafters.io.\t60\tIN\tA\t76.76.21.21\n"));
+        assert!(!output.contains("AUTHORITY SECTION"));
+    }
+
+    #[test]
+    fn test_dns_message_build_reply_without_questions() {
+        let query = DnsMessage::builder()
+            .header(DnsHeaderBuilder::new().id(4321).build())
+            .build();
+        let reply = query.build_reply();
+
+        assert!(reply.is_response());
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert!(reply.questions().is_empty());
+        assert!(reply.answers().is_empty());
+        assert_eq!(reply.header().answer_record_count, 0);
+
+        let parsed = DnsMessage::try_parse(&reply.to_vec()).unwrap();
+        assert_eq!(parsed.header().packet_identifier, 4321);
+        assert_eq!(parsed, reply);
+    }
+
+    #[test]
+    fn test_dns_message_semantically_eq() {
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let reply = query.build_reply();
+
+        let mut other_reply = reply.clone();
+        other_reply.header.packet_identifier = reply.header.packet_identifier.wrapping_add(1);
+        other_reply.header.recursion_available = true;
+        other_reply.header.authoritative_answer = true;
+        other_reply.answers[0].domain_name = "CodeCrafters.IO".parse().unwrap();
+        assert_ne!(reply, other_reply);
+        assert!(reply.semantically_eq(&other_reply));
+
+        other_reply.answers[0].time_to_live += 1;
+        assert!(!reply.semantically_eq(&other_reply));
+        assert!(!reply.semantically_eq(&query));
+
+        let mut failed_reply = reply.clone();
+        failed_reply.set_rcode(ResponseCode::ServerFailure);
+        assert!(!reply.semantically_eq(&failed_reply));
+    }
+
+    #[test]
+    fn test_dns_message_extended_rcode() {
+        let mut reply = DnsMessage::query("codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply();
+        assert_eq!(reply.opt(), None);
+
+        // BADVERS does not fit in the header, so an OPT record carries its upper bits
+        reply.set_extended_rcode(BADVERS);
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert_eq!(reply.opt().map(|opt| opt.extended_rcode), Some(1));
+        assert_eq!(reply.extended_rcode(), 16);
+
+        let parsed = DnsMessage::try_parse(&reply.to_vec()).unwrap();
+        assert_eq!(parsed.extended_rcode(), BADVERS);
+
+        reply.set_extended_rcode(u16::from(u8::from(ResponseCode::Refused)));
+        assert_eq!(reply.rcode(), ResponseCode::Refused);
+        assert_eq!(reply.extended_rcode(), 5);
+        assert_eq!(reply.additionals.len(), 1);
+
+        // BADCOOKIE (RFC 7873) has lower bits that no base code uses
+        reply.set_extended_rcode(23);
+        assert_eq!(reply.rcode(), ResponseCode::Unknown(7));
+        assert_eq!(reply.extended_rcode(), 23);
+        let parsed = DnsMessage::try_parse(&reply.to_vec()).unwrap();
+        assert_eq!(parsed.extended_rcode(), 23);
+    }
+
+    #[test]
+    fn test_dns_message_parse_stream() {
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let reply = query.build_reply();
+        let length_prefixed = |message: &DnsMessage| {
+            let bytes = message.to_vec();
+            [(bytes.len() as u16).to_be_bytes().to_vec(), bytes].concat()
+        };
+        let stream = [length_prefixed(&query), length_prefixed(&reply)].concat();
+
+        assert_eq!(DnsMessage::parse_stream(&stream), Ok(vec![query, reply]));
+        assert_eq!(DnsMessage::parse_stream(&[]), Ok(Vec::new()));
+        assert_eq!(
+            DnsMessage::parse_stream(&stream[..stream.len() - 1]),
+            Err(DnsError::PacketTooShort)
+        );
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn test_dns_message_try_parse_never_panics(
+            packet in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..600)
+        ) {
+            let _ = DnsMessage::try_parse(&packet);
+        }
+    }
+
+    #[test]
+    fn test_dns_message_keeps_trailing_bytes() {
+        let query = DnsMessage::builder()
+            .header(DnsHeaderBuilder::new().id(1234).build())
+            .add_question(DnsQuestion {
+                domain_name: "codecrafters.io".parse().unwrap(),
+                record_type: RecordType::A,
+                class: Class::IN,
+            })
+            .build();
+        let packet = [query.to_vec(), vec![0xde, 0xad, 0x00, 0xbe, 0xef]].concat();
+
+        let parsed = DnsMessage::try_parse(&packet).unwrap();
+        assert_eq!(parsed.trailing, [0xde, 0xad, 0x00, 0xbe, 0xef]);
+        assert_eq!(parsed.to_vec(), packet);
+
+        // The zero padding of a 512-byte buffer is not part of the message
+        let mut buffer = [0; 512];
+        buffer[..packet.len()].copy_from_slice(&packet);
+        assert_eq!(DnsMessage::new(&buffer), Ok(parsed));
+        assert_eq!(DnsMessage::new(&query.to_bytes()), Ok(query));
+    }
+
+    #[test]
+    fn test_dns_message_with_answers() {
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let answers = vec![
+            DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 21), 60).unwrap(),
+            DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 22), 60).unwrap(),
+        ];
+
+        let reply = query.build_reply().with_answers(answers.clone());
+        assert_eq!(reply.header().answer_record_count, 2);
+        assert_eq!(reply.answers(), answers);
+        assert_eq!(DnsMessage::try_parse(&reply.to_vec()), Ok(reply));
+    }
+
+    #[test]
+    fn test_dns_message_validate() {
+        let reply = DnsMessage::query("codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply();
+        assert_eq!(reply.validate(), Ok(()));
+
+        let mut miscounted = reply.clone();
+        miscounted.header.answer_record_count = 2;
+        assert_eq!(
+            miscounted.validate(),
+            Err(DnsError::SectionCountMismatch {
+                section: "answer",
+                declared: 2,
+                actual: 1,
+            })
+        );
+
+        let mut bad_r_data = reply.clone();
+        bad_r_data.answers[0].r_data.0.push(0);
+        bad_r_data.answers[0].r_data_length = 5;
+        assert_eq!(
+            bad_r_data.validate(),
+            Err(DnsError::RDataLengthMismatch {
+                record_type: RecordType::A,
+                length: 5,
+            })
+        );
+
+        let mut bad_opcode = reply;
+        bad_opcode.header.operation_code = OpCode::Unknown(16);
+        assert_eq!(bad_opcode.validate(), Err(DnsError::OpCodeOutOfRange(16)));
+    }
+
+    #[test]
+    fn test_dns_message_iterates_over_answers() {
+        let answers = vec![
+            DnsAnswerRecord::new_a("codecrafters.io", Ipv4Addr::new(76, 76, 21, 21), 60).unwrap(),
+            DnsAnswerRecord::new_aaaa("codecrafters.io", Ipv6Addr::LOCALHOST, 60).unwrap(),
+        ];
+        let reply = DnsMessage::query("codecrafters.io", RecordType::ANY)
+            .unwrap()
+            .build_reply_with(|_| answers.clone());
+
+        let mut record_types = Vec::new();
+        for record in &reply {
+            record_types.push(record.record_type);
+        }
+        assert_eq!(record_types, [RecordType::A, RecordType::AAAA]);
+        assert_eq!(reply.into_answers(), answers);
+    }
+
+    #[test]
+    fn test_dns_message_answers_for() {
+        let cname =
+            DnsAnswerRecord::new_cname("www.codecrafters.io", "codecrafters.io", 60).unwrap();
+        let a =
+            DnsAnswerRecord::new_a("CodeCrafters.io", Ipv4Addr::new(76, 76, 21, 21), 60).unwrap();
+        let unrelated = DnsAnswerRecord::new_a("example.com", Ipv4Addr::LOCALHOST, 60).unwrap();
+        let reply = DnsMessage::query("www.codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply_with(|_| vec![cname.clone(), a.clone(), unrelated.clone()]);
+
+        let question = reply.first_question().unwrap();
+        assert_eq!(reply.answers_for(question), [&cname, &a]);
+
+        let other = DnsQuestion::from_parts("example.org", RecordType::A, Class::IN).unwrap();
+        assert!(reply.answers_for(&other).is_empty());
+    }
+
+    #[test]
+    fn test_dns_message_sort_answers() {
+        let answers = [
+            DnsAnswerRecord::new_a("b.example.com", Ipv4Addr::new(192, 0, 2, 2), 60).unwrap(),
+            DnsAnswerRecord::new_aaaa("A.example.com", Ipv6Addr::LOCALHOST, 60).unwrap(),
+            DnsAnswerRecord::new_a("a.example.com", Ipv4Addr::new(192, 0, 2, 1), 60).unwrap(),
+            DnsAnswerRecord::new_a("a.example.com", Ipv4Addr::new(192, 0, 2, 3), 60).unwrap(),
+        ];
+        let sorted = [&answers[2], &answers[3], &answers[1], &answers[0]];
+
+        // The shuffles sort the same, the two A records of a.example.com keeping their order
+        for shuffle in [[0, 1, 2, 3], [1, 2, 0, 3], [2, 0, 3, 1], [2, 3, 1, 0]] {
+            let mut message = DnsMessage::builder()
+                .build()
+                .with_answers(shuffle.iter().map(|&i| answers[i].clone()).collect());
+            message.sort_answers();
+            assert_eq!(message.answers().iter().collect::<Vec<_>>(), sorted);
+        }
+    }
+
+    #[test]
+    fn test_dns_message_first_question() {
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        assert_eq!(
+            query.first_question(),
+            Some(&DnsQuestion::from_parts("codecrafters.io", RecordType::A, Class::IN).unwrap())
+        );
+
+        let without_questions = DnsMessage::builder().build();
+        assert_eq!(without_questions.first_question(), None);
+    }
+
+    #[test]
+    fn test_dns_message_query() {
+        let query = DnsMessage::query("codecrafters.io", RecordType::AAAA).unwrap();
+        let parsed = DnsMessage::new(&query.to_bytes()).unwrap();
+
+        assert_eq!(parsed, query);
+        assert!(parsed.header.is_query());
+        assert!(parsed.header.recursion_desired);
+        assert_eq!(parsed.header.question_count, 1);
+        assert_eq!(
+            parsed.questions[0].domain_name.to_text(),
+            "codecrafters.io."
+        );
+        assert_eq!(parsed.questions[0].record_type, RecordType::AAAA);
+        assert_eq!(parsed.questions[0].class, Class::IN);
+        assert!(parsed.answers.is_empty());
+
+        assert_eq!(
+            DnsMessage::query("bad..name", RecordType::A),
+            Err(DnsError::InvalidDomainName("bad..name".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_dns_message_records_of_type() {
+        let record = |record_type: RecordType, r_data: Vec<u8>| DnsAnswerRecord {
+            domain_name: "example.com".parse().unwrap(),
+            record_type,
+            class: Class::IN,
+            time_to_live: 60,
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        };
+        let a = record(RecordType::A, vec![1, 2, 3, 4]);
+        let other_a = record(RecordType::A, vec![5, 6, 7, 8]);
+        let ns = record(
+            RecordType::NS,
+            "ns.example.com".parse::<DomainName>().unwrap().wire_format,
+        );
+        let message = DnsMessage::builder()
+            .add_answer(a.clone())
+            .add_answer(ns.clone())
+            .add_answer(other_a.clone())
+            .add_authority(ns.clone())
+            .add_additional(a.clone())
+            .build();
+
+        assert_eq!(
+            message.answers_of_type(RecordType::A).collect::<Vec<_>>(),
+            [&a, &other_a]
+        );
+        assert_eq!(
+            message.answers_of_type(RecordType::NS).collect::<Vec<_>>(),
+            [&ns]
+        );
+        assert_eq!(message.answers_of_type(RecordType::MX).count(), 0);
+        assert_eq!(
+            message
+                .authorities_of_type(RecordType::NS)
+                .collect::<Vec<_>>(),
+            [&ns]
+        );
+        assert_eq!(message.authorities_of_type(RecordType::A).count(), 0);
+        assert_eq!(
+            message
+                .additionals_of_type(RecordType::A)
+                .collect::<Vec<_>>(),
+            [&a]
+        );
+    }
+
+    #[test]
+    fn test_dns_message_rcode() {
+        let mut message = DnsMessage::build_error_reply(1234, false);
+        assert_eq!(message.rcode(), ResponseCode::ServerFailure);
+
+        message.set_rcode(ResponseCode::Refused);
+        assert_eq!(message.rcode(), ResponseCode::Refused);
+        assert_eq!(
+            message.to_bytes()[3] & 0b00001111,
+            u8::from(ResponseCode::Refused)
+        );
+
+        // Only the RCODE bits of a built reply change
+        let mut reply = DnsMessage::query("codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply();
+        assert_eq!(reply.to_bytes()[3], 0b00000000);
+        reply.set_rcode(ResponseCode::Refused);
+        assert_eq!(reply.to_bytes()[2..4], [0b10000001, 0b00000101]);
+
+        reply.set_rcode(ResponseCode::NameError);
+        assert_eq!(reply.rcode(), ResponseCode::NameError);
+        assert_eq!(reply.to_bytes()[2..4], [0b10000001, 0b00000011]);
+    }
+
+    #[test]
+    fn test_dns_message_build_error_reply() {
+        let reply = DnsMessage::build_error_reply(0xCAFE, true);
+        let parsed = DnsMessage::new(&reply.to_bytes()).unwrap();
+
+        assert_eq!(parsed.header.packet_identifier, 0xCAFE);
+        assert!(parsed.header.is_response());
+        assert!(parsed.header.recursion_desired);
+        assert_eq!(parsed.rcode(), ResponseCode::ServerFailure);
+    }
+
+    #[test]
+    fn test_dns_message_build_format_error_reply() {
+        let question_bytes = [0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01];
+        let header_bytes = [
+            0x0b, 0xad,       // packet_identifier
+            0b00000001, // QR=0, Opcode=0, AA=0, TC=0, RD=1
+            0b00000000, // RA=0, Z=0, RCODE=0
+            0x00, 0x01, // QDCOUNT = 1
+            0x00, 0x01, // ANCOUNT = 1, but no answer follows
+            0x00, 0x00, // NSCOUNT = 0
+            0x00, 0x00, // ARCOUNT = 0
+        ];
+        let packet = [&header_bytes[..], &question_bytes[..]].concat();
+
+        // The question could be parsed, so it is echoed
+        let reply = DnsMessage::build_format_error_reply(&packet).unwrap();
+        assert_eq!(reply.header.packet_identifier, 0x0bad);
+        assert!(reply.header.is_response());
+        assert_eq!(reply.rcode(), ResponseCode::FormatError);
+        assert_eq!(reply.questions.len(), 1);
+        assert_eq!(reply.header.question_count, 1);
+        assert!(reply.answers.is_empty());
+
+        // A corrupt question section is left out
+        let reply = DnsMessage::build_format_error_reply(&packet[..15]).unwrap();
+        assert_eq!(reply.header.packet_identifier, 0x0bad);
+        assert!(reply.questions.is_empty());
+        assert_eq!(reply.header.question_count, 0);
+
+        assert_eq!(
+            DnsMessage::build_format_error_reply(&packet[..11]),
+            Err(DnsError::PacketTooShort)
+        );
+    }
+
+    #[test]
+    fn test_dns_message_echoes_opt_record() {
+        let query_bytes = [
+            0xab, 0xcd,       // packet_identifier
+            0b00000001, // QR=0, Opcode=0, AA=0, TC=0, RD=1
+            0b00000000, // RA=0, Z=0, RCODE=0
+            0x00, 0x01, // QDCOUNT = 1
+            0x00, 0x00, // ANCOUNT = 0
+            0x00, 0x00, // NSCOUNT = 0
+            0x00, 0x01, // ARCOUNT = 1
+            // Question: example.com A IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+            0x01, 0x00, 0x01,
+            // OPT record advertising a 4096 bytes UDP payload size
+            0x00, // root domain name
+            0x00, 0x29, // TYPE = OPT
+            0x10, 0x00, // UDP payload size = 4096
+            0x00, 0x00, 0x00, 0x00, // extended RCODE, version, DO=0, Z
+            0x00, 0x00, // RDLEN = 0
+        ];
+        let mut packet = [0u8; 512];
+        packet[..query_bytes.len()].copy_from_slice(&query_bytes);
+
+        let query = DnsMessage::new(&packet).unwrap();
+        assert_eq!(query.opt().map(|opt| opt.udp_payload_size), Some(4096));
+        assert_eq!(query.max_udp_payload_size(), 4096);
+
+        let reply = query.build_reply();
+        assert_eq!(reply.opt(), Some(OptRecord::new(MAX_UDP_PAYLOAD_SIZE)));
+        assert_eq!(reply.header.additional_record_count, 1);
+
+        // The OPT record survives serialization
+        let reparsed = DnsMessage::new(&reply.to_bytes()).unwrap();
+        assert_eq!(reparsed.opt(), Some(OptRecord::new(4096)));
+
+        // Without an OPT record, the classic 512 bytes limit applies
+        let mut plain_query = query.clone();
+        plain_query.additionals.clear();
+        plain_query.header.additional_record_count = 0;
+        assert_eq!(plain_query.max_udp_payload_size(), 512);
+        assert_eq!(plain_query.build_reply().opt(), None);
+    }
+
+    #[test]
+    fn test_dns_message_answers_cookie() {
+        let mut query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let client_cookie = Cookie {
+            client: [1, 2, 3, 4, 5, 6, 7, 8],
+            server: None,
+        };
+        let mut opt = OptRecord::new(MAX_UDP_PAYLOAD_SIZE);
+        opt.set_cookie(&client_cookie);
+        query.additionals.push(DnsAnswerRecord::from(&opt));
+        query.header.additional_record_count = 1;
+
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut reply = query.build_reply();
+        assert_eq!(reply.opt().and_then(|opt| opt.cookie()), None);
+        reply.answer_cookie(&query, client);
+        let reply_cookie = reply.opt().and_then(|opt| opt.cookie());
+        assert_eq!(reply_cookie, Some(client_cookie.answer(client)));
+
+        // Another address gets another server cookie for the same client cookie
+        let other_client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let mut other_reply = query.build_reply();
+        other_reply.answer_cookie(&query, other_client);
+        let other_cookie = other_reply.opt().and_then(|opt| opt.cookie());
+        assert_ne!(other_cookie.unwrap().server, reply_cookie.unwrap().server);
+    }
+
+    #[test]
+    fn test_dns_message_serialize_into_reuses_buffer() {
+        let long = DnsMessage::query("www.codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply_with(|_| {
+                vec![DnsAnswerRecord::new_txt("www.codecrafters.io", &"a".repeat(600), 60).unwrap()]
+            });
+        let short = DnsMessage::query("io", RecordType::A).unwrap();
+
+        let mut bytes = Vec::new();
+        long.serialize_into(&mut bytes);
+        assert_eq!(bytes, long.to_vec());
+        let capacity = bytes.capacity();
+
+        // The shorter message replaces the longer one in the same allocation
+        short.serialize_into(&mut bytes);
+        assert_eq!(bytes, short.to_vec());
+        assert_eq!(bytes.capacity(), capacity);
+
+        long.serialize_udp_into(512, &mut bytes);
+        assert_eq!(bytes, long.to_udp_bytes(512));
+        assert!(DnsMessage::try_parse(&bytes).unwrap().header.truncation);
+    }
+
+    #[test]
+    fn test_dns_message_to_udp_bytes_truncates() {
+        let mut reply = DnsMessage::build_error_reply(1234, false);
+        reply.questions = vec![DnsQuestion {
+            domain_name: DomainName::new(&[0x03, b'c', b'o', b'm', 0x00]).unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        }];
+        reply.header.question_count = 1;
+        reply.answers = vec![
+            DnsAnswerRecord {
+                domain_name: reply.questions[0].domain_name.clone(),
+                record_type: RecordType::TXT,
+                class: Class::IN,
+                time_to_live: 60,
+                r_data_length: 400,
+                r_data: RData(vec![0; 400]),
+            };
+            2
+        ];
+        reply.header.answer_record_count = 2;
+
+        assert!(reply.to_vec().len() > 512);
+
+        let bytes = reply.to_udp_bytes(512);
+        let mut packet = [0u8; 512];
+        packet[..bytes.len()].copy_from_slice(&bytes);
+        let truncated = DnsMessage::new(&packet).unwrap();
+
+        assert!(truncated.header.truncation);
+        assert!(truncated.answers.is_empty());
+        assert_eq!(truncated.questions, reply.questions);
+    }
+
+    #[test]
+    fn test_dns_message_reply_to_multiple_questions() {
+        let query_bytes = [
+            0x00, 0x2a,       // packet_identifier
+            0b00000001, // QR=0, Opcode=0, AA=0, TC=0, RD=1
+            0b00000000, // RA=0, Z=0, RCODE=0
+            0x00, 0x02, // QDCOUNT = 2
+            0x00, 0x00, // ANCOUNT = 0
+            0x00, 0x00, // NSCOUNT = 0
+            0x00, 0x00, // ARCOUNT = 0
+            // Question 1: abc.com A IN
+            0x03, b'a', b'b', b'c', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+            // Question 2: def.com A IN
+            0x03, b'd', b'e', b'f', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        let mut packet = [0u8; 512];
+        packet[..query_bytes.len()].copy_from_slice(&query_bytes);
+
+        let query = DnsMessage::new(&packet).unwrap();
+        assert_eq!(query.questions.len(), 2);
+
+        let reply = query.build_reply_with(|question| {
+            let address = match question.domain_name.label_segments[0].as_str() {
+                "abc" => vec![1, 1, 1, 1],
+                _ => vec![2, 2, 2, 2],
+            };
+
+            vec![DnsAnswerRecord {
+                domain_name: question.domain_name.clone(),
+                record_type: RecordType::A,
+                class: Class::IN,
+                time_to_live: 60,
+                r_data_length: 4,
+                r_data: RData(address),
+            }]
+        });
+
+        // The reply goes through the wire and comes back with matching counts and sections
+        let parsed_reply = DnsMessage::new(&reply.to_bytes()).unwrap();
+
+        assert_eq!(parsed_reply.header.packet_identifier, 0x2a);
+        assert_eq!(parsed_reply.header.question_count, 2);
+        assert_eq!(parsed_reply.header.answer_record_count, 2);
+        assert_eq!(parsed_reply.questions, query.questions);
+        assert_eq!(
+            parsed_reply
+                .answers
+                .iter()
+                .map(|answer| (answer.domain_name.clone(), answer.r_data.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    query.questions[0].domain_name.clone(),
+                    RData(vec![1, 1, 1, 1])
+                ),
+                (
+                    query.questions[1].domain_name.clone(),
+                    RData(vec![2, 2, 2, 2])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dns_message_reply_to_root_name() {
+        let query_bytes = [
+            0x00, 0x07,       // packet_identifier
+            0b00000001, // QR=0, Opcode=0, AA=0, TC=0, RD=1
+            0b00000000, // RA=0, Z=0, RCODE=0
+            0x00, 0x01, // QDCOUNT = 1
+            0x00, 0x00, // ANCOUNT = 0
+            0x00, 0x00, // NSCOUNT = 0
+            0x00, 0x00, // ARCOUNT = 0
+            // Question: root name, A IN
+            0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        let mut packet = [0u8; 512];
+        packet[..query_bytes.len()].copy_from_slice(&query_bytes);
+
+        let query = DnsMessage::new(&packet).unwrap();
+        assert!(query.questions[0].domain_name.is_root());
+
+        // NODATA: the question is echoed with no answer and no error
+        let reply = DnsMessage::new(&query.build_reply().to_bytes()).unwrap();
+        assert_eq!(reply.header.packet_identifier, 0x07);
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert_eq!(reply.questions, query.questions);
+        assert!(reply.answers.is_empty());
+        assert_eq!(reply.header.answer_record_count, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_dns_message_json_roundtrip() {
+        let mut packet = [0u8; 512];
+        let query_bytes = [
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'c',
+            b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        packet[..query_bytes.len()].copy_from_slice(&query_bytes);
+        let message = DnsMessage::new(&packet).unwrap().build_reply();
+
+        let json = serde_json::to_string(&message).unwrap();
+        // RData is rendered as a hex string
+        assert!(json.contains("\"r_data\":\"08080808\""));
+
+        let deserialized: DnsMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, message);
+
+        // Malformed hex is rejected
+        let bad_json = json.replace("08080808", "0808080");
+        assert!(serde_json::from_str::<DnsMessage>(&bad_json).is_err());
+    }
 }
@@ -1,14 +1,29 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::dns::DnsError;
+
+/// Number of compression pointers followed while reading a name before giving up, which protects
+/// against pointers looping back on each other.
+const MAX_COMPRESSION_POINTERS: usize = 64;
+
 /// Represents a DNS domain name in both wire (binary) format and string (dot-separated label) format.
 ///
 /// The `wire_format` field holds the domain as it appears in a DNS packet, using length-prefixed labels.
 /// The `label_segments` field is a vector of label segments as strings, such as `["www", "example", "com"]`.
+/// Bytes that are not printable ASCII are escaped as `\DDD` (as `dig` does), so no information is lost;
+/// use [`DomainName::labels`] to get at the raw label bytes.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DomainName {
     pub wire_format: Vec<u8>,
     pub label_segments: Vec<String>,
 }
 
 impl DomainName {
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet: &[u8]) -> Result<Self, ()> {
         if packet.is_empty() {
             return Err(());
@@ -18,7 +33,8 @@ impl DomainName {
         let mut label_segments: Vec<String> = Vec::new();
 
         let mut current_label_length: Option<usize> = None;
-        let mut current_label = String::new();
+        let mut current_label: Vec<u8> = Vec::new();
+        let mut terminated = false;
 
         for byte in packet.iter() {
             wire_format.push(*byte);
@@ -26,16 +42,17 @@ impl DomainName {
             match current_label_length {
                 None => {
                     if *byte == 0 {
+                        terminated = true;
                         break;
                     }
 
                     current_label_length = Some(usize::from(*byte));
                 }
                 Some(n) => {
-                    current_label.push(char::from(*byte));
+                    current_label.push(*byte);
 
                     if current_label.len() == n {
-                        label_segments.push(current_label.clone());
+                        label_segments.push(escape_label(&current_label));
                         current_label.clear();
                         current_label_length = None;
                     }
@@ -43,16 +60,375 @@ impl DomainName {
             }
         }
 
-        match (current_label_length, wire_format.last()) {
-            (None, Some(0)) => Ok(DomainName {
-                wire_format,
-                label_segments,
-            }),
-            _ => Err(()),
+        // A zero byte ending the input may belong to a label rather than terminate the name
+        if !terminated {
+            return Err(());
+        }
+
+        Ok(DomainName {
+            wire_format,
+            label_segments,
+        })
+    }
+
+    /// Parses a name that may use the compression pointers of RFC 1035 section 4.1.4, which are
+    /// offsets into `packet`, the whole message the name was found in.
+    ///
+    /// The name is returned in its expanded form, without any pointer.
+    pub fn new_in_packet(name: &[u8], packet: &[u8]) -> Result<Self, DnsError> {
+        Self::parse_in_packet(name, packet).map(|(name, _)| name)
+    }
+
+    /// Same as [`DomainName::new_in_packet`], also returning the number of bytes the name takes at
+    /// the start of `name`: up to its terminating zero label, or up to its first pointer.
+    ///
+    /// This is where whatever follows the name starts, which the length of the expanded name
+    /// does not tell once the name is compressed.
+    pub fn parse_in_packet(name: &[u8], packet: &[u8]) -> Result<(Self, usize), DnsError> {
+        let malformed = || DnsError::MalformedPacket;
+        let mut labels: Vec<&[u8]> = Vec::new();
+        let mut current_slice = name;
+        let mut pointers_followed = 0;
+        let mut position = 0;
+        let mut length_in_name = None;
+
+        loop {
+            let label_length = *current_slice.first().ok_or_else(malformed)?;
+
+            match label_length {
+                0 => {
+                    length_in_name.get_or_insert(position + 1);
+                    break;
+                }
+                0xc0..=0xff => {
+                    length_in_name.get_or_insert(position + 2);
+                    pointers_followed += 1;
+                    if pointers_followed > MAX_COMPRESSION_POINTERS {
+                        return Err(malformed());
+                    }
+
+                    let offset = u16::from_be_bytes([
+                        label_length & 0x3f,
+                        *current_slice.get(1).ok_or_else(malformed)?,
+                    ]);
+                    current_slice = packet.get(usize::from(offset)..).ok_or_else(malformed)?;
+                }
+                1..=63 => {
+                    let label = current_slice
+                        .get(1..1 + usize::from(label_length))
+                        .ok_or_else(malformed)?;
+                    labels.push(label);
+                    current_slice = &current_slice[1 + label.len()..];
+                    position += 1 + label.len();
+                }
+                _ => return Err(malformed()),
+            }
+        }
+
+        let name = Self::from_labels(labels);
+        if name.wire_format.len() > 255 {
+            return Err(malformed());
+        }
+
+        Ok((name, length_in_name.unwrap_or_default()))
+    }
+
+    /// Parses the name starting at `offset` in `packet`, the whole message, returning it expanded
+    /// along with the number of bytes it takes at `offset`, see [`DomainName::parse_in_packet`]:
+    /// 2 for a name that is only a pointer.
+    pub fn parse_at(packet: &[u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let name = packet.get(offset..).ok_or(DnsError::MalformedPacket)?;
+        Self::parse_in_packet(name, packet)
+    }
+
+    /// Builds the name under `in-addr.arpa` used for the reverse lookup of an IPv4 address
+    /// (RFC 1035 section 3.5), e.g. `4.3.2.1.in-addr.arpa` for `1.2.3.4`.
+    pub fn from_reverse_ipv4(address: Ipv4Addr) -> Self {
+        let octets: Vec<String> = address
+            .octets()
+            .iter()
+            .rev()
+            .map(|octet| octet.to_string())
+            .collect();
+
+        Self::from_labels(
+            octets
+                .iter()
+                .map(|octet| octet.as_bytes())
+                .chain([&b"in-addr"[..], &b"arpa"[..]]),
+        )
+    }
+
+    /// Builds the name under `ip6.arpa` used for the reverse lookup of an IPv6 address
+    /// (RFC 3596 section 2.5): one label per nibble, least significant first.
+    pub fn from_reverse_ipv6(address: Ipv6Addr) -> Self {
+        let nibbles: Vec<String> = address
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|octet| [octet & 0x0f, octet >> 4])
+            .map(|nibble| format!("{:x}", nibble))
+            .collect();
+
+        Self::from_labels(
+            nibbles
+                .iter()
+                .map(|nibble| nibble.as_bytes())
+                .chain([&b"ip6"[..], &b"arpa"[..]]),
+        )
+    }
+
+    /// Builds a name out of labels known to be 1 to 63 bytes long.
+    fn from_labels<'a, I>(labels: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut wire_format: Vec<u8> = Vec::new();
+        let mut label_segments: Vec<String> = Vec::new();
+
+        for label in labels {
+            wire_format.push(label.len() as u8);
+            wire_format.extend_from_slice(label);
+            label_segments.push(escape_label(label));
+        }
+        wire_format.push(0);
+
+        DomainName {
+            wire_format,
+            label_segments,
+        }
+    }
+
+    /// Iterates over the raw bytes of each label, exactly as they appear on the wire.
+    pub fn labels(&self) -> impl Iterator<Item = &[u8]> {
+        let mut position = 0;
+
+        std::iter::from_fn(move || {
+            let label_length = usize::from(*self.wire_format.get(position)?);
+            if label_length == 0 {
+                return None;
+            }
+
+            let label = self
+                .wire_format
+                .get(position + 1..position + 1 + label_length)?;
+            position += label_length + 1;
+
+            Some(label)
+        })
+    }
+
+    /// Renders the name as dotted text with a trailing dot, `.` being the root name.
+    pub fn to_text(&self) -> String {
+        format!("{}.", self.label_segments.join("."))
+    }
+
+    /// Number of bytes the name takes uncompressed, up to and including its terminating zero: the
+    /// bytes [`DomainName::new`] consumes, whatever follows them. A compressed name may take fewer
+    /// bytes in its message, see [`DomainName::parse_in_packet`].
+    pub fn encoded_len(&self) -> usize {
+        self.wire_format.len()
+    }
+
+    /// Number of labels of the name, not counting the terminating zero-length label: 0 for the
+    /// root name, 3 for `www.example.com`.
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Returns the name with every ASCII letter lowercased, the canonical form under which names
+    /// compare equal regardless of case. Other bytes are left untouched (RFC 4343 section 3).
+    pub fn to_ascii_lowercase(&self) -> DomainName {
+        DomainName {
+            wire_format: self.wire_format.to_ascii_lowercase(),
+            // Escapes only use digits and punctuation, so lowercasing the text leaves them intact
+            label_segments: self
+                .label_segments
+                .iter()
+                .map(|segment| segment.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// The root name, made of the terminating zero-length label alone.
+    pub fn root() -> Self {
+        DomainName {
+            wire_format: vec![0],
+            label_segments: Vec::new(),
+        }
+    }
+
+    /// Whether this is the root name, made of the terminating zero-length label alone.
+    pub fn is_root(&self) -> bool {
+        self.wire_format == [0]
+    }
+
+    /// Whether this name is `parent` or lies below it, comparing whole labels regardless of case:
+    /// `www.example.com` is a subdomain of `example.com`, but not of `ample.com`.
+    pub fn is_subdomain_of(&self, parent: &DomainName) -> bool {
+        let labels: Vec<&[u8]> = self.labels().collect();
+        let parent_labels: Vec<&[u8]> = parent.labels().collect();
+
+        labels.len() >= parent_labels.len()
+            && labels
+                .iter()
+                .rev()
+                .zip(parent_labels.iter().rev())
+                .all(|(label, parent_label)| label.eq_ignore_ascii_case(parent_label))
+    }
+
+    /// Appends `suffix` to this name, e.g. to make a relative name absolute under an origin.
+    ///
+    /// Fails when the resulting name is longer than 255 bytes.
+    pub fn append(&self, suffix: &DomainName) -> Result<DomainName, DnsError> {
+        let name = DomainName::from_labels(self.labels().chain(suffix.labels()));
+
+        if name.wire_format.len() > 255 {
+            return Err(DnsError::InvalidDomainName(name.to_text()));
+        }
+
+        Ok(name)
+    }
+
+    /// Checks that every label follows the LDH (letter-digit-hyphen) host name rule of RFC 952
+    /// and RFC 1123: 1 to 63 letters, digits or hyphens, neither starting nor ending with a hyphen.
+    ///
+    /// This is stricter than DNS itself, which allows any byte in a label (e.g. `_sip._tcp`).
+    pub fn is_valid_hostname(&self) -> bool {
+        self.labels().all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .iter()
+                    .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'-')
+                && label.first() != Some(&b'-')
+                && label.last() != Some(&b'-')
+        })
+    }
+}
+
+/// Writes names into a message, replacing the suffixes already written by pointers to them
+/// (RFC 1035 section 4.1.4).
+///
+/// Suffixes are matched byte for byte, so the case of every name is kept as is.
+#[derive(Debug, Default)]
+pub(crate) struct NameCompressor {
+    /// Offsets in the message of the suffixes written so far, keyed by their wire format.
+    offsets: HashMap<Vec<u8>, u16>,
+}
+
+impl NameCompressor {
+    /// Highest offset a compression pointer can hold, its two upper bits marking it as a pointer.
+    const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+    pub(crate) fn new() -> Self {
+        NameCompressor::default()
+    }
+
+    /// Appends `name` to `message`, which holds the message written so far from its first byte.
+    pub(crate) fn write(&mut self, name: &DomainName, message: &mut Vec<u8>) {
+        let mut position = 0;
+
+        for label in name.labels() {
+            let suffix = &name.wire_format[position..];
+            if let Some(offset) = self.offsets.get(suffix) {
+                message.extend_from_slice(&(0xC000 | offset).to_be_bytes());
+                return;
+            }
+
+            if message.len() <= Self::MAX_POINTER_OFFSET {
+                self.offsets.insert(suffix.to_vec(), message.len() as u16);
+            }
+            message.push(label.len() as u8);
+            message.extend_from_slice(label);
+            position += label.len() + 1;
+        }
+
+        message.push(0);
+    }
+}
+
+/// Parse a domain name from its textual form, such as `www.example.com.`
+///
+/// The trailing dot is optional and `.` alone is the root name. The `\DDD` and `\X` escapes
+/// produced when rendering the labels are understood, so names survive a round-trip through text.
+impl std::str::FromStr for DomainName {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut wire_format: Vec<u8> = Vec::new();
+        let mut label: Vec<u8> = Vec::new();
+        let mut bytes = text.bytes();
+
+        if text != "." {
+            while let Some(byte) = bytes.next() {
+                match byte {
+                    b'.' => {
+                        push_label(&mut wire_format, &label)?;
+                        label.clear();
+                    }
+                    b'\\' => label.push(unescape(&mut bytes)?),
+                    _ => label.push(byte),
+                }
+            }
+
+            if !label.is_empty() {
+                push_label(&mut wire_format, &label)?;
+            }
+        }
+        wire_format.push(0);
+
+        if wire_format.len() > 255 {
+            return Err(());
         }
+
+        DomainName::new(&wire_format)
     }
 }
 
+fn push_label(wire_format: &mut Vec<u8>, label: &[u8]) -> Result<(), ()> {
+    if !(1..=63).contains(&label.len()) {
+        return Err(());
+    }
+
+    wire_format.push(label.len() as u8);
+    wire_format.extend_from_slice(label);
+
+    Ok(())
+}
+
+/// Reads the byte escaped after a backslash, either a `DDD` decimal value or a single character.
+fn unescape(bytes: &mut std::str::Bytes) -> Result<u8, ()> {
+    let first = bytes.next().ok_or(())?;
+    if !first.is_ascii_digit() {
+        return Ok(first);
+    }
+
+    let digits = [first, bytes.next().ok_or(())?, bytes.next().ok_or(())?];
+    std::str::from_utf8(&digits)
+        .ok()
+        .and_then(|digits| digits.parse::<u8>().ok())
+        .ok_or(())
+}
+
+/// Renders a raw label as text, escaping dots, backslashes and any byte outside printable ASCII
+/// using the `\DDD` decimal notation from RFC 1035 section 5.1.
+fn escape_label(label: &[u8]) -> String {
+    let mut escaped = String::with_capacity(label.len());
+
+    for byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(char::from(*byte));
+            }
+            0x21..=0x7e => escaped.push(char::from(*byte)),
+            _ => escaped.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+
+    escaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,5 +471,290 @@ mod tests {
             DomainName::new(google_dot_com).map(|domain_name| { domain_name.wire_format }),
             Ok(google_dot_com.to_vec())
         );
+        assert_eq!(
+            DomainName::new(&[google_dot_com, &[0x06, 0x67, 0x6f]].concat())
+                .map(|domain_name| domain_name.encoded_len()),
+            Ok(google_dot_com.len())
+        );
+    }
+
+    #[test]
+    fn domain_name_root() {
+        let root = DomainName::new(&[0x00]).unwrap();
+
+        assert_eq!(root.wire_format, vec![0x00]);
+        assert!(root.label_segments.is_empty());
+        assert_eq!(root.to_text(), ".");
+        assert_eq!(".".parse(), Ok(root.clone()));
+        assert_eq!(
+            DomainName::new_in_packet(&[0x00], &[0x00]),
+            Ok(root.clone())
+        );
+
+        let mut message = Vec::new();
+        NameCompressor::new().write(&root, &mut message);
+        assert_eq!(message, [0x00]);
+
+        // A label holding a zero byte is not the terminating label
+        assert_eq!(DomainName::new(&[0x01, 0x00]), Err(()));
+        assert_eq!(
+            DomainName::new(&[0x01, 0x00, 0x00]).map(|name| name.label_count()),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn domain_name_non_ascii_label() {
+        // "caf\xc3\xa9" followed by "com", i.e. the UTF-8 bytes of "café.com"
+        let packet: &[u8] = &[
+            0x05, 0x63, 0x61, 0x66, 0xc3, 0xa9, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+        ];
+
+        let domain_name = DomainName::new(packet).unwrap();
+
+        // The raw bytes must be kept untouched rather than mapped to Latin-1 codepoints
+        assert_eq!(
+            domain_name.labels().collect::<Vec<&[u8]>>(),
+            vec![&[0x63, 0x61, 0x66, 0xc3, 0xa9][..], &b"com"[..]]
+        );
+        assert_eq!(domain_name.wire_format, packet.to_vec());
+
+        // The textual form escapes the non-ASCII bytes instead of producing mojibake
+        assert_eq!(
+            domain_name.label_segments,
+            vec![String::from("caf\\195\\169"), String::from("com")]
+        );
+    }
+
+    #[test]
+    fn domain_name_is_valid_hostname() {
+        let www_example_com: &[u8] = &[
+            0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c',
+            b'o', b'm', 0x00,
+        ];
+        assert!(DomainName::new(www_example_com)
+            .unwrap()
+            .is_valid_hostname());
+
+        // Hyphens and digits are allowed inside a label
+        let my_host_1_com: &[u8] = &[
+            0x09, b'm', b'y', b'-', b'h', b'o', b's', b't', b'-', b'1', 0x03, b'c', b'o', b'm',
+            0x00,
+        ];
+        assert!(DomainName::new(my_host_1_com).unwrap().is_valid_hostname());
+
+        // Underscores are valid in DNS names but not in host names
+        let underscore_label: &[u8] = &[0x04, b'_', b's', b'i', b'p', 0x03, b'c', b'o', b'm', 0x00];
+        assert!(!DomainName::new(underscore_label)
+            .unwrap()
+            .is_valid_hostname());
+
+        // Labels cannot start (or end) with a hyphen
+        let leading_hyphen: &[u8] = &[0x04, b'-', b'w', b'w', b'w', 0x03, b'c', b'o', b'm', 0x00];
+        assert!(!DomainName::new(leading_hyphen).unwrap().is_valid_hostname());
+        let trailing_hyphen: &[u8] = &[0x04, b'w', b'w', b'w', b'-', 0x03, b'c', b'o', b'm', 0x00];
+        assert!(!DomainName::new(trailing_hyphen)
+            .unwrap()
+            .is_valid_hostname());
+    }
+
+    #[test]
+    fn domain_name_from_str() {
+        let www_example_com: DomainName = "www.example.com.".parse().unwrap();
+        assert_eq!(
+            www_example_com.wire_format,
+            vec![
+                0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c',
+                b'o', b'm', 0x00
+            ]
+        );
+        assert_eq!(www_example_com.to_text(), "www.example.com.");
+
+        // The trailing dot is optional
+        assert_eq!("www.example.com".parse(), Ok(www_example_com));
+        assert_eq!(
+            ".".parse::<DomainName>().map(|name| name.is_root()),
+            Ok(true)
+        );
+
+        // Escaped bytes come back from the textual form
+        let packet: &[u8] = &[
+            0x05, b'c', b'a', b'f', 0xc3, 0xa9, 0x03, b'c', b'o', b'm', 0x00,
+        ];
+        let cafe_com = DomainName::new(packet).unwrap();
+        assert_eq!(cafe_com.to_text().parse(), Ok(cafe_com));
+
+        // Empty and overlong labels are rejected
+        assert_eq!("www..com".parse::<DomainName>(), Err(()));
+        assert_eq!(
+            format!("{}.com", "a".repeat(64)).parse::<DomainName>(),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn domain_name_reverse_lookup_names() {
+        assert_eq!(
+            DomainName::from_reverse_ipv4(Ipv4Addr::new(1, 2, 3, 4)).to_text(),
+            "4.3.2.1.in-addr.arpa."
+        );
+        assert_eq!(
+            DomainName::from_reverse_ipv4(Ipv4Addr::new(192, 168, 0, 10)),
+            "10.0.168.192.in-addr.arpa".parse().unwrap()
+        );
+
+        // Example from RFC 3596 section 2.5
+        assert_eq!(
+            DomainName::from_reverse_ipv6("4321:0:1:2:3:4:567:89ab".parse().unwrap()).to_text(),
+            "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa."
+        );
+    }
+
+    #[test]
+    fn domain_name_label_count() {
+        let name = |text: &str| text.parse::<DomainName>().unwrap();
+
+        assert_eq!(name(".").label_count(), 0);
+        assert!(name(".").is_root());
+        assert_eq!(name("com").label_count(), 1);
+        assert!(!name("com").is_root());
+        assert_eq!(name("www.example.com").label_count(), 3);
+        assert!(!name("www.example.com").is_root());
+    }
+
+    #[test]
+    fn domain_name_to_ascii_lowercase() {
+        let name: DomainName = "WWW.Example.COM".parse().unwrap();
+        let lowercase = name.to_ascii_lowercase();
+
+        assert_eq!(lowercase, "www.example.com".parse().unwrap());
+        assert_eq!(lowercase.wire_format, name.wire_format.to_ascii_lowercase());
+        assert_eq!(lowercase.to_text(), "www.example.com.");
+
+        // Bytes outside of ASCII are kept as is
+        let non_ascii = DomainName::new(&[0x04, b'C', 0xc3, 0x89, b'A', 0x00]).unwrap();
+        assert_eq!(
+            non_ascii.to_ascii_lowercase(),
+            DomainName::new(&[0x04, b'c', 0xc3, 0x89, b'a', 0x00]).unwrap()
+        );
+    }
+
+    #[test]
+    fn domain_name_is_subdomain_of() {
+        let name = |text: &str| text.parse::<DomainName>().unwrap();
+        let www_example_com = name("www.example.com");
+
+        assert!(www_example_com.is_subdomain_of(&name("example.com")));
+        assert!(www_example_com.is_subdomain_of(&name("EXAMPLE.com.")));
+        assert!(www_example_com.is_subdomain_of(&www_example_com));
+        assert!(www_example_com.is_subdomain_of(&name(".")));
+        assert!(!www_example_com.is_subdomain_of(&name("ample.com")));
+        assert!(!www_example_com.is_subdomain_of(&name("example.org")));
+        assert!(!name("example.com").is_subdomain_of(&www_example_com));
+    }
+
+    #[test]
+    fn domain_name_append() {
+        let name = |text: &str| text.parse::<DomainName>().unwrap();
+
+        assert_eq!(
+            name("www").append(&name("example.com")),
+            Ok(name("www.example.com"))
+        );
+        assert_eq!(name("www").append(&name(".")), Ok(name("www")));
+
+        let long_label = "a".repeat(63);
+        let long_name = name(&[long_label.as_str(); 3].join("."));
+        assert!(matches!(
+            long_name.append(&long_name),
+            Err(DnsError::InvalidDomainName(_))
+        ));
+    }
+
+    #[test]
+    fn domain_name_compressor() {
+        let mut compressor = NameCompressor::new();
+        let mut message = vec![0xAA, 0xBB];
+
+        compressor.write(&"www.example.com".parse().unwrap(), &mut message);
+        compressor.write(&"mail.example.com".parse().unwrap(), &mut message);
+        compressor.write(&"www.example.com".parse().unwrap(), &mut message);
+        compressor.write(&".".parse().unwrap(), &mut message);
+
+        assert_eq!(
+            message[19..],
+            [
+                0x04, b'm', b'a', b'i', b'l', // "mail"
+                0xC0, 0x06, // pointer to "example.com" at offset 6
+                0xC0, 0x02, // pointer to "www.example.com" at offset 2
+                0x00, // root name
+            ]
+        );
+        assert_eq!(
+            DomainName::new_in_packet(&message[19..], &message).map(|name| name.to_text()),
+            Ok("mail.example.com.".to_string())
+        );
+    }
+
+    #[test]
+    fn domain_name_new_in_packet() {
+        // "example.com" at offset 2, then "www" followed by a pointer to it
+        let packet: &[u8] = &[
+            0xff, 0xff, 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm',
+            0x00, 0x03, b'w', b'w', b'w', 0xc0, 0x02,
+        ];
+
+        assert_eq!(
+            DomainName::new_in_packet(&packet[15..], packet),
+            Ok("www.example.com".parse().unwrap())
+        );
+        assert_eq!(
+            DomainName::new_in_packet(&packet[2..], packet),
+            Ok("example.com".parse().unwrap())
+        );
+
+        // The name ends at its first pointer
+        assert_eq!(
+            DomainName::parse_in_packet(&packet[15..], packet).map(|(_, length)| length),
+            Ok(6)
+        );
+        assert_eq!(
+            DomainName::parse_in_packet(&packet[19..], packet).map(|(_, length)| length),
+            Ok(2)
+        );
+        assert_eq!(
+            DomainName::parse_in_packet(&packet[2..], packet).map(|(_, length)| length),
+            Ok(13)
+        );
+        // Label lengths above 63 are reserved
+        assert_eq!(
+            DomainName::parse_in_packet(&[0x40], packet),
+            Err(DnsError::MalformedPacket)
+        );
+
+        // Names can also be read at their offset in the packet
+        assert_eq!(
+            DomainName::parse_at(packet, 2),
+            Ok(("example.com".parse().unwrap(), 13))
+        );
+        let pointer_only = [packet, &[0xc0, 0x0f]].concat();
+        assert_eq!(
+            DomainName::parse_at(&pointer_only, 21),
+            Ok(("www.example.com".parse().unwrap(), 2))
+        );
+        assert_eq!(
+            DomainName::parse_at(packet, packet.len()),
+            Err(DnsError::MalformedPacket)
+        );
+
+        // Pointers outside the packet or looping on themselves are rejected
+        assert_eq!(
+            DomainName::new_in_packet(&[0xc0, 0x40], packet),
+            Err(DnsError::MalformedPacket)
+        );
+        assert_eq!(
+            DomainName::new_in_packet(&[0xc0, 0x00], &[0xc0, 0x00]),
+            Err(DnsError::MalformedPacket)
+        );
     }
 }
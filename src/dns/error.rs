@@ -0,0 +1,86 @@
+use thiserror::Error;
+
+use crate::dns::{RecordType, ResponseCode};
+
+/// Errors raised while exchanging DNS messages with other servers.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DnsError {
+    #[error("malformed DNS packet")]
+    MalformedPacket,
+    #[error("DNS packet too short for the records it declares")]
+    PacketTooShort,
+    #[error("I/O error: {0}")]
+    Io(std::io::ErrorKind),
+    #[error("upstream server answered with {0:?}")]
+    UpstreamFailure(ResponseCode),
+    #[error("no name server could be found to continue the resolution")]
+    NoNameServers,
+    #[error("resolution did not complete within {0} referrals")]
+    TooManyReferrals(usize),
+    #[error("no response received in time")]
+    Timeout,
+    #[error("no upstream server gave a usable response")]
+    AllUpstreamsFailed,
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("invalid domain name `{0}`")]
+    InvalidDomainName(String),
+    #[error("invalid hexadecimal digits `{0}`")]
+    InvalidHex(String),
+    #[error("character string of {0} bytes does not fit its length octet")]
+    CharacterStringTooLong(usize),
+    #[error("invalid CAA tag `{0}`")]
+    InvalidCaaTag(String),
+    #[error("header declares {declared} entries in the {section} section, which holds {actual}")]
+    SectionCountMismatch {
+        section: &'static str,
+        declared: usize,
+        actual: usize,
+    },
+    #[error("opcode {0} does not fit in 4 bits")]
+    OpCodeOutOfRange(u8),
+    #[error("{length} bytes of RDATA do not fit a {record_type} record")]
+    RDataLengthMismatch {
+        record_type: RecordType,
+        length: usize,
+    },
+}
+
+impl DnsError {
+    /// Response code telling a client why its query failed with this error: FORMERR when the
+    /// query itself could not be understood (RFC 1035 section 4.1.1), SERVFAIL when the server
+    /// failed to answer it.
+    pub fn response_code(&self) -> ResponseCode {
+        match self {
+            DnsError::MalformedPacket
+            | DnsError::PacketTooShort
+            | DnsError::InvalidDomainName(_)
+            | DnsError::InvalidHex(_)
+            | DnsError::CharacterStringTooLong(_)
+            | DnsError::InvalidCaaTag(_)
+            | DnsError::SectionCountMismatch { .. }
+            | DnsError::OpCodeOutOfRange(_)
+            | DnsError::RDataLengthMismatch { .. } => ResponseCode::FormatError,
+            DnsError::Io(_)
+            | DnsError::UpstreamFailure(_)
+            | DnsError::NoNameServers
+            | DnsError::TooManyReferrals(_)
+            | DnsError::Timeout
+            | DnsError::AllUpstreamsFailed
+            | DnsError::Tls(_) => ResponseCode::ServerFailure,
+        }
+    }
+}
+
+impl From<std::io::Error> for DnsError {
+    fn from(error: std::io::Error) -> Self {
+        DnsError::Io(error.kind())
+    }
+}
+
+/// Errors raised while loading records from a zone file.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ZoneError {
+    #[error("invalid record on line {0}")]
+    InvalidRecord(usize),
+}
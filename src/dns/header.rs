@@ -1,5 +1,21 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use crate::dns::{answer_record::MIN_RECORD_SIZE, question::MIN_QUESTION_SIZE};
+
+/// Returns an unpredictable packet identifier, so that off-path attackers cannot guess the
+/// identifier a reply must carry to be accepted (RFC 5452 section 4.3).
+///
+/// Every `RandomState` is seeded with fresh random keys, which is all the randomness needed here.
+pub fn random_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
 /// Query/Response indicator for DNS packets
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QRIndicator {
     Question = 0,
     Reply = 1,
@@ -16,26 +32,83 @@ impl From<u8> for QRIndicator {
 
 /// DNS response codes as defined in RFC 1035 section 4.1.1
 ///
-/// These codes indicate the outcome of a DNS query.
+/// These codes indicate the outcome of a DNS query. `Unknown` keeps the raw 4-bit value of the
+/// other codes, such as those of RFC 2136 or the lower bits of an extended code (RFC 6891).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ResponseCode {
-    NoError = 0,
-    FormatError = 1,
-    ServerFailure = 2,
-    NameError = 3,
-    NotImplemented = 4,
-    Refused = 5,
+    NoError,
+    FormatError,
+    ServerFailure,
+    NameError,
+    NotImplemented,
+    Refused,
+    Unknown(u8),
 }
 
 impl From<u8> for ResponseCode {
     fn from(byte: u8) -> Self {
         match byte {
             0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
             2 => ResponseCode::ServerFailure,
             3 => ResponseCode::NameError,
             4 => ResponseCode::NotImplemented,
             5 => ResponseCode::Refused,
-            _ => ResponseCode::FormatError,
+            _ => ResponseCode::Unknown(byte),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(response_code: ResponseCode) -> Self {
+        match response_code {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::Unknown(value) => value,
+        }
+    }
+}
+
+/// Kinds of DNS messages, as carried in the OPCODE header field (RFC 1035 section 4.1.1,
+/// RFC 1996 for NOTIFY and RFC 2136 for UPDATE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpCode {
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Unknown(u8),
+}
+
+impl From<u8> for OpCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => OpCode::Query,
+            1 => OpCode::IQuery,
+            2 => OpCode::Status,
+            4 => OpCode::Notify,
+            5 => OpCode::Update,
+            _ => OpCode::Unknown(value),
+        }
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(operation_code: OpCode) -> Self {
+        match operation_code {
+            OpCode::Query => 0,
+            OpCode::IQuery => 1,
+            OpCode::Status => 2,
+            OpCode::Notify => 4,
+            OpCode::Update => 5,
+            OpCode::Unknown(value) => value,
         }
     }
 }
@@ -45,15 +118,21 @@ impl From<u8> for ResponseCode {
 /// Represents the fixed 12-byte header that appears at the start of every DNS message.
 /// See RFC 1035 Section 4.1.1 for the full specification.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsHeader {
     pub packet_identifier: u16,
     pub query_response_indicator: QRIndicator,
-    pub operation_code: u8,
+    pub operation_code: OpCode,
     pub authoritative_answer: bool,
     pub truncation: bool,
     pub recursion_desired: bool,
     pub recursion_available: bool,
+    /// The Z bit, which must be zero (RFC 1035 section 4.1.1).
     pub reserved: u8,
+    /// Whether the records of the response were validated with DNSSEC (RFC 4035 section 3.2.3).
+    pub authentic_data: bool,
+    /// Whether the client asks for DNSSEC validation to be skipped (RFC 4035 section 3.2.2).
+    pub checking_disabled: bool,
     pub response_code: ResponseCode,
     pub question_count: usize,
     pub answer_record_count: usize,
@@ -62,6 +141,7 @@ pub struct DnsHeader {
 }
 
 impl DnsHeader {
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet_slice: &[u8]) -> Result<Self, ()> {
         packet_slice
             .get(..12)
@@ -70,20 +150,45 @@ impl DnsHeader {
             .ok_or(())
     }
 
+    /// Whether the QR bit marks this header as the one of a query.
+    pub fn is_query(&self) -> bool {
+        self.query_response_indicator == QRIndicator::Question
+    }
+
+    /// Whether the QR bit marks this header as the one of a response.
+    pub fn is_response(&self) -> bool {
+        self.query_response_indicator == QRIndicator::Reply
+    }
+
+    /// Number of bytes the message needs at the very least to hold the sections this header
+    /// declares, every question and record having the root as name and no RDATA.
+    pub fn min_message_size(&self) -> usize {
+        let records = self
+            .answer_record_count
+            .saturating_add(self.authority_record_count)
+            .saturating_add(self.additional_record_count);
+
+        12usize
+            .saturating_add(self.question_count.saturating_mul(MIN_QUESTION_SIZE))
+            .saturating_add(records.saturating_mul(MIN_RECORD_SIZE))
+    }
+
     /// Encodes the DNS header flags into a 2-byte array
     ///
     /// The flags are packed according to RFC 1035:
     /// - Byte 1: QR(1) | Opcode(4) | AA(1) | TC(1) | RD(1)
-    /// - Byte 2: RA(1) | Z(3) | RCODE(4)
+    /// - Byte 2: RA(1) | Z(1) | AD(1) | CD(1) | RCODE(4), AD and CD coming from RFC 2535
     pub fn get_flags_bytes(&self) -> [u8; 2] {
         let flags_first_byte = ((self.query_response_indicator as u8) << 7)
-            | (self.operation_code << 3)
+            | ((u8::from(self.operation_code) & 0b1111) << 3)
             | ((self.authoritative_answer as u8) << 2)
             | ((self.truncation as u8) << 1)
             | (self.recursion_desired as u8);
         let flags_second_byte = ((self.recursion_available as u8) << 7)
-            | (self.reserved << 4)
-            | (self.response_code as u8);
+            | ((self.reserved & 0b1) << 6)
+            | ((self.authentic_data as u8) << 5)
+            | ((self.checking_disabled as u8) << 4)
+            | (u8::from(self.response_code) & 0b00001111);
 
         [flags_first_byte, flags_second_byte]
     }
@@ -94,6 +199,123 @@ impl DnsHeader {
     pub fn to_bytes(&self) -> [u8; 12] {
         self.into()
     }
+
+    /// Appends the 12 bytes of the header to `out`.
+    pub fn write_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_bytes());
+    }
+}
+
+/// Builder for [`DnsHeader`], starting from a plain query header: QR=Question, opcode 0,
+/// no flags set, `NoError` and all section counts at 0.
+#[derive(Debug, Clone)]
+pub struct DnsHeaderBuilder {
+    header: DnsHeader,
+}
+
+impl DnsHeaderBuilder {
+    pub fn new() -> Self {
+        DnsHeaderBuilder {
+            header: DnsHeader {
+                packet_identifier: 0,
+                query_response_indicator: QRIndicator::Question,
+                operation_code: OpCode::Query,
+                authoritative_answer: false,
+                truncation: false,
+                recursion_desired: false,
+                recursion_available: false,
+                reserved: 0,
+                authentic_data: false,
+                checking_disabled: false,
+                response_code: ResponseCode::NoError,
+                question_count: 0,
+                answer_record_count: 0,
+                authority_record_count: 0,
+                additional_record_count: 0,
+            },
+        }
+    }
+
+    pub fn id(mut self, packet_identifier: u16) -> Self {
+        self.header.packet_identifier = packet_identifier;
+        self
+    }
+
+    /// Marks the header as belonging to a reply (QR=1).
+    pub fn response(mut self) -> Self {
+        self.header.query_response_indicator = QRIndicator::Reply;
+        self
+    }
+
+    pub fn operation_code(mut self, operation_code: OpCode) -> Self {
+        self.header.operation_code = operation_code;
+        self
+    }
+
+    pub fn authoritative_answer(mut self, authoritative_answer: bool) -> Self {
+        self.header.authoritative_answer = authoritative_answer;
+        self
+    }
+
+    pub fn truncation(mut self, truncation: bool) -> Self {
+        self.header.truncation = truncation;
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn recursion_available(mut self, recursion_available: bool) -> Self {
+        self.header.recursion_available = recursion_available;
+        self
+    }
+
+    pub fn authentic_data(mut self, authentic_data: bool) -> Self {
+        self.header.authentic_data = authentic_data;
+        self
+    }
+
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.header.checking_disabled = checking_disabled;
+        self
+    }
+
+    pub fn response_code(mut self, response_code: ResponseCode) -> Self {
+        self.header.response_code = response_code;
+        self
+    }
+
+    pub fn questions(mut self, count: u16) -> Self {
+        self.header.question_count = usize::from(count);
+        self
+    }
+
+    pub fn answers(mut self, count: u16) -> Self {
+        self.header.answer_record_count = usize::from(count);
+        self
+    }
+
+    pub fn authorities(mut self, count: u16) -> Self {
+        self.header.authority_record_count = usize::from(count);
+        self
+    }
+
+    pub fn additionals(mut self, count: u16) -> Self {
+        self.header.additional_record_count = usize::from(count);
+        self
+    }
+
+    pub fn build(self) -> DnsHeader {
+        self.header
+    }
+}
+
+impl Default for DnsHeaderBuilder {
+    fn default() -> Self {
+        DnsHeaderBuilder::new()
+    }
 }
 
 /// Deserialize a DNS header from a 12-byte array
@@ -102,12 +324,14 @@ impl From<&[u8; 12]> for DnsHeader {
         Self {
             packet_identifier: u16::from_be_bytes([buf[0], buf[1]]),
             query_response_indicator: QRIndicator::from(buf[2] & 0b10000000),
-            operation_code: (buf[2] & 0b01111000) >> 3,
+            operation_code: OpCode::from((buf[2] & 0b01111000) >> 3),
             authoritative_answer: (buf[2] & 0b00000100) != 0,
             truncation: (buf[2] & 0b00000010) != 0,
             recursion_desired: (buf[2] & 0b00000001) != 0,
             recursion_available: (buf[3] & 0b10000000) != 0,
-            reserved: (buf[3] & 0b01110000) >> 4,
+            reserved: (buf[3] & 0b01000000) >> 6,
+            authentic_data: (buf[3] & 0b00100000) != 0,
+            checking_disabled: (buf[3] & 0b00010000) != 0,
             response_code: ResponseCode::from(buf[3] & 0b00001111),
             question_count: u16::from_be_bytes([buf[4], buf[5]]).into(),
             answer_record_count: u16::from_be_bytes([buf[6], buf[7]]).into(),
@@ -163,12 +387,33 @@ impl From<DnsHeader> for [u8; 12] {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_header_min_message_size() {
+        let header = DnsHeaderBuilder::new().questions(1).answers(2).build();
+        assert_eq!(header.min_message_size(), 12 + 5 + 2 * 11);
+
+        let absurd =
+            DnsHeader::new(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap();
+        assert_eq!(absurd.min_message_size(), 12 + 65535 * 5 + 3 * 65535 * 11);
+    }
+
     #[test]
     fn test_qr_indicator_conversion() {
         assert_eq!(QRIndicator::from(0b00000000), QRIndicator::Question);
         assert_eq!(QRIndicator::from(0b10000000), QRIndicator::Reply);
     }
 
+    #[test]
+    fn test_qr_predicates() {
+        let query = DnsHeaderBuilder::new().build();
+        assert!(query.is_query());
+        assert!(!query.is_response());
+
+        let reply = DnsHeaderBuilder::new().response().build();
+        assert!(!reply.is_query());
+        assert!(reply.is_response());
+    }
+
     #[test]
     fn test_response_code_conversion() {
         assert_eq!(ResponseCode::from(0b0000), ResponseCode::NoError);
@@ -178,8 +423,10 @@ mod tests {
         assert_eq!(ResponseCode::from(0b0100), ResponseCode::NotImplemented);
         assert_eq!(ResponseCode::from(0b0101), ResponseCode::Refused);
 
-        // Test case for when pattern is greater than 5
-        assert_eq!(ResponseCode::from(0b1000), ResponseCode::FormatError);
+        // Other codes are kept as they are
+        assert_eq!(ResponseCode::from(0b1000), ResponseCode::Unknown(8));
+        assert_eq!(u8::from(ResponseCode::Unknown(8)), 0b1000);
+        assert_eq!(u8::from(ResponseCode::Refused), 0b0101);
     }
 
     #[test]
@@ -187,12 +434,14 @@ mod tests {
         let original = DnsHeader {
             packet_identifier: 1234,
             query_response_indicator: QRIndicator::Reply,
-            operation_code: 0,
+            operation_code: OpCode::Query,
             authoritative_answer: true,
             truncation: false,
             recursion_desired: true,
             recursion_available: false,
             reserved: 0,
+            authentic_data: false,
+            checking_disabled: false,
             response_code: ResponseCode::NoError,
             question_count: 1,
             answer_record_count: 0,
@@ -200,12 +449,109 @@ mod tests {
             additional_record_count: 0,
         };
 
-        let bytes: [u8; 12] = (&original).to_bytes();
+        let bytes: [u8; 12] = original.to_bytes();
         let deserialized = DnsHeader::from(&bytes);
 
         assert_eq!(original, deserialized);
     }
 
+    #[test]
+    fn test_header_write_into() {
+        let header = DnsHeaderBuilder::new().id(0x1234).questions(1).build();
+        let mut out = vec![0xff];
+        header.write_into(&mut out);
+        assert_eq!(out[0], 0xff);
+        assert_eq!(out[1..], header.to_bytes());
+    }
+
+    #[test]
+    fn test_header_dnssec_flags_roundtrip() {
+        let header = DnsHeaderBuilder::new()
+            .response()
+            .authentic_data(true)
+            .checking_disabled(true)
+            .build();
+
+        let bytes = header.to_bytes();
+        assert_eq!(bytes[3], 0b00110000);
+        assert_eq!(DnsHeader::from(&bytes), header);
+
+        // The Z bit is kept apart from them
+        let z_only = DnsHeader::from(&[0, 0, 0, 0b01000000, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(z_only.reserved, 1);
+        assert!(!z_only.authentic_data);
+        assert!(!z_only.checking_disabled);
+        assert_eq!(z_only.to_bytes()[3], 0b01000000);
+    }
+
+    #[test]
+    fn test_operation_code_roundtrip() {
+        let operation_codes = [
+            OpCode::Query,
+            OpCode::IQuery,
+            OpCode::Status,
+            OpCode::Unknown(3),
+            OpCode::Notify,
+            OpCode::Update,
+            OpCode::Unknown(15),
+        ];
+
+        for operation_code in operation_codes {
+            let header = DnsHeaderBuilder::new()
+                .operation_code(operation_code)
+                .build();
+            let flags = header.get_flags_bytes();
+
+            assert_eq!(OpCode::from((flags[0] & 0b01111000) >> 3), operation_code);
+            assert_eq!(DnsHeader::from(&header.to_bytes()), header);
+        }
+    }
+
+    #[test]
+    fn test_random_id() {
+        let ids: std::collections::HashSet<u16> = (0..100).map(|_| random_id()).collect();
+
+        // 100 draws out of 65536 values are very unlikely to collide more than a couple of times
+        assert!(ids.len() > 95);
+    }
+
+    #[test]
+    fn test_header_builder() {
+        let header = DnsHeaderBuilder::new()
+            .id(0xBEEF)
+            .recursion_desired(true)
+            .questions(1)
+            .build();
+
+        assert_eq!(
+            header,
+            DnsHeader {
+                packet_identifier: 0xBEEF,
+                query_response_indicator: QRIndicator::Question,
+                operation_code: OpCode::Query,
+                authoritative_answer: false,
+                truncation: false,
+                recursion_desired: true,
+                recursion_available: false,
+                reserved: 0,
+                authentic_data: false,
+                checking_disabled: false,
+                response_code: ResponseCode::NoError,
+                question_count: 1,
+                answer_record_count: 0,
+                authority_record_count: 0,
+                additional_record_count: 0,
+            }
+        );
+
+        let reply = DnsHeaderBuilder::new()
+            .response()
+            .response_code(ResponseCode::Refused)
+            .build();
+        assert_eq!(reply.query_response_indicator, QRIndicator::Reply);
+        assert_eq!(reply.response_code, ResponseCode::Refused);
+    }
+
     #[test]
     fn test_dns_header_new_success_and_error() {
         // Prepare a valid 12-byte DNS header packet (all fields are minimal/deterministic)
@@ -223,12 +569,14 @@ mod tests {
             Ok(DnsHeader {
                 packet_identifier: 1234,
                 query_response_indicator: QRIndicator::Reply,
-                operation_code: 0,
+                operation_code: OpCode::Query,
                 authoritative_answer: false,
                 truncation: false,
                 recursion_desired: true,
                 recursion_available: false,
                 reserved: 0,
+                authentic_data: false,
+                checking_disabled: false,
                 response_code: ResponseCode::NoError,
                 question_count: 1,
                 answer_record_count: 2,
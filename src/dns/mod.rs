@@ -2,15 +2,21 @@ pub mod answer_record;
 pub mod class;
 pub mod dns_message;
 pub mod domain_name;
+pub mod error;
 pub mod header;
+pub mod opt_record;
 pub mod question;
 pub mod record_type;
+pub mod zone;
 
 // Re-export commonly used types for convenience
-pub use answer_record::DnsAnswerRecord;
+pub use answer_record::{Caa, DnsAnswerRecord, Dnskey, Rrsig, Soa, Wks};
 pub use class::Class;
-pub use dns_message::DnsMessage;
+pub use dns_message::{DnsMessage, DnsMessageBuilder};
 pub use domain_name::DomainName;
-pub use header::{DnsHeader, QRIndicator, ResponseCode};
+pub use error::{DnsError, ZoneError};
+pub use header::{random_id, DnsHeader, DnsHeaderBuilder, OpCode, QRIndicator, ResponseCode};
+pub use opt_record::OptRecord;
 pub use question::DnsQuestion;
 pub use record_type::RecordType;
+pub use zone::Zone;
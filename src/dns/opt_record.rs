@@ -0,0 +1,459 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::OnceLock,
+};
+
+use crate::dns::{answer_record::RData, Class, DnsAnswerRecord, DomainName, RecordType};
+
+/// Largest UDP payload size this server advertises and accepts, per RFC 6891 section 6.2.5.
+pub const MAX_UDP_PAYLOAD_SIZE: u16 = 4096;
+
+/// UDP payload size that every DNS implementation must support (RFC 1035 section 4.2.1).
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+/// Extended response code telling that the EDNS version of a query is not supported, see
+/// RFC 6891 section 6.1.3.
+pub const BADVERS: u16 = 16;
+
+/// A single EDNS0 option carried in the RDATA of an OPT pseudo-record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// Code of the EDNS Client Subnet option (RFC 7871 section 6).
+pub const CLIENT_SUBNET_OPTION_CODE: u16 = 8;
+
+/// The network a query originates from, as carried by the EDNS Client Subnet option
+/// (RFC 7871 section 6) so that upstream servers can tailor their answers to it.
+///
+/// Only the first `source_prefix_length` bits of `address` are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSubnet {
+    pub address: IpAddr,
+    pub source_prefix_length: u8,
+    pub scope_prefix_length: u8,
+}
+
+impl ClientSubnet {
+    /// Decodes the data of a client subnet option: the address family, both prefix lengths, then
+    /// just enough address bytes to hold the source prefix, whose bits past the prefix must be
+    /// zero.
+    fn parse(data: &[u8]) -> Result<Self, ()> {
+        let fixed_fields = data.get(..4).ok_or(())?;
+        let family = u16::from_be_bytes([fixed_fields[0], fixed_fields[1]]);
+        let source_prefix_length = fixed_fields[2];
+        let scope_prefix_length = fixed_fields[3];
+        let address_bytes = &data[4..];
+        if address_bytes.len() != usize::from(source_prefix_length).div_ceil(8) {
+            return Err(());
+        }
+        let bits_in_last_byte = source_prefix_length % 8;
+        if bits_in_last_byte != 0
+            && address_bytes
+                .last()
+                .is_some_and(|last| last & (0xff >> bits_in_last_byte) != 0)
+        {
+            return Err(());
+        }
+
+        let address = match family {
+            1 if source_prefix_length <= 32 => {
+                let mut octets = [0; 4];
+                octets[..address_bytes.len()].copy_from_slice(address_bytes);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 if source_prefix_length <= 128 => {
+                let mut octets = [0; 16];
+                octets[..address_bytes.len()].copy_from_slice(address_bytes);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(()),
+        };
+
+        Ok(ClientSubnet {
+            address,
+            source_prefix_length,
+            scope_prefix_length,
+        })
+    }
+
+    /// Encodes the subnet as an EDNS option, the address being cut down to the source prefix.
+    fn to_option(self) -> EdnsOption {
+        let (family, octets): (u16, Vec<u8>) = match self.address {
+            IpAddr::V4(address) => (1, address.octets().to_vec()),
+            IpAddr::V6(address) => (2, address.octets().to_vec()),
+        };
+        let prefix_length = usize::from(self.source_prefix_length).min(octets.len() * 8);
+        let mut address_bytes = octets[..prefix_length.div_ceil(8)].to_vec();
+        // The bits past the prefix must be zero
+        if let Some(last_byte) = address_bytes.last_mut().filter(|_| prefix_length % 8 != 0) {
+            *last_byte &= 0xFF << (8 - prefix_length % 8);
+        }
+
+        EdnsOption {
+            code: CLIENT_SUBNET_OPTION_CODE,
+            data: [
+                &family.to_be_bytes()[..],
+                &[prefix_length as u8, self.scope_prefix_length],
+                &address_bytes,
+            ]
+            .concat(),
+        }
+    }
+}
+
+/// Code of the DNS Cookie option (RFC 7873 section 4).
+pub const COOKIE_OPTION_CODE: u16 = 10;
+
+/// The cookies exchanged by a client and a server (RFC 7873 section 4), which let each of them
+/// tell that a message comes from a peer it already talked to, rather than from an off-path
+/// attacker.
+///
+/// The client cookie takes 8 bytes, the server cookie, sent back by the server, 8 to 32 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub client: [u8; 8],
+    pub server: Option<Vec<u8>>,
+}
+
+impl Cookie {
+    /// Decodes the data of a cookie option: the client cookie, optionally followed by the server
+    /// cookie.
+    fn parse(data: &[u8]) -> Result<Self, ()> {
+        let client = data.get(..8).ok_or(())?.try_into().map_err(|_| ())?;
+        let server = match data.len() {
+            8 => None,
+            16..=40 => Some(data[8..].to_vec()),
+            _ => return Err(()),
+        };
+
+        Ok(Cookie { client, server })
+    }
+
+    /// Encodes the cookies as an EDNS option.
+    fn to_option(&self) -> EdnsOption {
+        EdnsOption {
+            code: COOKIE_OPTION_CODE,
+            data: [&self.client[..], self.server.as_deref().unwrap_or_default()].concat(),
+        }
+    }
+
+    /// Builds the cookies answering these ones, sent from `client`: the client cookie echoed
+    /// along with an 8-byte server cookie derived from it.
+    ///
+    /// The server cookie is a keyed hash of the client address and cookie under a secret drawn
+    /// when the process starts (RFC 7873 section 6), so the same client cookie always gets the
+    /// same server cookie from the same address until a restart.
+    pub fn answer(&self, client: IpAddr) -> Self {
+        static SECRET: OnceLock<RandomState> = OnceLock::new();
+        let server_cookie = SECRET
+            .get_or_init(RandomState::new)
+            .hash_one((client, self.client));
+
+        Cookie {
+            client: self.client,
+            server: Some(server_cookie.to_be_bytes().to_vec()),
+        }
+    }
+}
+
+/// EDNS0 OPT pseudo-record as defined in RFC 6891 section 6.1.
+///
+/// The OPT record lives in the additional section and repurposes the fields of a regular
+/// resource record:
+/// - `udp_payload_size`: Stored in the CLASS field, the largest UDP response the sender accepts.
+/// - `extended_rcode`: Upper 8 bits of the 12-bit response code, stored in the TTL field.
+/// - `version`: The EDNS version, stored in the TTL field.
+/// - `do_bit`: Whether the sender can handle DNSSEC records, stored in the TTL field.
+/// - `options`: The options found in the RDATA, kept in their raw form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptRecord {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub do_bit: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl OptRecord {
+    /// Creates an OPT record advertising the given UDP payload size and no options.
+    pub fn new(udp_payload_size: u16) -> Self {
+        OptRecord {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            do_bit: false,
+            options: Vec::new(),
+        }
+    }
+
+    /// Returns the EDNS Client Subnet option, if the record carries a well-formed one.
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.options
+            .iter()
+            .find(|option| option.code == CLIENT_SUBNET_OPTION_CODE)
+            .and_then(|option| ClientSubnet::parse(&option.data).ok())
+    }
+
+    /// Adds the EDNS Client Subnet option, replacing the one the record may already carry.
+    pub fn set_client_subnet(&mut self, client_subnet: ClientSubnet) {
+        self.options
+            .retain(|option| option.code != CLIENT_SUBNET_OPTION_CODE);
+        self.options.push(client_subnet.to_option());
+    }
+
+    /// Returns the DNS Cookie option, if the record carries a well-formed one.
+    pub fn cookie(&self) -> Option<Cookie> {
+        self.options
+            .iter()
+            .find(|option| option.code == COOKIE_OPTION_CODE)
+            .and_then(|option| Cookie::parse(&option.data).ok())
+    }
+
+    /// Adds the DNS Cookie option, replacing the one the record may already carry.
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.options
+            .retain(|option| option.code != COOKIE_OPTION_CODE);
+        self.options.push(cookie.to_option());
+    }
+
+    fn parse_options(r_data: &[u8]) -> Result<Vec<EdnsOption>, ()> {
+        let mut options: Vec<EdnsOption> = Vec::new();
+        let mut current_slice = r_data;
+
+        while !current_slice.is_empty() {
+            let (code, length) = match current_slice.get(..4) {
+                Some(bytes) => (
+                    u16::from_be_bytes([bytes[0], bytes[1]]),
+                    usize::from(u16::from_be_bytes([bytes[2], bytes[3]])),
+                ),
+                None => return Err(()),
+            };
+            let data = current_slice.get(4..4 + length).ok_or(())?;
+
+            options.push(EdnsOption {
+                code,
+                data: data.to_vec(),
+            });
+            current_slice = &current_slice[4 + length..];
+        }
+
+        Ok(options)
+    }
+
+    fn options_to_bytes(&self) -> Vec<u8> {
+        self.options
+            .iter()
+            .flat_map(|option| {
+                [
+                    option.code.to_be_bytes().to_vec(),
+                    (option.data.len() as u16).to_be_bytes().to_vec(),
+                    option.data.clone(),
+                ]
+                .concat()
+            })
+            .collect()
+    }
+}
+
+/// Interpret a resource record of type OPT as an EDNS0 OPT record
+impl TryFrom<&DnsAnswerRecord> for OptRecord {
+    type Error = ();
+
+    fn try_from(record: &DnsAnswerRecord) -> Result<Self, Self::Error> {
+        if record.record_type != RecordType::OPT {
+            return Err(());
+        }
+
+        let ttl_bytes = record.time_to_live.to_be_bytes();
+
+        Ok(OptRecord {
+            udp_payload_size: u16::from(record.class),
+            extended_rcode: ttl_bytes[0],
+            version: ttl_bytes[1],
+            do_bit: (ttl_bytes[2] & 0b10000000) != 0,
+            options: Self::parse_options(&record.r_data.0)?,
+        })
+    }
+}
+
+/// Encode an EDNS0 OPT record as a resource record for the additional section
+impl From<&OptRecord> for DnsAnswerRecord {
+    fn from(opt: &OptRecord) -> Self {
+        let r_data = opt.options_to_bytes();
+
+        DnsAnswerRecord {
+            domain_name: DomainName {
+                wire_format: vec![0x00],
+                label_segments: Vec::new(),
+            },
+            record_type: RecordType::OPT,
+            class: Class::Unknown(opt.udp_payload_size),
+            time_to_live: u32::from_be_bytes([
+                opt.extended_rcode,
+                opt.version,
+                (opt.do_bit as u8) << 7,
+                0,
+            ]),
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opt_record_roundtrip() {
+        let packet = [
+            0x00, // root domain name
+            0x00, 0x29, // TYPE = OPT
+            0x10, 0x00, // UDP payload size = 4096
+            0x00, // extended RCODE
+            0x00, // version
+            0x80, 0x00, // DO=1, Z=0
+            0x00, 0x08, // RDLEN = 8
+            0x00, 0x0a, // option code = 10 (cookie)
+            0x00, 0x04, // option length = 4
+            0xde, 0xad, 0xbe, 0xef, // option data
+        ];
+
+        let record = DnsAnswerRecord::new(&packet).unwrap();
+        let opt = OptRecord::try_from(&record);
+
+        assert_eq!(
+            opt,
+            Ok(OptRecord {
+                udp_payload_size: 4096,
+                extended_rcode: 0,
+                version: 0,
+                do_bit: true,
+                options: vec![EdnsOption {
+                    code: 10,
+                    data: vec![0xde, 0xad, 0xbe, 0xef],
+                }],
+            })
+        );
+        assert_eq!(DnsAnswerRecord::from(&opt.unwrap()).to_bytes(), packet);
+    }
+
+    #[test]
+    fn test_opt_record_errors() {
+        let mut record = DnsAnswerRecord::from(&OptRecord::new(1232));
+
+        // A truncated option must be rejected
+        record.r_data = RData(vec![0x00, 0x0a, 0x00, 0x04, 0xde]);
+        assert_eq!(OptRecord::try_from(&record), Err(()));
+
+        // Only OPT records can be interpreted as such
+        record.record_type = RecordType::A;
+        assert_eq!(OptRecord::try_from(&record), Err(()));
+    }
+
+    #[test]
+    fn test_opt_record_client_subnet() {
+        let client_subnet = ClientSubnet {
+            address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)),
+            source_prefix_length: 24,
+            scope_prefix_length: 0,
+        };
+        let mut opt = OptRecord::new(MAX_UDP_PAYLOAD_SIZE);
+        opt.set_client_subnet(client_subnet);
+
+        assert_eq!(
+            opt.options,
+            vec![EdnsOption {
+                code: 8,
+                data: vec![0x00, 0x01, 24, 0, 192, 0, 2],
+            }]
+        );
+        let record = DnsAnswerRecord::from(&opt);
+        assert_eq!(
+            OptRecord::try_from(&record).map(|opt| opt.client_subnet()),
+            Ok(Some(client_subnet))
+        );
+
+        // Address bits past the prefix are not sent
+        opt.set_client_subnet(ClientSubnet {
+            address: IpAddr::V6("2001:db8:ffff::1".parse().unwrap()),
+            source_prefix_length: 36,
+            scope_prefix_length: 0,
+        });
+        assert_eq!(opt.options.len(), 1);
+        assert_eq!(
+            opt.options[0].data,
+            vec![0x00, 0x02, 36, 0, 0x20, 0x01, 0x0d, 0xb8, 0xf0]
+        );
+
+        // The address must be exactly as long as the prefix requires
+        opt.options[0].data.push(0x00);
+        assert_eq!(opt.client_subnet(), None);
+
+        // And carry no bit past the prefix (RFC 7871 section 6)
+        opt.options[0].data = vec![0x00, 0x02, 36, 0, 0x20, 0x01, 0x0d, 0xb8, 0xf8];
+        assert_eq!(opt.client_subnet(), None);
+        opt.options[0].data = vec![0x00, 0x01, 20, 0, 192, 0, 0x21];
+        assert_eq!(opt.client_subnet(), None);
+        opt.options[0].data = vec![0x00, 0x01, 20, 0, 192, 0, 0x20];
+        assert_eq!(
+            opt.client_subnet()
+                .map(|client_subnet| client_subnet.address),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 32, 0)))
+        );
+    }
+
+    #[test]
+    fn test_opt_record_cookie() {
+        let client_only = Cookie {
+            client: [1, 2, 3, 4, 5, 6, 7, 8],
+            server: None,
+        };
+        let mut opt = OptRecord::new(MAX_UDP_PAYLOAD_SIZE);
+        opt.set_cookie(&client_only);
+        assert_eq!(
+            opt.options,
+            vec![EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }]
+        );
+        let record = DnsAnswerRecord::from(&opt);
+        assert_eq!(
+            OptRecord::try_from(&record).map(|opt| opt.cookie()),
+            Ok(Some(client_only.clone()))
+        );
+
+        let full = Cookie {
+            client: client_only.client,
+            server: Some(vec![0xaa; 16]),
+        };
+        opt.set_cookie(&full);
+        assert_eq!(opt.options.len(), 1);
+        let record = DnsAnswerRecord::from(&opt);
+        assert_eq!(
+            OptRecord::try_from(&record).map(|opt| opt.cookie()),
+            Ok(Some(full))
+        );
+
+        // The answer echoes the client cookie, with the same server cookie each time
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let answer = client_only.answer(client);
+        assert_eq!(answer.client, client_only.client);
+        assert_eq!(answer.server.as_ref().map(Vec::len), Some(8));
+        assert_eq!(answer, client_only.answer(client));
+
+        // The server cookie is bound to the address of the client
+        let other_client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert_ne!(answer.server, client_only.answer(other_client).server);
+
+        // Server cookies are 8 to 32 bytes long
+        opt.options[0].data.truncate(12);
+        assert_eq!(opt.cookie(), None);
+    }
+}
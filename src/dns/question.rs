@@ -1,10 +1,18 @@
-use crate::dns::{Class, DomainName, RecordType};
+use crate::dns::{Class, DnsError, DomainName, RecordType};
+
+/// Size of the smallest possible question: the root name followed by the type and class.
+pub(crate) const MIN_QUESTION_SIZE: usize = 5;
+
+/// Identifies the records answering a question, whatever the case of its name: the lowercased
+/// wire format of the name, the type and the class.
+pub type CacheKey = (Vec<u8>, RecordType, Class);
 
 /// Represents a single DNS question section entry.
 ///
 /// A DNS question specifies the query information in a DNS packet, including the domain name to look up,
 /// the type of record being requested (such as A, NS, MX, etc.), and the class of the query (typically Internet).
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsQuestion {
     pub domain_name: DomainName,
     pub record_type: RecordType,
@@ -12,61 +20,97 @@ pub struct DnsQuestion {
 }
 
 impl DnsQuestion {
-    pub fn new(packet: &[u8]) -> Result<Self, ()> {
-        DomainName::new(packet).and_then(|domain_name| {
-            let domain_name_len = domain_name.wire_format.len();
-            match (
-                RecordType::new(packet, domain_name_len),
-                Class::new(packet, domain_name_len),
-            ) {
-                (Ok(record_type), Ok(class)) => Ok(DnsQuestion {
-                    domain_name,
-                    record_type,
-                    class,
-                }),
-                _ => Err(()),
-            }
+    /// Builds the question for the records of type `record_type` and class `class` of `name`,
+    /// given in its textual form.
+    pub fn from_parts(name: &str, record_type: RecordType, class: Class) -> Result<Self, DnsError> {
+        let domain_name = name
+            .parse()
+            .map_err(|_| DnsError::InvalidDomainName(name.to_string()))?;
+
+        Ok(DnsQuestion {
+            domain_name,
+            record_type,
+            class,
         })
     }
 
-    fn parse_and_return_next_slice(packet_slice: &[u8]) -> Result<(Self, &[u8]), ()> {
-        let question = Self::new(packet_slice)?;
-        let domain_name_len = question.domain_name.wire_format.len();
+    /// The key of the answers to the question, equal for questions whose names only differ in
+    /// case, as domain names are compared case-insensitively (RFC 4343).
+    pub fn cache_key(&self) -> CacheKey {
+        (
+            self.domain_name.to_ascii_lowercase().wire_format,
+            self.record_type,
+            self.class,
+        )
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn new(packet: &[u8]) -> Result<Self, ()> {
+        Self::parse_at(packet, 0).map(|(question, _)| question)
+    }
+
+    /// Parses the question starting at `offset` in `packet`, whose name may be compressed,
+    /// returning it along with the number of bytes it takes.
+    fn parse_at(packet: &[u8], offset: usize) -> Result<(Self, usize), ()> {
+        let (domain_name, name_length) = DomainName::parse_at(packet, offset).map_err(|_| ())?;
+        let question_slice = &packet[offset..];
+        let record_type = RecordType::new(question_slice, name_length)?;
+        let class = Class::new(question_slice, name_length)?;
 
-        packet_slice
-            .get(domain_name_len + 4..)
-            .ok_or(())
-            .map(|next_slice| (question, next_slice))
+        Ok((
+            DnsQuestion {
+                domain_name,
+                record_type,
+                class,
+            },
+            name_length + 4,
+        ))
     }
 
+    /// Parses `number_of_questions` consecutive questions starting at `offset` in `packet`,
+    /// returning them along with the offset following the last one.
+    ///
+    /// A count that cannot possibly fit in the packet is rejected upfront, so that a tiny packet
+    /// declaring thousands of questions does not trigger any work.
     pub fn parse_all_questions(
-        packet_slice: &[u8],
+        packet: &[u8],
+        offset: usize,
         number_of_questions: usize,
-    ) -> Result<(Vec<Self>, &[u8]), ()> {
-        let mut questions: Vec<Self> = Vec::new();
-        let mut current_slice = packet_slice;
+    ) -> Result<(Vec<Self>, usize), DnsError> {
+        let remaining_length = packet.len().saturating_sub(offset);
+        if number_of_questions.saturating_mul(MIN_QUESTION_SIZE) > remaining_length {
+            return Err(DnsError::PacketTooShort);
+        }
+
+        let mut questions: Vec<Self> = Vec::with_capacity(number_of_questions);
+        let mut offset = offset;
 
         for _ in 0..number_of_questions {
-            match Self::parse_and_return_next_slice(current_slice) {
-                Err(_) => {
-                    return Err(());
-                }
-                Ok((question, next_slice)) => {
-                    questions.push(question);
-                    current_slice = next_slice;
-                }
-            }
+            let (question, length) =
+                Self::parse_at(packet, offset).map_err(|_| DnsError::MalformedPacket)?;
+            questions.push(question);
+            offset += length;
         }
 
-        Ok((questions, current_slice))
+        Ok((questions, offset))
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let domain_name_bytes = self.domain_name.wire_format.clone();
-        let record_type_bytes = (self.record_type as u16).to_be_bytes().to_vec();
-        let class_bytes = (self.class as u16).to_be_bytes().to_vec();
+        let mut bytes = Vec::with_capacity(self.domain_name.encoded_len() + 4);
+        self.write_into(&mut bytes);
+        bytes
+    }
+
+    /// Appends the question to `out`, its name uncompressed.
+    pub fn write_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.domain_name.wire_format);
+        self.write_fields_after_name(out);
+    }
 
-        [domain_name_bytes, record_type_bytes, class_bytes].concat()
+    /// Appends what follows the name to `out`: the type and class.
+    pub(crate) fn write_fields_after_name(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&u16::from(self.record_type).to_be_bytes());
+        out.extend_from_slice(&u16::from(self.class).to_be_bytes());
     }
 }
 
@@ -127,6 +171,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dns_question_new_with_pseudo_types() {
+        let question = |record_type: [u8; 2]| {
+            [
+                &[
+                    0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+                ][..],
+                &record_type,
+                &[0x00, 0x01],
+            ]
+            .concat()
+        };
+
+        assert_eq!(
+            DnsQuestion::new(&question([0x00, 0xff])),
+            DnsQuestion::from_parts("example.com", RecordType::ANY, Class::IN).map_err(|_| ())
+        );
+        assert_eq!(
+            DnsQuestion::new(&question([0x00, 0xfc])),
+            DnsQuestion::from_parts("example.com", RecordType::AXFR, Class::IN).map_err(|_| ())
+        );
+        assert_eq!(
+            DnsQuestion::new(&question([0x00, 0x01])),
+            DnsQuestion::from_parts("example.com", RecordType::A, Class::IN).map_err(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_dns_question_write_into() {
+        let question = DnsQuestion::from_parts("example.com", RecordType::MX, Class::IN).unwrap();
+        let mut out = vec![0xff];
+        question.write_into(&mut out);
+        assert_eq!(
+            out,
+            [
+                0xff, // already in the buffer
+                0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+                0x0f, // RecordType::MX
+                0x00, 0x01, // Class::IN
+            ]
+        );
+        assert_eq!(out[1..], question.to_bytes());
+    }
+
+    #[test]
+    fn test_dns_question_new_with_any_class() {
+        let packet = [
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+            0x01, // RecordType::A
+            0x00, 0xff, // QCLASS = 255 (ANY)
+        ];
+
+        let question = DnsQuestion::new(&packet).unwrap();
+        assert_eq!(question.class, Class::ANY);
+        assert_eq!(question.to_bytes(), packet);
+
+        // QCLASS = 254 (NONE), as in the prerequisites of dynamic updates
+        let packet = [&packet[..packet.len() - 1], &[0xfe]].concat();
+        let question = DnsQuestion::new(&packet).unwrap();
+        assert_eq!(question.class, Class::NONE);
+        assert_eq!(question.to_bytes(), packet);
+    }
+
+    #[test]
+    fn test_dns_question_from_parts() {
+        let question = DnsQuestion::from_parts("example.com", RecordType::A, Class::IN).unwrap();
+        assert_eq!(
+            question.to_bytes(),
+            [
+                0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', // "example"
+                0x03, b'c', b'o', b'm', // "com"
+                0x00, // end of name
+                0x00, 0x01, // A
+                0x00, 0x01, // IN
+            ]
+        );
+
+        assert_eq!(
+            DnsQuestion::from_parts("example..com", RecordType::A, Class::IN),
+            Err(DnsError::InvalidDomainName("example..com".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_dns_question_cache_key() {
+        let question = |name| DnsQuestion::from_parts(name, RecordType::A, Class::IN).unwrap();
+
+        assert_eq!(
+            question("WWW.Example.com").cache_key(),
+            question("www.example.com").cache_key()
+        );
+        assert_ne!(
+            question("www.example.com").cache_key(),
+            DnsQuestion::from_parts("www.example.com", RecordType::AAAA, Class::IN)
+                .unwrap()
+                .cache_key()
+        );
+    }
+
     #[test]
     fn test_parse_all_questions() {
         let packet = [
@@ -148,7 +291,7 @@ mod tests {
         ];
 
         assert_eq!(
-            DnsQuestion::parse_all_questions(&packet, 2),
+            DnsQuestion::parse_all_questions(&packet, 0, 2),
             Ok((
                 vec![
                     DnsQuestion {
@@ -177,14 +320,60 @@ mod tests {
                         class: Class::IN
                     }
                 ],
-                &packet[packet.len() - 4..]
+                packet.len() - 4
             ))
         );
 
         // If we trucate the second question, the parsing should fail
         assert_eq!(
-            DnsQuestion::parse_all_questions(&packet[..packet.len() - 10], 2),
-            Err(())
+            DnsQuestion::parse_all_questions(&packet[..packet.len() - 10], 0, 2),
+            Err(DnsError::MalformedPacket)
         )
     }
+
+    #[test]
+    fn test_parse_all_questions_with_compressed_name() {
+        let packet = [
+            // Header, not relevant for this test
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+            // Question 1, at offset 12
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', // "example"
+            0x03, b'c', b'o', b'm', // "com"
+            0x00, // end of name
+            0x00, 0x01, // RecordType::A
+            0x00, 0x01, // Class::IN
+            // Question 2, whose name points to the one of question 1
+            0xc0, 0x0c, // pointer to offset 12
+            0x00, 0x1c, // RecordType::AAAA
+            0x00, 0x01, // Class::IN
+        ];
+
+        // The compressed name takes 2 bytes, whatever the length of the name it points to
+        assert_eq!(
+            DnsQuestion::parse_at(&packet, 29),
+            Ok((
+                DnsQuestion::from_parts("example.com", RecordType::AAAA, Class::IN).unwrap(),
+                2 + 4
+            ))
+        );
+        assert_eq!(
+            DnsQuestion::parse_all_questions(&packet, 12, 2).map(|(_, offset)| offset),
+            Ok(packet.len())
+        );
+    }
+
+    #[test]
+    fn test_parse_all_questions_rejects_impossible_count() {
+        // 30 bytes cannot hold more than 6 questions, whatever they contain
+        let packet = [0u8; 30];
+
+        assert_eq!(
+            DnsQuestion::parse_all_questions(&packet, 0, 1000),
+            Err(DnsError::PacketTooShort)
+        );
+        assert_eq!(
+            DnsQuestion::parse_all_questions(&packet, 0, usize::MAX),
+            Err(DnsError::PacketTooShort)
+        );
+    }
 }
@@ -1,32 +1,49 @@
-/// DNS Record Types as defined in RFC 1035 section 3.2.2.
+/// DNS Record Types as defined in RFC 1035 section 3.2.2, along with the later AAAA (RFC 3596),
+/// OPT (RFC 6891), RRSIG and DNSKEY (RFC 4034) and CAA (RFC 8659) types, and the AXFR (RFC 5936)
+/// and ANY (RFC 8482) question types, which only appear in questions.
 ///
 /// This enum represents the TYPE field in a DNS question or resource record, specifying
 /// the kind of resource being queried or provided.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// `Unknown` keeps the raw value of the types this crate does not know, such as SVCB or HTTPS,
+/// so that their records can be passed through with their RDATA untouched (RFC 3597).
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordType {
-    A = 1,
-    NS = 2,
-    MD = 3,
-    MF = 4,
-    CNAME = 5,
-    SOA = 6,
-    MB = 7,
-    MG = 8,
-    MR = 9,
-    NULL = 10,
-    WKS = 11,
-    PTR = 12,
-    HINFO = 13,
-    MINFO = 14,
-    MX = 15,
-    TXT = 16,
+    A,
+    NS,
+    MD,
+    MF,
+    CNAME,
+    SOA,
+    MB,
+    MG,
+    MR,
+    NULL,
+    WKS,
+    PTR,
+    HINFO,
+    MINFO,
+    MX,
+    TXT,
+    AAAA,
+    OPT,
+    RRSIG,
+    DNSKEY,
+    AXFR,
+    ANY,
+    CAA,
+    Unknown(u16),
 }
 
 impl RecordType {
+    #[allow(clippy::result_unit_err)]
     pub fn new(packet: &[u8], domain_name_len: usize) -> Result<Self, ()> {
         match (packet.get(domain_name_len), packet.get(domain_name_len + 1)) {
             (Some(first_byte), Some(second_byte)) => {
-                RecordType::try_from(u16::from_be_bytes([*first_byte, *second_byte]))
+                let value = u16::from_be_bytes([*first_byte, *second_byte]);
+                Ok(RecordType::try_from(value).unwrap_or(RecordType::Unknown(value)))
             }
             _ => Err(()),
         }
@@ -54,11 +71,128 @@ impl TryFrom<u16> for RecordType {
             14 => Ok(RecordType::MINFO),
             15 => Ok(RecordType::MX),
             16 => Ok(RecordType::TXT),
+            28 => Ok(RecordType::AAAA),
+            41 => Ok(RecordType::OPT),
+            46 => Ok(RecordType::RRSIG),
+            48 => Ok(RecordType::DNSKEY),
+            252 => Ok(RecordType::AXFR),
+            255 => Ok(RecordType::ANY),
+            257 => Ok(RecordType::CAA),
             _ => Err(()),
         }
     }
 }
 
+impl From<RecordType> for u16 {
+    fn from(record_type: RecordType) -> Self {
+        match record_type {
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::MD => 3,
+            RecordType::MF => 4,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::MB => 7,
+            RecordType::MG => 8,
+            RecordType::MR => 9,
+            RecordType::NULL => 10,
+            RecordType::WKS => 11,
+            RecordType::PTR => 12,
+            RecordType::HINFO => 13,
+            RecordType::MINFO => 14,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::OPT => 41,
+            RecordType::RRSIG => 46,
+            RecordType::DNSKEY => 48,
+            RecordType::AXFR => 252,
+            RecordType::ANY => 255,
+            RecordType::CAA => 257,
+            RecordType::Unknown(value) => value,
+        }
+    }
+}
+
+/// Compare record types by their numeric value, so that `Unknown` of a known value equals that type
+impl PartialEq for RecordType {
+    fn eq(&self, other: &Self) -> bool {
+        u16::from(*self) == u16::from(*other)
+    }
+}
+
+impl Eq for RecordType {}
+
+impl std::hash::Hash for RecordType {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        u16::from(*self).hash(state);
+    }
+}
+
+/// Order record types by their numeric value, e.g. to sort records deterministically
+impl PartialOrd for RecordType {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RecordType {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        u16::from(*self).cmp(&u16::from(*other))
+    }
+}
+
+/// Render a record type by its mnemonic, as used in zone files, or as `TYPE<n>` (RFC 3597
+/// section 5) when it has none
+impl std::fmt::Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordType::Unknown(value) => write!(f, "TYPE{}", value),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Parse a record type from its mnemonic or its `TYPE<n>` form, ignoring case
+impl std::str::FromStr for RecordType {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.to_ascii_uppercase();
+
+        match text.as_str() {
+            "A" => Ok(RecordType::A),
+            "NS" => Ok(RecordType::NS),
+            "MD" => Ok(RecordType::MD),
+            "MF" => Ok(RecordType::MF),
+            "CNAME" => Ok(RecordType::CNAME),
+            "SOA" => Ok(RecordType::SOA),
+            "MB" => Ok(RecordType::MB),
+            "MG" => Ok(RecordType::MG),
+            "MR" => Ok(RecordType::MR),
+            "NULL" => Ok(RecordType::NULL),
+            "WKS" => Ok(RecordType::WKS),
+            "PTR" => Ok(RecordType::PTR),
+            "HINFO" => Ok(RecordType::HINFO),
+            "MINFO" => Ok(RecordType::MINFO),
+            "MX" => Ok(RecordType::MX),
+            "TXT" => Ok(RecordType::TXT),
+            "AAAA" => Ok(RecordType::AAAA),
+            "OPT" => Ok(RecordType::OPT),
+            "RRSIG" => Ok(RecordType::RRSIG),
+            "DNSKEY" => Ok(RecordType::DNSKEY),
+            "AXFR" => Ok(RecordType::AXFR),
+            "ANY" => Ok(RecordType::ANY),
+            "CAA" => Ok(RecordType::CAA),
+            _ => text
+                .strip_prefix("TYPE")
+                .and_then(|value| value.parse::<u16>().ok())
+                .map(|value| RecordType::try_from(value).unwrap_or(RecordType::Unknown(value)))
+                .ok_or(()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,10 +215,49 @@ mod tests {
         assert_eq!(RecordType::try_from(14), Ok(RecordType::MINFO));
         assert_eq!(RecordType::try_from(15), Ok(RecordType::MX));
         assert_eq!(RecordType::try_from(16), Ok(RecordType::TXT));
+        assert_eq!(RecordType::try_from(28), Ok(RecordType::AAAA));
+        assert_eq!(RecordType::try_from(252), Ok(RecordType::AXFR));
+        assert_eq!(RecordType::try_from(255), Ok(RecordType::ANY));
+        assert_eq!(RecordType::try_from(41), Ok(RecordType::OPT));
+        assert_eq!(RecordType::try_from(46), Ok(RecordType::RRSIG));
+        assert_eq!(RecordType::try_from(48), Ok(RecordType::DNSKEY));
+        assert_eq!(RecordType::try_from(257), Ok(RecordType::CAA));
         // Test error case
         assert_eq!(RecordType::try_from(0), Err(()));
         assert_eq!(RecordType::try_from(17), Err(()));
         assert_eq!(RecordType::try_from(200), Err(()));
+
+        assert_eq!(u16::from(RecordType::A), 1);
+        assert_eq!(u16::from(RecordType::CAA), 257);
+        assert_eq!(u16::from(RecordType::Unknown(65)), 65);
+    }
+
+    #[test]
+    fn test_record_type_order() {
+        assert!(RecordType::A < RecordType::AAAA);
+        assert!(RecordType::TXT < RecordType::Unknown(17));
+        assert!(RecordType::Unknown(64) < RecordType::ANY);
+
+        // An unknown record type of a known value is that record type
+        assert_eq!(RecordType::Unknown(1), RecordType::A);
+        assert_eq!(
+            RecordType::Unknown(1).cmp(&RecordType::A),
+            std::cmp::Ordering::Equal
+        );
+        let types: std::collections::HashSet<_> = [RecordType::A, RecordType::Unknown(1)].into();
+        assert_eq!(types.len(), 1);
+    }
+
+    #[test]
+    fn test_record_type_text() {
+        assert_eq!(RecordType::CNAME.to_string(), "CNAME");
+        assert_eq!("cname".parse(), Ok(RecordType::CNAME));
+        assert_eq!("MX".parse(), Ok(RecordType::MX));
+        assert_eq!("BOGUS".parse::<RecordType>(), Err(()));
+
+        assert_eq!(RecordType::Unknown(65).to_string(), "TYPE65");
+        assert_eq!("type65".parse(), Ok(RecordType::Unknown(65)));
+        assert_eq!("TYPE1".parse(), Ok(RecordType::A));
     }
 
     #[test]
@@ -106,5 +279,11 @@ mod tests {
         // domain_name_len is position after domain name (should be 17 for above)
         let domain_name_len = 16;
         assert_eq!(RecordType::new(packet, domain_name_len), Ok(RecordType::A));
+
+        // Types this crate does not know are kept as is
+        assert_eq!(
+            RecordType::new(&[0x00, 0x41], 0),
+            Ok(RecordType::Unknown(65))
+        );
     }
 }
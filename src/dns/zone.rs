@@ -0,0 +1,370 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{
+    answer_record::RData, DnsAnswerRecord, DnsQuestion, DomainName, RecordType, ZoneError,
+};
+
+/// Number of aliases followed while answering a question, which bounds the work done when
+/// CNAME records loop back on each other.
+pub(crate) const MAX_CNAME_CHAIN_LENGTH: usize = 8;
+
+/// Records this server is authoritative for, loaded from a zone file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Zone {
+    records: Vec<DnsAnswerRecord>,
+}
+
+impl Zone {
+    /// Loads the records of a zone file, in the format described in [`load`].
+    pub fn load(text: &str) -> Result<Self, ZoneError> {
+        load(text).map(|records| Zone { records })
+    }
+
+    /// Returns the records answering `question`, every record of the name for ANY questions.
+    ///
+    /// When the name has no record of the requested type but is an alias, its CNAME record is
+    /// returned along with the records answering the question for the alias target, following
+    /// the chain of aliases within the zone (RFC 1034 section 3.6.2).
+    pub fn lookup(&self, question: &DnsQuestion) -> Vec<DnsAnswerRecord> {
+        let mut answers: Vec<DnsAnswerRecord> = Vec::new();
+        let mut current_question = question.clone();
+        // Aliases only answer the questions asking for them; the others follow them
+        let follows_aliases = !matches!(question.record_type, RecordType::CNAME | RecordType::ANY);
+
+        for _ in 0..MAX_CNAME_CHAIN_LENGTH {
+            let (aliases, matching_records): (Vec<DnsAnswerRecord>, Vec<DnsAnswerRecord>) = self
+                .owned_records(&current_question.domain_name, question)
+                .into_iter()
+                .filter(|record| record.answers_question(&current_question))
+                .partition(|record| follows_aliases && record.record_type == RecordType::CNAME);
+            if !matching_records.is_empty() {
+                answers.extend(matching_records);
+                break;
+            }
+
+            match aliases.into_iter().next() {
+                Some(alias) => {
+                    answers.push(alias.clone());
+                    match alias.r_data.as_cname() {
+                        Some(target) => current_question.domain_name = target,
+                        None => break,
+                    }
+                }
+                None => break,
+            }
+        }
+
+        answers
+    }
+
+    /// Returns the records owned by `name` in the class of `question`.
+    ///
+    /// A name owning no record at all matches the wildcard replacing its leftmost label, if any:
+    /// the records of `*.example.com` are returned as owned by `a.example.com`, but never mix with
+    /// the records of `exact.example.com` (RFC 1034 section 4.3.3).
+    fn owned_records(&self, name: &DomainName, question: &DnsQuestion) -> Vec<DnsAnswerRecord> {
+        let records_of = |owner: &DomainName| -> Vec<DnsAnswerRecord> {
+            self.records
+                .iter()
+                .filter(|record| {
+                    record.class == question.class
+                        && record
+                            .domain_name
+                            .wire_format
+                            .eq_ignore_ascii_case(&owner.wire_format)
+                })
+                .cloned()
+                .collect()
+        };
+
+        let records = records_of(name);
+        if !records.is_empty() {
+            return records;
+        }
+
+        match wildcard_of(name) {
+            Some(wildcard) => records_of(&wildcard)
+                .into_iter()
+                .map(|record| DnsAnswerRecord {
+                    domain_name: name.clone(),
+                    ..record
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The wildcard name replacing the leftmost label of `name`, `*.example.com` for
+/// `a.example.com`, or nothing for the root name.
+fn wildcard_of(name: &DomainName) -> Option<DomainName> {
+    let first_label_length = usize::from(*name.wire_format.first()?);
+    if first_label_length == 0 {
+        return None;
+    }
+
+    let parent = name.wire_format.get(first_label_length + 1..)?;
+    DomainName::new(&[&[1, b'*'][..], parent].concat()).ok()
+}
+
+/// Loads the records of a zone file, one record per line as written by
+/// [`DnsAnswerRecord::to_zone_line`]: owner name, TTL, class, type and RDATA.
+///
+/// Names are always absolute, and everything following a `;` is a comment. Directives such as
+/// `$ORIGIN` and the other shorthands of RFC 1035 section 5.1 are not supported.
+pub fn load(text: &str) -> Result<Vec<DnsAnswerRecord>, ZoneError> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split(';').next().unwrap_or_default()))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_number, line)| {
+            parse_record(line).map_err(|_| ZoneError::InvalidRecord(line_number))
+        })
+        .collect()
+}
+
+fn parse_record(line: &str) -> Result<DnsAnswerRecord, ()> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 5 {
+        return Err(());
+    }
+
+    let record_type: RecordType = fields[3].parse()?;
+    let r_data = parse_r_data(record_type, &fields[4..])?;
+
+    Ok(DnsAnswerRecord {
+        domain_name: fields[0].parse()?,
+        record_type,
+        class: fields[2].parse()?,
+        time_to_live: fields[1].parse().map_err(|_| ())?,
+        r_data_length: r_data.0.len(),
+        r_data,
+    })
+}
+
+fn parse_r_data(record_type: RecordType, fields: &[&str]) -> Result<RData, ()> {
+    match (record_type, fields) {
+        // Generic form from RFC 3597 section 5, valid for any record type
+        (_, ["\\#", length, hex @ ..]) => {
+            let r_data = RData::from_hex(&hex.concat()).map_err(|_| ())?;
+            match length.parse::<usize>() {
+                Ok(length) if length == r_data.0.len() => Ok(r_data),
+                _ => Err(()),
+            }
+        }
+        (RecordType::A, [address]) => address
+            .parse::<Ipv4Addr>()
+            .map(|address| RData(address.octets().to_vec()))
+            .map_err(|_| ()),
+        (RecordType::AAAA, [address]) => address
+            .parse::<Ipv6Addr>()
+            .map(|address| RData(address.octets().to_vec()))
+            .map_err(|_| ()),
+        (
+            RecordType::NS
+            | RecordType::MD
+            | RecordType::MF
+            | RecordType::CNAME
+            | RecordType::MB
+            | RecordType::MG
+            | RecordType::MR
+            | RecordType::PTR,
+            [name],
+        ) => name
+            .parse::<DomainName>()
+            .map(|name| RData(name.wire_format)),
+        (RecordType::MX, [preference, exchange]) => {
+            let preference = preference.parse::<u16>().map_err(|_| ())?;
+            let exchange = exchange.parse::<DomainName>()?;
+            Ok(RData(
+                [preference.to_be_bytes().to_vec(), exchange.wire_format].concat(),
+            ))
+        }
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::Class;
+
+    use super::*;
+
+    #[test]
+    fn test_zone_load() {
+        let zone = "
+            ; A small zone
+            example.com.      3600 IN MX    10 mail.example.com.
+            www.example.com.  300  IN A     1.2.3.4 ; the web server
+            ftp.example.com.  300  IN CNAME www.example.com.
+            example.com.      300  IN TXT   \\# 3 026869
+        ";
+
+        let records = load(zone).unwrap();
+        let lines: Vec<String> = records.iter().map(|record| record.to_zone_line()).collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "example.com. 3600 IN MX 10 mail.example.com.",
+                "www.example.com. 300 IN A 1.2.3.4",
+                "ftp.example.com. 300 IN CNAME www.example.com.",
+                "example.com. 300 IN TXT \\# 3 026869",
+            ]
+        );
+        assert_eq!(records[1].r_data, RData(vec![1, 2, 3, 4]));
+        assert_eq!(records[1].class, Class::IN);
+
+        // Errors point at the offending line
+        assert_eq!(
+            load("www.example.com. 300 IN A 1.2.3.4\nwww.example.com. 300 IN A 1.2.3"),
+            Err(ZoneError::InvalidRecord(2))
+        );
+        assert_eq!(
+            load("www.example.com. 300 IN TXT \\# 4 026869"),
+            Err(ZoneError::InvalidRecord(1))
+        );
+    }
+
+    #[test]
+    fn test_zone_lookup_follows_cname_chain() {
+        let zone = Zone::load(
+            "
+            www.example.com.     300 IN CNAME web.example.com.
+            web.example.com.     300 IN CNAME webhost.example.com.
+            webhost.example.com. 300 IN A     1.2.3.4
+            loop1.example.com.   300 IN CNAME loop2.example.com.
+            loop2.example.com.   300 IN CNAME loop1.example.com.
+            ",
+        )
+        .unwrap();
+        let question = |name: &str| DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+
+        assert_eq!(
+            zone.lookup(&question("www.example.com"))
+                .iter()
+                .map(|answer| answer.to_zone_line())
+                .collect::<Vec<_>>(),
+            vec![
+                "www.example.com. 300 IN CNAME web.example.com.",
+                "web.example.com. 300 IN CNAME webhost.example.com.",
+                "webhost.example.com. 300 IN A 1.2.3.4",
+            ]
+        );
+
+        // Aliases looping back on each other stop after a bounded number of records
+        assert_eq!(
+            zone.lookup(&question("loop1.example.com")).len(),
+            MAX_CNAME_CHAIN_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_zone_lookup_wildcard() {
+        let zone = Zone::load(
+            "
+            *.example.com.     300 IN A     1.2.3.4
+            exact.example.com. 300 IN A     5.6.7.8
+            mail.example.com.  300 IN MX    \\# 4 000a0000
+            ",
+        )
+        .unwrap();
+        let question = |name: &str| DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let zone_lines = |name: &str| {
+            zone.lookup(&question(name))
+                .iter()
+                .map(|answer| answer.to_zone_line())
+                .collect::<Vec<_>>()
+        };
+
+        // The wildcard answers with the queried name as owner
+        assert_eq!(
+            zone_lines("a.example.com"),
+            ["a.example.com. 300 IN A 1.2.3.4"]
+        );
+        assert_eq!(
+            zone_lines("exact.example.com"),
+            ["exact.example.com. 300 IN A 5.6.7.8"]
+        );
+        // Names that exist do not match the wildcard, even without records of the queried type
+        assert!(zone_lines("mail.example.com").is_empty());
+        // The wildcard only stands for the leftmost label
+        assert!(zone_lines("b.a.example.com").is_empty());
+        assert!(zone_lines("example.com").is_empty());
+    }
+
+    #[test]
+    fn test_zone_lookup() {
+        let zone = Zone::load(
+            "
+            www.example.com.  300 IN A     1.2.3.4
+            www.example.com.  300 IN AAAA  2001:db8::1
+            ftp.example.com.  300 IN CNAME www.example.com.
+            ",
+        )
+        .unwrap();
+        let question = |name: &str, record_type: RecordType| DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type,
+            class: Class::IN,
+        };
+
+        let a_answers = zone.lookup(&question("WWW.example.com", RecordType::A));
+        assert_eq!(a_answers.len(), 1);
+        assert_eq!(a_answers[0].r_data.as_a(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let aaaa_answers = zone.lookup(&question("www.example.com", RecordType::AAAA));
+        assert_eq!(
+            aaaa_answers[0].r_data.as_aaaa(),
+            Some("2001:db8::1".parse().unwrap())
+        );
+
+        // An alias is answered with its CNAME record, followed by the records of its target
+        let zone_lines = |answers: Vec<DnsAnswerRecord>| {
+            answers
+                .iter()
+                .map(|answer| answer.to_zone_line())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            zone_lines(zone.lookup(&question("ftp.example.com", RecordType::CNAME))),
+            vec!["ftp.example.com. 300 IN CNAME www.example.com."]
+        );
+        assert_eq!(
+            zone_lines(zone.lookup(&question("ftp.example.com", RecordType::A))),
+            vec![
+                "ftp.example.com. 300 IN CNAME www.example.com.",
+                "www.example.com. 300 IN A 1.2.3.4"
+            ]
+        );
+
+        // Names or types without records get no answer
+        assert!(zone
+            .lookup(&question("mail.example.com", RecordType::A))
+            .is_empty());
+        assert!(zone
+            .lookup(&question("www.example.com", RecordType::MX))
+            .is_empty());
+
+        // ANY questions get every record of the name, aliases included
+        assert_eq!(
+            zone_lines(zone.lookup(&question("www.example.com", RecordType::ANY))),
+            vec![
+                "www.example.com. 300 IN A 1.2.3.4",
+                "www.example.com. 300 IN AAAA 2001:db8::1"
+            ]
+        );
+        assert_eq!(
+            zone_lines(zone.lookup(&question("ftp.example.com", RecordType::ANY))),
+            vec!["ftp.example.com. 300 IN CNAME www.example.com."]
+        );
+    }
+}
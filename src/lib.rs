@@ -0,0 +1,7 @@
+pub mod cache;
+pub mod dns;
+pub mod metrics;
+pub mod observer;
+pub mod policy;
+pub mod resolver;
+pub mod server;
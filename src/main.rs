@@ -1,8 +1,15 @@
-mod dns;
-mod server;
+use codecrafters_dns_server::server::{self, ServerConfig};
 
 fn main() {
-    if let Err(e) = server::run() {
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid arguments: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = server::run(&config) {
         eprintln!("Server error: {}", e);
         std::process::exit(1);
     }
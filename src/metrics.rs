@@ -0,0 +1,95 @@
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    dns::{DnsMessage, DnsQuestion, ResponseCode},
+    observer::QueryObserver,
+};
+
+/// Number of response codes the header of a reply can carry, see [`ResponseCode`].
+const RESPONSE_CODES: usize = 16;
+
+/// Counters of the transactions of the server, updated as a [`QueryObserver`].
+///
+/// Every counter is atomic, so the metrics can be shared with, and read from, another thread
+/// while the server runs.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    queries_received: AtomicU64,
+    responses_sent: AtomicU64,
+    parse_failures: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    responses_by_code: [AtomicU64; RESPONSE_CODES],
+}
+
+/// The values of the [`ServerMetrics`] counters at some point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub queries_received: u64,
+    pub responses_sent: u64,
+    pub parse_failures: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Number of replies sent with each response code, indexed by the value of the code.
+    pub responses_by_code: [u64; RESPONSE_CODES],
+}
+
+impl ServerMetrics {
+    pub fn new() -> Self {
+        ServerMetrics::default()
+    }
+
+    /// Reads every counter.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let read = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        MetricsSnapshot {
+            queries_received: read(&self.queries_received),
+            responses_sent: read(&self.responses_sent),
+            parse_failures: read(&self.parse_failures),
+            cache_hits: read(&self.cache_hits),
+            cache_misses: read(&self.cache_misses),
+            responses_by_code: self.responses_by_code.each_ref().map(read),
+        }
+    }
+}
+
+/// Index of the counter of `response_code`: its 4-bit value in the header.
+fn code_index(response_code: ResponseCode) -> usize {
+    usize::from(u8::from(response_code) & 0b1111)
+}
+
+impl MetricsSnapshot {
+    /// Number of replies sent with `response_code`.
+    pub fn responses_with(&self, response_code: ResponseCode) -> u64 {
+        self.responses_by_code[code_index(response_code)]
+    }
+}
+
+impl QueryObserver for ServerMetrics {
+    fn on_query(&self, _source: SocketAddr, _query: &DnsMessage) {
+        self.queries_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_response(&self, _response: &DnsMessage, response_code: ResponseCode) {
+        self.responses_sent.fetch_add(1, Ordering::Relaxed);
+        self.responses_by_code[code_index(response_code)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_parse_failure(&self, _source: SocketAddr) {
+        self.queries_received.fetch_add(1, Ordering::Relaxed);
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_cache_lookup(&self, _question: &DnsQuestion, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+
+use crate::dns::{DnsMessage, DnsQuestion, ResponseCode};
+
+/// Gets told about every transaction of the server, e.g. to log queries or collect metrics.
+pub trait QueryObserver {
+    /// Called with each query that could be parsed, before it is answered.
+    fn on_query(&self, source: SocketAddr, query: &DnsMessage);
+
+    /// Called with each reply, just before it is sent.
+    fn on_response(&self, response: &DnsMessage, response_code: ResponseCode);
+
+    /// Called with each datagram that could not be parsed as a query.
+    fn on_parse_failure(&self, _source: SocketAddr) {}
+
+    /// Called with each question looked up in the cache of forwarded answers.
+    fn on_cache_lookup(&self, _question: &DnsQuestion, _hit: bool) {}
+}
+
+/// Ignores every transaction.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl QueryObserver for NoopObserver {
+    fn on_query(&self, _source: SocketAddr, _query: &DnsMessage) {}
+
+    fn on_response(&self, _response: &DnsMessage, _response_code: ResponseCode) {}
+}
+
+/// Prints a line per query and per reply on the standard output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutObserver;
+
+impl QueryObserver for StdoutObserver {
+    fn on_query(&self, source: SocketAddr, query: &DnsMessage) {
+        for question in query.questions() {
+            println!(
+                "Query {} from {}: {} {} {}",
+                query.header().packet_identifier,
+                source,
+                question.domain_name.to_text(),
+                question.class,
+                question.record_type
+            );
+        }
+    }
+
+    fn on_response(&self, response: &DnsMessage, response_code: ResponseCode) {
+        println!(
+            "Reply {}: {:?} with {} answers",
+            response.header().packet_identifier,
+            response_code,
+            response.answers().len()
+        );
+    }
+}
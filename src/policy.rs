@@ -0,0 +1,384 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+};
+
+use thiserror::Error;
+
+use crate::dns::{
+    answer_record::RData, Class, DnsAnswerRecord, DnsQuestion, DomainName, RecordType, ResponseCode,
+};
+
+/// What to answer for a name caught by a policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Pretend the name does not exist.
+    NameError,
+    /// Answer A questions with the given address, and other questions with no records.
+    Sinkhole(Ipv4Addr),
+}
+
+impl PolicyAction {
+    /// The response code of a reply enforcing this action.
+    pub fn response_code(&self) -> ResponseCode {
+        match self {
+            PolicyAction::NameError => ResponseCode::NameError,
+            PolicyAction::Sinkhole(_) => ResponseCode::NoError,
+        }
+    }
+
+    /// The answers of a reply enforcing this action for `question`, which can be cached for
+    /// `time_to_live` seconds.
+    pub fn answers(&self, question: &DnsQuestion, time_to_live: u32) -> Vec<DnsAnswerRecord> {
+        match self {
+            PolicyAction::Sinkhole(address) if question.record_type == RecordType::A => {
+                vec![DnsAnswerRecord {
+                    domain_name: question.domain_name.clone(),
+                    record_type: RecordType::A,
+                    class: Class::IN,
+                    time_to_live,
+                    r_data_length: 4,
+                    r_data: RData(address.octets().to_vec()),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// TTLs pinned on the records of specific names, regardless of the value they came with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TtlOverrides {
+    // Keyed by the lowercased wire format, as names are compared case-insensitively (RFC 4343)
+    overrides: HashMap<Vec<u8>, u32>,
+}
+
+impl TtlOverrides {
+    pub fn new() -> Self {
+        TtlOverrides::default()
+    }
+
+    /// Pins the TTL of the records owned by `name` to `time_to_live`.
+    pub fn insert(&mut self, name: &DomainName, time_to_live: u32) {
+        self.overrides
+            .insert(name.wire_format.to_ascii_lowercase(), time_to_live);
+    }
+
+    /// Replaces the TTL of the records whose owner name has an override.
+    pub fn apply(&self, records: Vec<DnsAnswerRecord>) -> Vec<DnsAnswerRecord> {
+        records
+            .into_iter()
+            .map(|record| {
+                match self
+                    .overrides
+                    .get(&record.domain_name.wire_format.to_ascii_lowercase())
+                {
+                    Some(time_to_live) => DnsAnswerRecord {
+                        time_to_live: *time_to_live,
+                        ..record
+                    },
+                    None => record,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Errors raised while loading a blocklist.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BlocklistError {
+    #[error("invalid domain name on line {0}")]
+    InvalidName(usize),
+}
+
+/// Catches the questions about a set of blocked names and the names below them, e.g. blocking
+/// `ads.example.com` also blocks `tracker.ads.example.com`.
+///
+/// With the `regex` feature, it also catches the names matching the patterns of a
+/// [`RegexResolver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blocklist {
+    // Keyed by the lowercased wire format, as names are compared case-insensitively (RFC 4343)
+    names: HashSet<Vec<u8>>,
+    action: PolicyAction,
+    #[cfg(feature = "regex")]
+    patterns: Option<RegexResolver>,
+}
+
+impl Blocklist {
+    /// Creates an empty blocklist enforcing `action` on the names it catches.
+    pub fn new(action: PolicyAction) -> Self {
+        Blocklist {
+            names: HashSet::new(),
+            action,
+            #[cfg(feature = "regex")]
+            patterns: None,
+        }
+    }
+
+    /// Also catches the questions whose name matches `patterns`, which enforce their own actions.
+    /// The blocked names are looked up first.
+    #[cfg(feature = "regex")]
+    pub fn with_patterns(mut self, patterns: RegexResolver) -> Self {
+        self.patterns = Some(patterns);
+        self
+    }
+
+    /// Loads a list of names, one per line. Blank lines and lines starting with `#` are skipped.
+    pub fn load(text: &str, action: PolicyAction) -> Result<Self, BlocklistError> {
+        let mut blocklist = Blocklist::new(action);
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let name = line
+                .parse()
+                .map_err(|_| BlocklistError::InvalidName(index + 1))?;
+            blocklist.insert(&name);
+        }
+
+        Ok(blocklist)
+    }
+
+    /// Blocks `name` and every name below it.
+    pub fn insert(&mut self, name: &DomainName) {
+        self.names.insert(name.wire_format.to_ascii_lowercase());
+    }
+
+    /// Returns the action to enforce when the name of `question`, or one of its parents, is
+    /// blocked, or else the action of the first pattern the name matches.
+    pub fn lookup(&self, question: &DnsQuestion) -> Option<PolicyAction> {
+        let name = question.domain_name.wire_format.to_ascii_lowercase();
+        let mut position = 0;
+
+        // Try the name itself, then each of its parents, stopping before the root
+        while let Some(&label_length) = name.get(position).filter(|length| **length != 0) {
+            if self.names.contains(&name[position..]) {
+                return Some(self.action);
+            }
+            position += usize::from(label_length) + 1;
+        }
+
+        #[cfg(feature = "regex")]
+        if let Some(patterns) = &self.patterns {
+            return patterns.lookup(question);
+        }
+
+        None
+    }
+}
+
+/// Catches the questions whose name matches one of a set of regular expressions.
+///
+/// Names are matched in their dotted form without the trailing dot and lowercased, e.g.
+/// `ads.example.com`. The patterns are compiled once, when the resolver is created.
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct RegexResolver {
+    rules: Vec<(regex::Regex, PolicyAction)>,
+}
+
+/// Resolvers are equal when they have the same patterns, with the same actions, in the same order
+#[cfg(feature = "regex")]
+impl PartialEq for RegexResolver {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules.len() == other.rules.len()
+            && self.rules.iter().zip(&other.rules).all(
+                |((pattern, action), (other_pattern, other_action))| {
+                    pattern.as_str() == other_pattern.as_str() && action == other_action
+                },
+            )
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Eq for RegexResolver {}
+
+#[cfg(feature = "regex")]
+impl RegexResolver {
+    /// Compiles the patterns of `rules`, which are tried in order.
+    pub fn new<S: AsRef<str>>(rules: &[(S, PolicyAction)]) -> Result<Self, regex::Error> {
+        let rules = rules
+            .iter()
+            .map(|(pattern, action)| {
+                regex::Regex::new(pattern.as_ref()).map(|pattern| (pattern, *action))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RegexResolver { rules })
+    }
+
+    /// Returns the action of the first rule matching the name of `question`, if any.
+    pub fn lookup(&self, question: &DnsQuestion) -> Option<PolicyAction> {
+        let name = question.domain_name.label_segments.join(".").to_lowercase();
+
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&name))
+            .map(|(_, action)| *action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(name: &str, record_type: RecordType) -> DnsQuestion {
+        DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type,
+            class: Class::IN,
+        }
+    }
+
+    fn record(name: &str, time_to_live: u32) -> DnsAnswerRecord {
+        DnsAnswerRecord {
+            domain_name: name.parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+            time_to_live,
+            r_data_length: 4,
+            r_data: RData(vec![1, 2, 3, 4]),
+        }
+    }
+
+    #[test]
+    fn test_ttl_overrides() {
+        let mut overrides = TtlOverrides::new();
+        overrides.insert(&"volatile.example.com".parse().unwrap(), 5);
+
+        assert_eq!(
+            overrides.apply(vec![
+                record("VOLATILE.example.com", 3600),
+                record("stable.example.com", 3600),
+                record("volatile.example.com", 1),
+            ]),
+            vec![
+                record("VOLATILE.example.com", 5),
+                record("stable.example.com", 3600),
+                record("volatile.example.com", 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_policy_action() {
+        let sinkhole = PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED);
+        assert_eq!(sinkhole.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            sinkhole.answers(&question("ads.example.com", RecordType::A), 60),
+            vec![DnsAnswerRecord {
+                time_to_live: 60,
+                r_data: RData(vec![0, 0, 0, 0]),
+                ..record("ads.example.com", 0)
+            }]
+        );
+        assert!(sinkhole
+            .answers(&question("ads.example.com", RecordType::MX), 60)
+            .is_empty());
+
+        assert_eq!(
+            PolicyAction::NameError.response_code(),
+            ResponseCode::NameError
+        );
+        assert!(PolicyAction::NameError
+            .answers(&question("ads.example.com", RecordType::A), 60)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let blocklist = Blocklist::load(
+            "
+            # Advertising
+            ads.example.com
+            TRACKER.example.org.
+            ",
+            PolicyAction::NameError,
+        )
+        .unwrap();
+
+        assert_eq!(
+            blocklist.lookup(&question("ads.example.com", RecordType::A)),
+            Some(PolicyAction::NameError)
+        );
+        assert_eq!(
+            blocklist.lookup(&question("eu.Tracker.example.org", RecordType::AAAA)),
+            Some(PolicyAction::NameError)
+        );
+        assert_eq!(
+            blocklist.lookup(&question("example.com", RecordType::A)),
+            None
+        );
+        assert_eq!(
+            blocklist.lookup(&question("myads.example.com", RecordType::A)),
+            None
+        );
+
+        assert_eq!(
+            Blocklist::load("ads.example.com\nbad..name", PolicyAction::NameError),
+            Err(BlocklistError::InvalidName(2))
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_resolver() {
+        let resolver = RegexResolver::new(&[
+            (
+                r"(^|\.)ads\.",
+                PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED),
+            ),
+            (r"^tracker\d*\.", PolicyAction::NameError),
+        ])
+        .unwrap();
+
+        // A name containing "ads" is sinkholed
+        assert_eq!(
+            resolver.lookup(&question("eu.ADS.example.com", RecordType::A)),
+            Some(PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED))
+        );
+
+        let tracker_question = question("tracker42.example.com", RecordType::A);
+        assert_eq!(
+            resolver.lookup(&tracker_question),
+            Some(PolicyAction::NameError)
+        );
+
+        // A clean name passes through
+        assert_eq!(
+            resolver.lookup(&question("roads.example.com", RecordType::A)),
+            None
+        );
+
+        assert!(RegexResolver::new(&[("(unclosed", PolicyAction::NameError)]).is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_blocklist_with_patterns() {
+        let patterns = RegexResolver::new(&[(r"^tracker\d*\.", PolicyAction::NameError)]).unwrap();
+        let blocklist = Blocklist::load(
+            "ads.example.com",
+            PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED),
+        )
+        .unwrap()
+        .with_patterns(patterns);
+
+        assert_eq!(
+            blocklist.lookup(&question("ads.example.com", RecordType::A)),
+            Some(PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED))
+        );
+        assert_eq!(
+            blocklist.lookup(&question("tracker7.example.com", RecordType::A)),
+            Some(PolicyAction::NameError)
+        );
+        assert_eq!(
+            blocklist.lookup(&question("www.example.com", RecordType::A)),
+            None
+        );
+    }
+}
@@ -0,0 +1,1105 @@
+#[cfg(feature = "dot")]
+use std::sync::{Arc, Mutex, PoisonError};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "dot")]
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::dns::{
+    question::CacheKey, random_id, Class, DnsAnswerRecord, DnsError, DnsHeader, DnsHeaderBuilder,
+    DnsMessage, DnsQuestion, DomainName, RecordType, ResponseCode,
+};
+
+/// IPv4 addresses of the root name servers, `a.root-servers.net` through `m.root-servers.net`.
+const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+const DNS_PORT: u16 = 53;
+
+/// Port of the servers answering DNS over TLS (RFC 7858 section 3.1).
+#[cfg(feature = "dot")]
+pub const DOT_PORT: u16 = 853;
+
+/// Maximum number of queries sent while resolving a single question, name server lookups
+/// included. This bounds the work done when delegations loop back on themselves.
+const MAX_REFERRALS: usize = 32;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the response to a query, and how many times to send it before giving up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    /// Two attempts of five seconds each, as for the stub resolver of glibc.
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: QUERY_TIMEOUT,
+            attempts: 2,
+        }
+    }
+}
+
+/// Order in which the upstream servers are tried when forwarding a question to several of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamPolicy {
+    /// Always start with the first upstream server, the others being fallbacks.
+    #[default]
+    Ordered,
+    /// Start with the next upstream server at each question, to spread the load.
+    RoundRobin,
+}
+
+/// Resolves questions by walking the delegation chain down from the root name servers.
+pub struct Resolver {
+    root_hints: Vec<SocketAddr>,
+    name_server_port: u16,
+    retry_policy: RetryPolicy,
+    upstream_policy: UpstreamPolicy,
+    next_upstream: AtomicUsize,
+    responder: Option<Box<dyn MockResponder>>,
+    #[cfg(feature = "dot")]
+    dot_upstream: Option<DotUpstream>,
+}
+
+/// An upstream server reached over TLS, whose connection is kept open across queries.
+#[cfg(feature = "dot")]
+struct DotUpstream {
+    address: SocketAddr,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    connection: Mutex<Option<StreamOwned<ClientConnection, TcpStream>>>,
+}
+
+impl Resolver {
+    /// Creates a resolver starting from the given root servers, and reaching the name servers
+    /// it is referred to on `name_server_port`.
+    pub fn new(root_hints: Vec<SocketAddr>, name_server_port: u16) -> Self {
+        Resolver {
+            root_hints,
+            name_server_port,
+            retry_policy: RetryPolicy::default(),
+            upstream_policy: UpstreamPolicy::default(),
+            next_upstream: AtomicUsize::new(0),
+            responder: None,
+            #[cfg(feature = "dot")]
+            dot_upstream: None,
+        }
+    }
+
+    /// Creates a resolver forwarding to `upstream` over TLS (RFC 7858), usually listening on
+    /// [`DOT_PORT`]. The certificate of `upstream` must be valid for `server_name` and issued by
+    /// one of the roots trusted by Mozilla.
+    #[cfg(feature = "dot")]
+    pub fn new_dot(upstream: SocketAddr, server_name: &str) -> Result<Self, DnsError> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::new_dot_with_roots(upstream, server_name, roots)
+    }
+
+    /// Like [`Resolver::new_dot`], but trusting the certificates issued by `roots` instead, e.g.
+    /// those of a private certificate authority.
+    #[cfg(feature = "dot")]
+    pub fn new_dot_with_roots(
+        upstream: SocketAddr,
+        server_name: &str,
+        roots: RootCertStore,
+    ) -> Result<Self, DnsError> {
+        let invalid_name = || DnsError::InvalidDomainName(server_name.to_string());
+        let server_name =
+            ServerName::try_from(server_name.to_string()).map_err(|_| invalid_name())?;
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|error| DnsError::Tls(error.to_string()))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+        let mut resolver = Resolver::new(vec![upstream], upstream.port());
+        resolver.dot_upstream = Some(DotUpstream {
+            address: upstream,
+            server_name,
+            config: Arc::new(config),
+            connection: Mutex::new(None),
+        });
+        Ok(resolver)
+    }
+
+    /// Has `responder` answer every query instead of the name servers, so that no packet is sent.
+    pub fn with_responder<R: MockResponder + 'static>(mut self, responder: R) -> Self {
+        self.responder = Some(Box::new(responder));
+        self
+    }
+
+    /// Replaces the default retry policy of the queries sent over UDP.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Resolves `question` iteratively, starting from the root hints and following the NS
+    /// referrals found in the authority section (with their glue records from the additional
+    /// section) until a server answers.
+    ///
+    /// An empty vector means the name exists but has no records of the requested type.
+    pub fn resolve_recursive(
+        &self,
+        question: &DnsQuestion,
+    ) -> Result<Vec<DnsAnswerRecord>, DnsError> {
+        let mut queries_sent = 0;
+        self.resolve_iteratively(question, &mut queries_sent)
+    }
+
+    /// Forwards `question` to the `upstream` server, which is expected to resolve it recursively,
+    /// and returns its whole response so that negative answers can be told apart. The query asks
+    /// for recursion (RD), without which a recursive server only answers from its cache.
+    ///
+    /// The question goes over TLS when `upstream` is the server of a resolver created with
+    /// `Resolver::new_dot`, and over UDP otherwise.
+    pub fn forward(
+        &self,
+        upstream: SocketAddr,
+        question: &DnsQuestion,
+    ) -> Result<DnsMessage, DnsError> {
+        self.query(upstream, question, true)
+    }
+
+    /// Replaces the default, ordered, policy choosing the upstream server tried first.
+    pub fn with_upstream_policy(mut self, upstream_policy: UpstreamPolicy) -> Self {
+        self.upstream_policy = upstream_policy;
+        self
+    }
+
+    /// Forwards `question` to each of `upstreams` in turn, following the upstream policy, until
+    /// one of them gives a usable response: an answer, NODATA or NXDOMAIN.
+    pub fn forward_with_failover(
+        &self,
+        upstreams: &[SocketAddr],
+        question: &DnsQuestion,
+    ) -> Result<DnsMessage, DnsError> {
+        let first = match self.upstream_policy {
+            UpstreamPolicy::Ordered => 0,
+            UpstreamPolicy::RoundRobin => self.next_upstream.fetch_add(1, Ordering::Relaxed),
+        };
+
+        for offset in 0..upstreams.len() {
+            let upstream = upstreams[(first + offset) % upstreams.len()];
+            match self.forward(upstream, question) {
+                Ok(response)
+                    if matches!(
+                        response.rcode(),
+                        ResponseCode::NoError | ResponseCode::NameError
+                    ) =>
+                {
+                    return Ok(response)
+                }
+                _ => continue,
+            }
+        }
+
+        Err(DnsError::AllUpstreamsFailed)
+    }
+
+    /// Transfers the whole of `zone` from `server` over TCP (RFC 5936), returning its records in
+    /// the order they were sent: starting with the SOA record of the zone and ending with it.
+    pub fn zone_transfer(
+        &self,
+        server: SocketAddr,
+        zone: &str,
+    ) -> Result<Vec<DnsAnswerRecord>, DnsError> {
+        let query = DnsMessage::axfr_query(zone)?;
+        let query_bytes = query.to_vec();
+        let query_length =
+            u16::try_from(query_bytes.len()).map_err(|_| DnsError::MalformedPacket)?;
+
+        let mut stream = TcpStream::connect_timeout(&server, self.retry_policy.timeout)?;
+        stream.set_read_timeout(Some(self.retry_policy.timeout))?;
+        stream.write_all(&[&query_length.to_be_bytes()[..], &query_bytes].concat())?;
+
+        read_zone_transfer(&mut stream, query.header.packet_identifier)
+    }
+
+    fn resolve_iteratively(
+        &self,
+        question: &DnsQuestion,
+        queries_sent: &mut usize,
+    ) -> Result<Vec<DnsAnswerRecord>, DnsError> {
+        let mut name_servers = self.root_hints.clone();
+
+        loop {
+            if *queries_sent >= MAX_REFERRALS {
+                return Err(DnsError::TooManyReferrals(MAX_REFERRALS));
+            }
+            *queries_sent += 1;
+
+            let response = self.query_name_servers(&name_servers, question)?;
+            if response.header.response_code != ResponseCode::NoError {
+                return Err(DnsError::UpstreamFailure(response.header.response_code));
+            }
+            if !response.answers.is_empty() {
+                return Ok(response.answers);
+            }
+
+            let name_server_names: Vec<DomainName> = response
+                .authorities
+                .iter()
+                .filter(|record| record.record_type == RecordType::NS)
+                .filter_map(|record| record.r_data.as_ns())
+                .collect();
+            if name_server_names.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let glue_addresses: Vec<SocketAddr> = response
+                .additionals
+                .iter()
+                .filter(|record| record.record_type == RecordType::A)
+                .filter(|record| {
+                    name_server_names
+                        .iter()
+                        .any(|name| same_name(name, &record.domain_name))
+                })
+                .filter_map(|record| record.r_data.as_a())
+                .map(|address| SocketAddr::new(IpAddr::V4(address), self.name_server_port))
+                .collect();
+
+            name_servers = if glue_addresses.is_empty() {
+                self.resolve_name_server_addresses(&name_server_names, queries_sent)?
+            } else {
+                glue_addresses
+            };
+        }
+    }
+
+    /// Looks up the addresses of the first name server in `names` that can be resolved, for
+    /// referrals that came without glue records.
+    fn resolve_name_server_addresses(
+        &self,
+        names: &[DomainName],
+        queries_sent: &mut usize,
+    ) -> Result<Vec<SocketAddr>, DnsError> {
+        for name in names {
+            let question = DnsQuestion {
+                domain_name: name.clone(),
+                record_type: RecordType::A,
+                class: Class::IN,
+            };
+
+            let addresses: Vec<SocketAddr> = self
+                .resolve_iteratively(&question, queries_sent)?
+                .iter()
+                .filter(|record| record.record_type == RecordType::A)
+                .filter_map(|record| record.r_data.as_a())
+                .map(|address| SocketAddr::new(IpAddr::V4(address), self.name_server_port))
+                .collect();
+
+            if !addresses.is_empty() {
+                return Ok(addresses);
+            }
+        }
+
+        Err(DnsError::NoNameServers)
+    }
+
+    /// Sends `question` to each of `name_servers` in turn, returning the first response.
+    fn query_name_servers(
+        &self,
+        name_servers: &[SocketAddr],
+        question: &DnsQuestion,
+    ) -> Result<DnsMessage, DnsError> {
+        let mut last_error = DnsError::NoNameServers;
+
+        for name_server in name_servers {
+            match self.query(*name_server, question, false) {
+                Ok(response) => return Ok(response),
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Sends `question` to `name_server` under a random packet identifier and waits for the
+    /// response carrying that identifier and question. Datagrams from other sources, with other
+    /// identifiers or about other questions are discarded, as they cannot be replies to this query
+    /// (RFC 5452 section 3).
+    ///
+    /// The query is sent again, unchanged, each time the timeout of the retry policy expires. It
+    /// asks for recursion (RD) only when `recursion_desired` is set, as when forwarding: the
+    /// iterative walk asks authoritative servers, which do not recurse.
+    fn query(
+        &self,
+        name_server: SocketAddr,
+        question: &DnsQuestion,
+        recursion_desired: bool,
+    ) -> Result<DnsMessage, DnsError> {
+        if let Some(responder) = &self.responder {
+            return responder.respond(name_server, question);
+        }
+
+        #[cfg(feature = "dot")]
+        if let Some(dot_upstream) = self
+            .dot_upstream
+            .as_ref()
+            .filter(|dot_upstream| dot_upstream.address == name_server)
+        {
+            return dot_upstream.query(question, recursion_desired, self.retry_policy.timeout);
+        }
+
+        let local_address = match name_server {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local_address)?;
+
+        let packet_identifier = random_id();
+        let header = DnsHeaderBuilder::new()
+            .id(packet_identifier)
+            .recursion_desired(recursion_desired)
+            .questions(1)
+            .build();
+        let query = [header.to_bytes().to_vec(), question.to_bytes()].concat();
+
+        for _ in 0..self.retry_policy.attempts {
+            socket.send_to(&query, name_server)?;
+
+            let deadline = Instant::now() + self.retry_policy.timeout;
+            if let Some(response) =
+                wait_for_response(&socket, deadline, name_server, packet_identifier, question)?
+            {
+                return Ok(response);
+            }
+        }
+
+        Err(DnsError::Timeout)
+    }
+}
+
+impl Default for Resolver {
+    /// A resolver starting from the real root name servers.
+    fn default() -> Self {
+        Resolver::new(
+            ROOT_HINTS
+                .iter()
+                .map(|address| SocketAddr::new(IpAddr::V4(*address), DNS_PORT))
+                .collect(),
+            DNS_PORT,
+        )
+    }
+}
+
+/// Answers the queries of a resolver in place of the name servers, e.g. to test the resolution
+/// logic without any network, see [`Resolver::with_responder`].
+pub trait MockResponder: Send + Sync {
+    /// Returns the response `name_server` would give to `question`.
+    fn respond(
+        &self,
+        name_server: SocketAddr,
+        question: &DnsQuestion,
+    ) -> Result<DnsMessage, DnsError>;
+}
+
+/// Answers each question with the records it was given for it, whatever the name server asked,
+/// and with NXDOMAIN when it was given none. Names are matched case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResponder {
+    answers: HashMap<CacheKey, Vec<DnsAnswerRecord>>,
+}
+
+impl StaticResponder {
+    pub fn new() -> Self {
+        StaticResponder::default()
+    }
+
+    /// Answers `question` with `answers`, an empty vector giving a NODATA response.
+    pub fn with_answers(mut self, question: &DnsQuestion, answers: Vec<DnsAnswerRecord>) -> Self {
+        self.answers.insert(question.cache_key(), answers);
+        self
+    }
+}
+
+impl MockResponder for StaticResponder {
+    fn respond(
+        &self,
+        _name_server: SocketAddr,
+        question: &DnsQuestion,
+    ) -> Result<DnsMessage, DnsError> {
+        let query = DnsMessage::builder()
+            .header(DnsHeaderBuilder::new().id(random_id()).build())
+            .add_question(question.clone())
+            .build();
+
+        match self.answers.get(&question.cache_key()) {
+            Some(answers) => Ok(query.build_reply_with(|_| answers.clone())),
+            None => {
+                let mut reply = query.build_reply();
+                reply.set_rcode(ResponseCode::NameError);
+                Ok(reply)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "dot")]
+impl DotUpstream {
+    /// Sends `question` over the open connection, or over a new one if there is none.
+    ///
+    /// The server may close idle connections at any time (RFC 7858 section 3.4), so a query failing
+    /// on a reused connection is sent once more over a new one.
+    fn query(
+        &self,
+        question: &DnsQuestion,
+        recursion_desired: bool,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let packet_identifier = random_id();
+        let header = DnsHeaderBuilder::new()
+            .id(packet_identifier)
+            .recursion_desired(recursion_desired)
+            .questions(1)
+            .build();
+        let query = [header.to_bytes().to_vec(), question.to_bytes()].concat();
+        let query_length = u16::try_from(query.len()).map_err(|_| DnsError::MalformedPacket)?;
+        let framed_query = [&query_length.to_be_bytes()[..], &query].concat();
+
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (mut stream, reused) = match connection.take() {
+            Some(stream) => (stream, true),
+            None => (self.connect(timeout)?, false),
+        };
+
+        let response = match exchange(&mut stream, &framed_query, packet_identifier, question) {
+            Err(DnsError::Io(_)) if reused => {
+                stream = self.connect(timeout)?;
+                exchange(&mut stream, &framed_query, packet_identifier, question)?
+            }
+            result => result?,
+        };
+        *connection = Some(stream);
+
+        Ok(response)
+    }
+
+    /// Opens a connection to the server and completes the TLS handshake, verifying the
+    /// certificate of the server.
+    fn connect(
+        &self,
+        timeout: Duration,
+    ) -> Result<StreamOwned<ClientConnection, TcpStream>, DnsError> {
+        let mut socket = TcpStream::connect_timeout(&self.address, timeout)?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+
+        let mut connection =
+            ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())
+                .map_err(|error| DnsError::Tls(error.to_string()))?;
+        while connection.is_handshaking() {
+            connection
+                .complete_io(&mut socket)
+                .map_err(|error| DnsError::Tls(error.to_string()))?;
+        }
+
+        Ok(StreamOwned::new(connection, socket))
+    }
+}
+
+/// Sends a length-prefixed query over `stream` and reads the response, which must carry
+/// `packet_identifier` and `question`.
+#[cfg(feature = "dot")]
+fn exchange<S: Read + Write>(
+    stream: &mut S,
+    framed_query: &[u8],
+    packet_identifier: u16,
+    question: &DnsQuestion,
+) -> Result<DnsMessage, DnsError> {
+    stream.write_all(framed_query)?;
+    stream.flush()?;
+
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let response = DnsMessage::from_reader(stream, usize::from(u16::from_be_bytes(length)))?;
+    let answers_question = match response.questions.as_slice() {
+        [answered] => same_question(answered, question),
+        _ => false,
+    };
+    if response.header.packet_identifier != packet_identifier || !answers_question {
+        return Err(DnsError::MalformedPacket);
+    }
+
+    Ok(response)
+}
+
+/// Waits until `deadline` for the response to the query with `packet_identifier` and `question`
+/// sent to `name_server`, returning `None` if it does not come in time.
+fn wait_for_response(
+    socket: &UdpSocket,
+    deadline: Instant,
+    name_server: SocketAddr,
+    packet_identifier: u16,
+    question: &DnsQuestion,
+) -> Result<Option<DnsMessage>, DnsError> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let mut buf = [0; 512];
+        let source = match socket.recv_from(&mut buf) {
+            Ok((_, source)) => source,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if source != name_server {
+            continue;
+        }
+
+        match DnsHeader::new(&buf) {
+            Ok(header) if header.packet_identifier == packet_identifier => {}
+            _ => continue,
+        }
+        // Anyone can send garbage bearing the right identifier; only a reply can end the wait
+        let Ok(response) = DnsMessage::new(&buf) else {
+            continue;
+        };
+        let answers_question = match response.questions.as_slice() {
+            [answered] => same_question(answered, question),
+            _ => false,
+        };
+        if answers_question {
+            return Ok(Some(response));
+        }
+    }
+}
+
+/// Reads the length-prefixed messages of a zone transfer from `reader` until the SOA record that
+/// opened the transfer is sent again.
+fn read_zone_transfer<R: Read>(
+    reader: &mut R,
+    packet_identifier: u16,
+) -> Result<Vec<DnsAnswerRecord>, DnsError> {
+    let mut records: Vec<DnsAnswerRecord> = Vec::new();
+
+    loop {
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let response = DnsMessage::from_reader(reader, usize::from(u16::from_be_bytes(length)))?;
+        if response.header.packet_identifier != packet_identifier {
+            return Err(DnsError::MalformedPacket);
+        }
+        if response.header.response_code != ResponseCode::NoError {
+            return Err(DnsError::UpstreamFailure(response.header.response_code));
+        }
+
+        for record in response.answers {
+            let is_soa = record.record_type == RecordType::SOA;
+            if records.is_empty() && !is_soa {
+                return Err(DnsError::MalformedPacket);
+            }
+
+            records.push(record);
+            if is_soa && records.len() > 1 {
+                return Ok(records);
+            }
+        }
+    }
+}
+
+fn same_question(first: &DnsQuestion, second: &DnsQuestion) -> bool {
+    same_name(&first.domain_name, &second.domain_name)
+        && first.record_type == second.record_type
+        && first.class == second.class
+}
+
+/// Domain names are compared case-insensitively (RFC 4343).
+fn same_name(first: &DomainName, second: &DomainName) -> bool {
+    first.wire_format.eq_ignore_ascii_case(&second.wire_format)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    #[cfg(feature = "dot")]
+    use rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig, ServerConnection,
+    };
+
+    use crate::dns::answer_record::RData;
+
+    use super::*;
+
+    fn domain_name(name: &str) -> DomainName {
+        let wire_format: Vec<u8> = name
+            .split('.')
+            .flat_map(|label| [&[label.len() as u8], label.as_bytes()].concat())
+            .chain([0])
+            .collect();
+
+        DomainName::new(&wire_format).unwrap()
+    }
+
+    fn record(name: &str, record_type: RecordType, r_data: Vec<u8>) -> DnsAnswerRecord {
+        DnsAnswerRecord {
+            domain_name: domain_name(name),
+            record_type,
+            class: Class::IN,
+            time_to_live: 60,
+            r_data_length: r_data.len(),
+            r_data: RData(r_data),
+        }
+    }
+
+    /// Answers a single query received on `socket` with the given sections.
+    fn serve_once(
+        socket: UdpSocket,
+        answers: Vec<DnsAnswerRecord>,
+        authorities: Vec<DnsAnswerRecord>,
+        additionals: Vec<DnsAnswerRecord>,
+    ) -> thread::JoinHandle<DnsQuestion> {
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = socket.recv_from(&mut buf).unwrap();
+            let query = DnsMessage::new(&buf).unwrap();
+            // Servers walked iteratively are not asked to recurse
+            assert!(!query.header.recursion_desired);
+
+            let mut reply = query.build_reply_with(|_| answers.clone());
+            reply.header.authority_record_count = authorities.len();
+            reply.authorities = authorities;
+            reply.header.additional_record_count = additionals.len();
+            reply.additionals = additionals;
+
+            socket.send_to(&reply.to_vec(), source).unwrap();
+            query.first_question().unwrap().clone()
+        })
+    }
+
+    #[test]
+    fn test_resolve_recursive_follows_delegation() {
+        let root_server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = root_server.local_addr().unwrap().port();
+        let example_server = UdpSocket::bind(("127.0.0.2", port)).unwrap();
+
+        // The root refers us to ns.example.com, whose address comes as glue
+        let root_handle = serve_once(
+            root_server,
+            Vec::new(),
+            vec![record(
+                "example.com",
+                RecordType::NS,
+                domain_name("ns.example.com").wire_format,
+            )],
+            vec![record("ns.example.com", RecordType::A, vec![127, 0, 0, 2])],
+        );
+        // ns.example.com is authoritative and answers
+        let example_handle = serve_once(
+            example_server,
+            vec![record("www.example.com", RecordType::A, vec![1, 2, 3, 4])],
+            Vec::new(),
+            Vec::new(),
+        );
+
+        let question = DnsQuestion {
+            domain_name: domain_name("www.example.com"),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let resolver = Resolver::new(vec![SocketAddr::from(([127, 0, 0, 1], port))], port);
+
+        assert_eq!(
+            resolver.resolve_recursive(&question),
+            Ok(vec![record(
+                "www.example.com",
+                RecordType::A,
+                vec![1, 2, 3, 4]
+            )])
+        );
+        assert_eq!(root_handle.join().unwrap(), question);
+        assert_eq!(example_handle.join().unwrap(), question);
+    }
+
+    #[test]
+    fn test_resolve_recursive_with_static_responder() {
+        let question =
+            DnsQuestion::from_parts("www.example.com", RecordType::A, Class::IN).unwrap();
+        let answer = record("www.example.com", RecordType::A, vec![1, 2, 3, 4]);
+        // Nothing listens on the root hint, every query goes to the responder
+        let resolver = Resolver::new(vec![SocketAddr::from(([192, 0, 2, 1], 53))], 53)
+            .with_responder(StaticResponder::new().with_answers(&question, vec![answer.clone()]));
+
+        assert_eq!(resolver.resolve_recursive(&question), Ok(vec![answer]));
+
+        let unknown = DnsQuestion::from_parts("example.org", RecordType::A, Class::IN).unwrap();
+        assert_eq!(
+            resolver.resolve_recursive(&unknown),
+            Err(DnsError::UpstreamFailure(ResponseCode::NameError))
+        );
+        assert_eq!(
+            resolver
+                .forward(SocketAddr::from(([192, 0, 2, 2], 53)), &unknown)
+                .map(|response| response.rcode()),
+            Ok(ResponseCode::NameError)
+        );
+    }
+
+    #[test]
+    fn test_forward_discards_spoofed_responses() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // Bogus replies with the wrong identifier or question, or that do not parse, come first
+        let upstream_handle = thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let query = DnsMessage::new(&buf).unwrap();
+            // The upstream server is asked to recurse
+            assert!(query.header.recursion_desired);
+            let answer = |address| vec![record("www.example.com", RecordType::A, address)];
+
+            let mut wrong_identifier = query.build_reply_with(|_| answer(vec![6, 6, 6, 6]));
+            wrong_identifier.header.packet_identifier =
+                query.header.packet_identifier.wrapping_add(1);
+            upstream
+                .send_to(&wrong_identifier.to_vec(), source)
+                .unwrap();
+
+            let mut wrong_question = query.build_reply_with(|_| answer(vec![6, 6, 6, 6]));
+            wrong_question.questions[0].record_type = RecordType::AAAA;
+            upstream.send_to(&wrong_question.to_vec(), source).unwrap();
+
+            // Garbage with the right identifier, announcing more questions than it carries
+            let [first_byte, second_byte] = query.header.packet_identifier.to_be_bytes();
+            let garbage = [first_byte, second_byte, 0x81, 0x80, 0xff, 0xff, 0xde, 0xad];
+            upstream.send_to(&garbage, source).unwrap();
+
+            let reply = query.build_reply_with(|_| answer(vec![1, 2, 3, 4]));
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let question = DnsQuestion {
+            domain_name: domain_name("www.example.com"),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let response = Resolver::default()
+            .forward(upstream_address, &question)
+            .unwrap();
+        upstream_handle.join().unwrap();
+
+        assert_eq!(
+            response.answers,
+            [record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+        );
+    }
+
+    #[test]
+    fn test_zone_transfer() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_address = listener.local_addr().unwrap();
+        let soa = record("example.com", RecordType::SOA, vec![0xab; 22]);
+        let zone = vec![
+            soa.clone(),
+            record("www.example.com", RecordType::A, vec![1, 2, 3, 4]),
+            record("ftp.example.com", RecordType::A, vec![5, 6, 7, 8]),
+            soa,
+        ];
+
+        // The server streams the zone over two messages
+        let messages = [zone[..2].to_vec(), zone[2..].to_vec()];
+        let server_handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            let query =
+                DnsMessage::from_reader(&mut stream, u16::from_be_bytes(length).into()).unwrap();
+
+            for answers in messages {
+                let reply = query.build_reply_with(|_| answers.clone()).to_vec();
+                stream
+                    .write_all(&(reply.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+            query.first_question().unwrap().clone()
+        });
+
+        let records = Resolver::default()
+            .zone_transfer(server_address, "example.com")
+            .unwrap();
+        assert_eq!(records, zone);
+        assert_eq!(
+            server_handle.join().unwrap(),
+            DnsQuestion::from_parts("example.com", RecordType::AXFR, Class::IN).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_forward_retries_after_timeout() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // The first datagram is dropped, the retry is answered
+        let upstream_handle = thread::spawn(move || {
+            let mut buf = [0; 512];
+            upstream.recv_from(&mut buf).unwrap();
+            let dropped = DnsMessage::new(&buf).unwrap();
+
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let retry = DnsMessage::new(&buf).unwrap();
+            let reply = retry.build_reply_with(|_| {
+                vec![record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+            });
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+
+            (dropped, retry)
+        });
+
+        let question = DnsQuestion {
+            domain_name: domain_name("www.example.com"),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let resolver = Resolver::default().with_retry_policy(RetryPolicy {
+            timeout: Duration::from_millis(100),
+            attempts: 2,
+        });
+
+        let response = resolver.forward(upstream_address, &question).unwrap();
+        assert_eq!(
+            response.answers,
+            [record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+        );
+        let (dropped, retry) = upstream_handle.join().unwrap();
+        assert_eq!(dropped, retry);
+
+        // Without any answer the resolver gives up after its last attempt
+        let silent_upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            resolver.forward(silent_upstream.local_addr().unwrap(), &question),
+            Err(DnsError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_forward_with_failover() {
+        // Nothing answers on the first upstream
+        let unreachable = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstreams = [
+            unreachable.local_addr().unwrap(),
+            upstream.local_addr().unwrap(),
+        ];
+
+        let upstream_handle = thread::spawn(move || {
+            for _ in 0..2 {
+                let mut buf = [0; 512];
+                let (_, source) = upstream.recv_from(&mut buf).unwrap();
+                let reply = DnsMessage::new(&buf).unwrap().build_reply_with(|_| {
+                    vec![record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+                });
+                upstream.send_to(&reply.to_vec(), source).unwrap();
+            }
+        });
+
+        let question = DnsQuestion {
+            domain_name: domain_name("www.example.com"),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let retry_policy = RetryPolicy {
+            timeout: Duration::from_millis(100),
+            attempts: 1,
+        };
+
+        let resolver = Resolver::default().with_retry_policy(retry_policy);
+        let response = resolver.forward_with_failover(&upstreams, &question);
+        assert_eq!(
+            response.map(|response| response.answers),
+            Ok(vec![record(
+                "www.example.com",
+                RecordType::A,
+                vec![1, 2, 3, 4]
+            )])
+        );
+
+        // Round-robin starts with the second upstream on the second question
+        let resolver = Resolver::default()
+            .with_retry_policy(retry_policy)
+            .with_upstream_policy(UpstreamPolicy::RoundRobin);
+        resolver.next_upstream.store(1, Ordering::Relaxed);
+        assert!(resolver
+            .forward_with_failover(&upstreams, &question)
+            .is_ok());
+        upstream_handle.join().unwrap();
+
+        assert_eq!(
+            resolver.forward_with_failover(&upstreams[..1], &question),
+            Err(DnsError::AllUpstreamsFailed)
+        );
+    }
+
+    #[test]
+    fn test_resolve_recursive_stops_on_referral_loop() {
+        let root_server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = root_server.local_addr().unwrap().port();
+
+        // The server keeps referring us back to itself
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (_, source) = root_server.recv_from(&mut buf).unwrap();
+            let mut reply = DnsMessage::new(&buf)
+                .unwrap()
+                .build_reply_with(|_| Vec::new());
+            reply.authorities = vec![record(
+                "com",
+                RecordType::NS,
+                domain_name("ns.com").wire_format,
+            )];
+            reply.header.authority_record_count = 1;
+            reply.additionals = vec![record("ns.com", RecordType::A, vec![127, 0, 0, 1])];
+            reply.header.additional_record_count = 1;
+            root_server.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let question = DnsQuestion {
+            domain_name: domain_name("www.example.com"),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let resolver = Resolver::new(vec![SocketAddr::from(([127, 0, 0, 1], port))], port);
+
+        assert_eq!(
+            resolver.resolve_recursive(&question),
+            Err(DnsError::TooManyReferrals(MAX_REFERRALS))
+        );
+    }
+
+    /// Answers `queries` length-prefixed queries over a single TLS connection accepted on
+    /// `listener`, with the certificate of `dns.example.test`.
+    #[cfg(feature = "dot")]
+    fn serve_dot(listener: std::net::TcpListener, queries: usize) -> thread::JoinHandle<()> {
+        let certificates =
+            CertificateDer::pem_slice_iter(include_bytes!("../testdata/dot/server.pem"))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        let key =
+            PrivateKeyDer::from_pem_slice(include_bytes!("../testdata/dot/server.key")).unwrap();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(certificates, key)
+                .unwrap();
+
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+
+            for _ in 0..queries {
+                let mut length = [0; 2];
+                if stream.read_exact(&mut length).is_err() {
+                    return;
+                }
+                let query = DnsMessage::from_reader(&mut stream, u16::from_be_bytes(length).into())
+                    .unwrap();
+                let reply = query
+                    .build_reply_with(|_| {
+                        vec![record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+                    })
+                    .to_vec();
+                stream
+                    .write_all(&[&(reply.len() as u16).to_be_bytes()[..], &reply].concat())
+                    .unwrap();
+            }
+        })
+    }
+
+    #[cfg(feature = "dot")]
+    #[test]
+    fn test_forward_over_tls_reuses_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        // A single connection is accepted, for both questions
+        let server_handle = serve_dot(listener, 2);
+
+        let mut roots = RootCertStore::empty();
+        for certificate in CertificateDer::pem_slice_iter(include_bytes!("../testdata/dot/ca.pem"))
+        {
+            roots.add(certificate.unwrap()).unwrap();
+        }
+        let resolver = Resolver::new_dot_with_roots(upstream, "dns.example.test", roots).unwrap();
+
+        let question =
+            DnsQuestion::from_parts("www.example.com", RecordType::A, Class::IN).unwrap();
+        for _ in 0..2 {
+            let response = resolver.forward(upstream, &question).unwrap();
+            assert_eq!(
+                response.answers,
+                [record("www.example.com", RecordType::A, vec![1, 2, 3, 4])]
+            );
+        }
+        server_handle.join().unwrap();
+    }
+
+    #[cfg(feature = "dot")]
+    #[test]
+    fn test_forward_over_tls_verifies_certificate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = listener.local_addr().unwrap();
+        let server_handle = serve_dot(listener, 1);
+
+        // The test authority is not among the public roots
+        let resolver = Resolver::new_dot(upstream, "dns.example.test").unwrap();
+        let question =
+            DnsQuestion::from_parts("www.example.com", RecordType::A, Class::IN).unwrap();
+        assert!(matches!(
+            resolver.forward(upstream, &question),
+            Err(DnsError::Tls(_))
+        ));
+        drop(resolver);
+
+        assert!(matches!(
+            Resolver::new_dot(upstream, "not a name"),
+            Err(DnsError::InvalidDomainName(_))
+        ));
+        let _ = server_handle.join();
+    }
+}
@@ -1,31 +1,361 @@
-use std::net::UdpSocket;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
 
-use crate::dns::DnsMessage;
+use thiserror::Error;
+
+use crate::{
+    cache::{CacheHit, DnsCache},
+    dns::{
+        answer_record::RData, opt_record::MAX_UDP_PAYLOAD_SIZE, Class, DnsAnswerRecord, DnsError,
+        DnsHeader, DnsMessage, DnsQuestion, DomainName, OpCode, RecordType, ResponseCode, Soa,
+        Zone,
+    },
+    observer::{NoopObserver, QueryObserver},
+    policy::{Blocklist, PolicyAction, TtlOverrides},
+    resolver::{Resolver, UpstreamPolicy},
+};
+
+#[cfg(feature = "regex")]
+use crate::policy::RegexResolver;
+
+/// Address the server listens on when `--listen` is not given.
+const DEFAULT_LISTEN_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 2053);
+
+/// TTL of the records the server synthesizes when `ServerConfig::default_ttl` is not changed.
+const DEFAULT_TTL: u32 = 60;
+
+/// How often the server loop wakes up to check whether it was asked to shut down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Window over which `--rate-limit` counts the queries of each source.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Errors raised while reading the command-line arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ArgsError {
+    #[error("unknown argument `{0}`")]
+    UnknownArgument(String),
+    #[error("missing value for `{0}`")]
+    MissingValue(String),
+    #[error("invalid address `{1}` for `{0}`, expected <ip>:<port>")]
+    InvalidAddress(String, String),
+    #[error("invalid TTL override `{0}`, expected <name>=<seconds>")]
+    InvalidTtlOverride(String),
+    #[error("invalid rate limit `{0}`, expected a number of queries per second")]
+    InvalidRateLimit(String),
+    #[error("invalid message size `{0}`, expected a number of bytes")]
+    InvalidMessageSize(String),
+}
+
+/// Runtime settings of the server, as given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Address the UDP socket is bound to.
+    pub listen: SocketAddr,
+    /// Upstream servers the questions are forwarded to, the next one being tried when one fails.
+    pub resolvers: Vec<SocketAddr>,
+    /// Order in which the upstream servers are tried.
+    pub upstream_policy: UpstreamPolicy,
+    /// Zone file holding the records this server answers with authority, which are looked up
+    /// before forwarding. With neither a zone nor a resolver, every question gets the placeholder
+    /// answer.
+    pub zone_file: Option<PathBuf>,
+    /// TTLs replacing the ones of the forwarded answers for specific names.
+    pub ttl_overrides: TtlOverrides,
+    /// Whether expired answers are served when the upstream server fails (RFC 8767).
+    pub serve_stale: bool,
+    /// Number of queries per second answered for each source address, the others being dropped.
+    pub rate_limit: Option<u32>,
+    /// File listing the names, one per line, whose questions are answered with `block_action`
+    /// along with those of the names below them.
+    pub blocklist_file: Option<PathBuf>,
+    /// What to answer for the blocked names: `0.0.0.0` for A questions unless
+    /// `--block-nxdomain` asks for NXDOMAIN.
+    pub block_action: PolicyAction,
+    /// Regular expressions whose matching names are blocked with `block_action` as well, see
+    /// [`RegexResolver`].
+    #[cfg(feature = "regex")]
+    pub block_patterns: Vec<String>,
+    /// TTL of the records synthesized by the server: the answers for the blocked names and the
+    /// SOA record of the negative answers.
+    pub default_ttl: u32,
+    /// SOA record put in the authority section of the negative answers, so that other resolvers
+    /// know for how long they can cache them (RFC 2308 section 3).
+    pub soa: Option<Soa>,
+    /// Owner of the SOA record of the negative answers: the apex of the zone it describes. The
+    /// root by default, which holds every name.
+    pub soa_owner: DomainName,
+    /// Largest message the server accepts to parse, in bytes. Datagrams whose header declares
+    /// more questions and records than fit in it, or in the datagram itself, are dropped unread.
+    pub max_message_size: usize,
+}
+
+impl ServerConfig {
+    /// Parses `--listen <addr:port>`, `--zone <path>`, `--serve-stale`, `--round-robin`,
+    /// `--rate-limit <queries>`, `--max-message-size <bytes>`, `--blocklist <path>`,
+    /// `--block-nxdomain` and any number of
+    /// `--resolver <addr:port>` and `--ttl-override <name>=<seconds>` from the program arguments,
+    /// not including the program name. With the `regex` feature, any number of
+    /// `--block-regex <pattern>` is accepted too.
+    pub fn from_args<I>(args: I) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = ServerConfig::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--listen" => config.listen = parse_address(&flag, args.next())?,
+                "--resolver" => config.resolvers.push(parse_address(&flag, args.next())?),
+                "--round-robin" => config.upstream_policy = UpstreamPolicy::RoundRobin,
+                "--zone" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ArgsError::MissingValue(flag.clone()))?;
+                    config.zone_file = Some(PathBuf::from(path));
+                }
+                "--serve-stale" => config.serve_stale = true,
+                "--blocklist" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ArgsError::MissingValue(flag.clone()))?;
+                    config.blocklist_file = Some(PathBuf::from(path));
+                }
+                "--block-nxdomain" => config.block_action = PolicyAction::NameError,
+                #[cfg(feature = "regex")]
+                "--block-regex" => {
+                    let pattern = args
+                        .next()
+                        .ok_or_else(|| ArgsError::MissingValue(flag.clone()))?;
+                    config.block_patterns.push(pattern);
+                }
+                "--rate-limit" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| ArgsError::MissingValue(flag.clone()))?;
+                    let rate_limit = value
+                        .parse()
+                        .map_err(|_| ArgsError::InvalidRateLimit(value))?;
+                    config.rate_limit = Some(rate_limit);
+                }
+                "--max-message-size" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| ArgsError::MissingValue(flag.clone()))?;
+                    config.max_message_size = value
+                        .parse()
+                        .map_err(|_| ArgsError::InvalidMessageSize(value))?;
+                }
+                "--ttl-override" => {
+                    let (name, time_to_live) = parse_ttl_override(&flag, args.next())?;
+                    config.ttl_overrides.insert(&name, time_to_live);
+                }
+                _ => return Err(ArgsError::UnknownArgument(flag)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: DEFAULT_LISTEN_ADDRESS,
+            resolvers: Vec::new(),
+            upstream_policy: UpstreamPolicy::Ordered,
+            zone_file: None,
+            ttl_overrides: TtlOverrides::new(),
+            serve_stale: false,
+            rate_limit: None,
+            blocklist_file: None,
+            block_action: PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED),
+            #[cfg(feature = "regex")]
+            block_patterns: Vec::new(),
+            default_ttl: DEFAULT_TTL,
+            soa: None,
+            soa_owner: DomainName::root(),
+            max_message_size: usize::from(MAX_UDP_PAYLOAD_SIZE),
+        }
+    }
+}
+
+/// Parses the `<ip>:<port>` value following `flag`.
+fn parse_address(flag: &str, value: Option<String>) -> Result<SocketAddr, ArgsError> {
+    let value = value.ok_or_else(|| ArgsError::MissingValue(flag.to_string()))?;
+
+    value
+        .parse()
+        .map_err(|_| ArgsError::InvalidAddress(flag.to_string(), value))
+}
+
+/// Parses the `<name>=<seconds>` value following `flag`.
+fn parse_ttl_override(flag: &str, value: Option<String>) -> Result<(DomainName, u32), ArgsError> {
+    let value = value.ok_or_else(|| ArgsError::MissingValue(flag.to_string()))?;
+
+    value
+        .split_once('=')
+        .and_then(|(name, time_to_live)| Some((name.parse().ok()?, time_to_live.parse().ok()?)))
+        .ok_or(ArgsError::InvalidTtlOverride(value))
+}
 
 /// Starts and runs the DNS server
 ///
-/// Binds to the specified address and handles incoming DNS queries in a loop.
-/// For each query, it responds with a basic DNS header.
-pub fn run() -> std::io::Result<()> {
+/// Binds to the configured address and handles incoming DNS queries in a loop.
+/// For each query, it responds with a reply sized to the UDP payload the client advertised.
+pub fn run(config: &ServerConfig) -> std::io::Result<()> {
+    run_with_observer(config, &NoopObserver)
+}
+
+/// Same as [`run`], telling `observer` about every query and reply.
+pub fn run_with_observer(
+    config: &ServerConfig,
+    observer: &dyn QueryObserver,
+) -> std::io::Result<()> {
+    run_until(config, observer, &AtomicBool::new(false))
+}
+
+/// Same as [`run_with_observer`], returning once `shutdown` is set, e.g. by another thread. The
+/// socket is closed on return.
+pub fn run_until(
+    config: &ServerConfig,
+    observer: &dyn QueryObserver,
+    shutdown: &AtomicBool,
+) -> std::io::Result<()> {
     println!("Logs from your program will appear here!");
 
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053")?;
-    let mut buf = [0; 512];
+    serve_until(UdpSocket::bind(config.listen)?, config, observer, shutdown)
+}
 
-    loop {
+/// A DNS server bound to its socket, not yet handling queries.
+///
+/// Binding to port 0 lets the system pick a free port, which [`Server::local_addr`] tells.
+#[derive(Debug)]
+pub struct Server {
+    udp_socket: UdpSocket,
+    local_addr: SocketAddr,
+    config: ServerConfig,
+}
+
+impl Server {
+    /// Binds the socket of a server with the default configuration to `addr`, e.g.
+    /// `127.0.0.1:0`.
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        let udp_socket = UdpSocket::bind(addr)?;
+        let local_addr = udp_socket.local_addr()?;
+
+        Ok(Server {
+            udp_socket,
+            local_addr,
+            config: ServerConfig::default(),
+        })
+    }
+
+    /// Replaces the default configuration, whose listen address is ignored as the socket is
+    /// already bound.
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The address the socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Handles the queries received on the socket, see [`serve`].
+    pub fn serve(self) -> std::io::Result<()> {
+        serve(self.udp_socket, &self.config, &NoopObserver)
+    }
+
+    /// Handles the queries received on the socket until `shutdown` is set, see [`serve_until`].
+    pub fn serve_until(self, shutdown: &AtomicBool) -> std::io::Result<()> {
+        serve_until(self.udp_socket, &self.config, &NoopObserver, shutdown)
+    }
+}
+
+/// Handles the queries received on an already bound socket, as configured by `config`.
+pub fn serve(
+    udp_socket: UdpSocket,
+    config: &ServerConfig,
+    observer: &dyn QueryObserver,
+) -> std::io::Result<()> {
+    serve_until(udp_socket, config, observer, &AtomicBool::new(false))
+}
+
+/// Same as [`serve`], returning once `shutdown` is set. The flag is checked between queries, and
+/// at least every 100 milliseconds while none comes in.
+///
+/// Datagrams are read whole up to the largest UDP payload this server advertises, so that the
+/// queries of EDNS clients are not cut at 512 bytes.
+pub fn serve_until(
+    udp_socket: UdpSocket,
+    config: &ServerConfig,
+    observer: &dyn QueryObserver,
+    shutdown: &AtomicBool,
+) -> std::io::Result<()> {
+    let (zone, blocklist) = load_files(config)?;
+    let mut forwarder = Forwarder::from_config(config);
+    let mut rate_limiter = config
+        .rate_limit
+        .map(|max_queries| RateLimiter::new(max_queries, RATE_LIMIT_WINDOW));
+    let mut buf = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+    // Every reply is written into this buffer, reused from one query to the next
+    let mut response = Vec::with_capacity(MAX_UDP_PAYLOAD_SIZE as usize);
+    udp_socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+
+    while !shutdown.load(Ordering::Relaxed) {
         match udp_socket.recv_from(&mut buf) {
             Ok((size, source)) => {
                 println!("Received {} bytes from {}", size, source);
+                if let Some(rate_limiter) = rate_limiter.as_mut() {
+                    if !rate_limiter.allow(source.ip()) {
+                        println!("Dropping query from rate limited {}", source);
+                        continue;
+                    }
+                }
+                if !fits_message_budget(&buf[..size], config.max_message_size) {
+                    println!("Dropping oversized query from {}", source);
+                    continue;
+                }
 
-                let response = DnsMessage::new(&buf)
-                    .map(|query| query.build_reply())
-                    .unwrap_or_else(|_| {
-                        println!("Failed query parsing");
-                        DnsMessage::build_error_reply()
-                    })
-                    .to_bytes();
-
-                udp_socket.send_to(&response, source)?;
+                let answered = observe(
+                    observer,
+                    source,
+                    &buf[..size],
+                    &mut response,
+                    |query, client| match (&zone, &blocklist, forwarder.as_mut()) {
+                        (None, None, None) => handle_query(query, client),
+                        (zone, blocklist, forwarder) => handle_query_with(
+                            query,
+                            client,
+                            zone.as_ref(),
+                            blocklist.as_ref(),
+                            forwarder,
+                            config,
+                            observer,
+                        ),
+                    },
+                );
+                if answered {
+                    udp_socket.send_to(&response, source)?;
+                } else {
+                    println!("Ignoring response from {}", source);
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                // No query came in before the poll interval ran out
             }
             Err(e) => {
                 eprintln!("Error receiving data: {}", e);
@@ -33,4 +363,1516 @@ pub fn run() -> std::io::Result<()> {
             }
         }
     }
+
+    Ok(())
+}
+
+/// Loads the zone and the blocklist of the files named by `config`, if any. The blocklist also
+/// catches the names matching the patterns of `config`.
+fn load_files(config: &ServerConfig) -> std::io::Result<(Option<Zone>, Option<Blocklist>)> {
+    let zone = config
+        .zone_file
+        .as_ref()
+        .map(|path| {
+            let text = std::fs::read_to_string(path)?;
+            Zone::load(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .transpose()?;
+    let blocklist = config
+        .blocklist_file
+        .as_ref()
+        .map(|path| {
+            let text = std::fs::read_to_string(path)?;
+            Blocklist::load(&text, config.block_action)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .transpose()?;
+    #[cfg(feature = "regex")]
+    let blocklist = if config.block_patterns.is_empty() {
+        blocklist
+    } else {
+        let rules: Vec<(&str, PolicyAction)> = config
+            .block_patterns
+            .iter()
+            .map(|pattern| (pattern.as_str(), config.block_action))
+            .collect();
+        let patterns = RegexResolver::new(&rules)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Some(
+            blocklist
+                .unwrap_or_else(|| Blocklist::new(config.block_action))
+                .with_patterns(patterns),
+        )
+    };
+
+    Ok((zone, blocklist))
+}
+
+/// Whether the sections declared by the header of `buf` can fit both in the datagram and in
+/// `max_message_size` bytes, checked before parsing so that adversarial counts cost no work.
+/// Datagrams too short for a header are left to the parser, which answers them with FORMERR.
+fn fits_message_budget(buf: &[u8], max_message_size: usize) -> bool {
+    DnsHeader::new(buf).map_or(true, |header| {
+        header.min_message_size() <= buf.len().min(max_message_size)
+    })
+}
+
+/// Writes the datagram answering the query `buf` into `response`, telling `observer` about the
+/// query and the reply. Returns whether there is a reply to send.
+///
+/// The query is parsed once and `handle` builds the reply to it, given the address of the
+/// client; queries that cannot be parsed get an error reply instead. Datagrams that are
+/// themselves responses get no reply at all, lest two servers keep bouncing replies off each
+/// other.
+fn observe<F>(
+    observer: &dyn QueryObserver,
+    source: SocketAddr,
+    buf: &[u8],
+    response: &mut Vec<u8>,
+    handle: F,
+) -> bool
+where
+    F: FnOnce(&DnsMessage, IpAddr) -> DnsMessage,
+{
+    // Only the header is checked, so that malformed responses are not answered either
+    if DnsHeader::new(buf).is_ok_and(|header| header.is_response()) {
+        return false;
+    }
+
+    match DnsMessage::try_parse(buf) {
+        Ok(query) => {
+            observer.on_query(source, &query);
+            let reply = handle(&query, source.ip());
+            debug_assert_eq!(reply.validate(), Ok(()));
+            observer.on_response(&reply, reply.rcode());
+            reply.serialize_udp_into(query.max_udp_payload_size(), response);
+        }
+        Err(error) => {
+            observer.on_parse_failure(source);
+            let reply = error_reply(buf, &error);
+            observer.on_response(&reply, reply.rcode());
+            reply.serialize_into(response);
+        }
+    }
+
+    true
+}
+
+/// Builds the reply to `query`, sent from `client`.
+fn handle_query(query: &DnsMessage, client: IpAddr) -> DnsMessage {
+    match query.header().operation_code {
+        OpCode::Notify => query.build_notify_acknowledgment(false),
+        _ => {
+            let mut reply = query.build_reply();
+            reply.answer_cookie(query, client);
+            reply
+        }
+    }
+}
+
+/// Limits the number of queries answered for each source address, so that the server cannot be
+/// used to flood a spoofed victim with replies.
+///
+/// Each source gets `max_queries` queries per window, the window starting at its first query.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max_queries: u32,
+    window: Duration,
+    sources: HashMap<IpAddr, (Instant, u32)>,
+    last_pruned: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(max_queries: u32, window: Duration) -> Self {
+        RateLimiter {
+            max_queries,
+            window,
+            sources: HashMap::new(),
+            last_pruned: Instant::now(),
+        }
+    }
+
+    /// Counts a query from `source`, returning whether it is within the limit.
+    pub fn allow(&mut self, source: IpAddr) -> bool {
+        self.allow_at(source, Instant::now())
+    }
+
+    fn allow_at(&mut self, source: IpAddr, now: Instant) -> bool {
+        // Forget the sources whose window is over, so that the map does not grow forever
+        if now.duration_since(self.last_pruned) >= self.window {
+            let window = self.window;
+            self.sources
+                .retain(|_, (window_start, _)| now.duration_since(*window_start) < window);
+            self.last_pruned = now;
+        }
+
+        let (window_start, count) = self.sources.entry(source).or_insert((now, 0));
+        if now.duration_since(*window_start) >= self.window {
+            *window_start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+
+        *count <= self.max_queries
+    }
+}
+
+/// Answers questions with the help of upstream servers, remembering their answers.
+struct Forwarder {
+    resolver: Resolver,
+    upstreams: Vec<SocketAddr>,
+    cache: DnsCache,
+    serve_stale: bool,
+}
+
+impl Forwarder {
+    /// The forwarder to the resolvers of `config`, if it names any.
+    fn from_config(config: &ServerConfig) -> Option<Self> {
+        (!config.resolvers.is_empty()).then(|| {
+            Forwarder::new(
+                config.resolvers.clone(),
+                config.serve_stale,
+                config.upstream_policy,
+            )
+        })
+    }
+
+    fn new(upstreams: Vec<SocketAddr>, serve_stale: bool, upstream_policy: UpstreamPolicy) -> Self {
+        Forwarder {
+            resolver: Resolver::default().with_upstream_policy(upstream_policy),
+            upstreams,
+            cache: DnsCache::new(),
+            serve_stale,
+        }
+    }
+
+    /// Returns the cached answers to `question`, asking the upstream server when there are none.
+    /// The error is the response code of a negative answer other than NODATA, e.g. `NameError`.
+    ///
+    /// The cache is keyed case-insensitively, so the answers are found again whatever the casing
+    /// of the question or of the names in the upstream reply. Negative answers are cached too, as
+    /// long as the upstream reply tells for how long. When the upstream server fails, expired
+    /// answers are served if the forwarder was asked to, and SERVFAIL is returned otherwise.
+    fn answers(
+        &mut self,
+        question: &DnsQuestion,
+        observer: &dyn QueryObserver,
+    ) -> Result<Vec<DnsAnswerRecord>, ResponseCode> {
+        if let Some(cached) = self.cached_answers(question, observer) {
+            return cached;
+        }
+
+        match self
+            .resolver
+            .forward_with_failover(&self.upstreams, question)
+        {
+            Ok(response) if response.rcode() == ResponseCode::NoError => {
+                // Records that do not answer the question, e.g. injected to poison the cache, are
+                // left out
+                let answers: Vec<DnsAnswerRecord> = response
+                    .answers_for(question)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                if answers.is_empty() {
+                    self.cache.insert_negative(
+                        question,
+                        ResponseCode::NoError,
+                        &response.authorities,
+                    );
+                } else {
+                    self.cache.insert(question, answers.clone());
+                }
+                Ok(answers)
+            }
+            Ok(response) if response.rcode() == ResponseCode::NameError => {
+                self.cache.insert_negative(
+                    question,
+                    ResponseCode::NameError,
+                    &response.authorities,
+                );
+                Err(ResponseCode::NameError)
+            }
+            failure => {
+                let error = match failure {
+                    Ok(response) => DnsError::UpstreamFailure(response.rcode()),
+                    Err(error) => error,
+                };
+                eprintln!("Error forwarding question: {}", error);
+
+                self.serve_stale
+                    .then(|| self.cache.get_stale(question))
+                    .flatten()
+                    .ok_or(ResponseCode::ServerFailure)
+            }
+        }
+    }
+
+    /// Returns what the cache knows about `question`, without ever asking the upstream server:
+    /// the same as [`Forwarder::answers`], or `None` when nothing is cached.
+    fn cached_answers(
+        &self,
+        question: &DnsQuestion,
+        observer: &dyn QueryObserver,
+    ) -> Option<Result<Vec<DnsAnswerRecord>, ResponseCode>> {
+        let cached = self.cache.get(question);
+        observer.on_cache_lookup(question, cached.is_some());
+
+        match cached? {
+            CacheHit::Answers(answers) => Some(Ok(answers)),
+            CacheHit::NegativeHit(ResponseCode::NoError) => Some(Ok(Vec::new())),
+            CacheHit::NegativeHit(response_code) => Some(Err(response_code)),
+        }
+    }
+}
+
+/// Builds the reply to `query`, sent from `client`, with the records of `zone`, or else with the
+/// answers of `forwarder` after applying the TTL overrides of `config`. Queries not asking for
+/// recursion (RD) only get the answers the forwarder has cached.
+///
+/// Questions about the names caught by `blocklist` get the answer of its action instead. With
+/// neither a zone nor a forwarder, the other questions get the placeholder answer.
+///
+/// The reply offers recursion (RA) only when there is a forwarder. Without one, queries asking
+/// for recursion (RD) that the zone has no answer to are REFUSED.
+fn handle_query_with(
+    query: &DnsMessage,
+    client: IpAddr,
+    zone: Option<&Zone>,
+    blocklist: Option<&Blocklist>,
+    mut forwarder: Option<&mut Forwarder>,
+    config: &ServerConfig,
+    observer: &dyn QueryObserver,
+) -> DnsMessage {
+    if query.header().operation_code == OpCode::Notify {
+        // We are authoritative for the zones whose SOA record we hold
+        let authoritative_answer = zone.is_some_and(|zone| {
+            query
+                .questions()
+                .iter()
+                .any(|question| !zone.lookup(question).is_empty())
+        });
+        return query.build_notify_acknowledgment(authoritative_answer);
+    }
+
+    // With neither a zone nor a forwarder, the names the blocklist does not catch get the same
+    // placeholder answer as without one
+    let blocked = |question: &DnsQuestion| {
+        blocklist.is_some_and(|blocklist| blocklist.lookup(question).is_some())
+    };
+    if zone.is_none() && forwarder.is_none() && !query.questions().iter().any(blocked) {
+        return handle_query(query, client);
+    }
+
+    let recursion_desired = query.header().recursion_desired;
+    let mut response_code = None;
+    let mut reply = query.build_reply_with(|question| {
+        if let Some(action) = blocklist.and_then(|blocklist| blocklist.lookup(question)) {
+            response_code = Some(action.response_code());
+            return action.answers(question, config.default_ttl);
+        }
+
+        let zone_answers = zone.map(|zone| zone.lookup(question)).unwrap_or_default();
+
+        match forwarder.as_mut() {
+            Some(forwarder) if zone_answers.is_empty() => {
+                // Without RD, only what we already know is given (RFC 1034 section 4.3.1)
+                let answers = if recursion_desired {
+                    forwarder.answers(question, observer)
+                } else {
+                    forwarder
+                        .cached_answers(question, observer)
+                        .unwrap_or(Ok(Vec::new()))
+                };
+                match answers {
+                    Ok(answers) => config.ttl_overrides.apply(answers),
+                    Err(code) => {
+                        response_code = Some(code);
+                        Vec::new()
+                    }
+                }
+            }
+            _ => zone_answers,
+        }
+    });
+    if let Some(code) = response_code {
+        reply.set_rcode(code);
+    }
+    reply.answer_cookie(query, client);
+
+    // Recursion is only offered through the forwarder; without it, a client asking for
+    // recursion about names we hold nothing for must turn to another server
+    reply.header.recursion_available = forwarder.is_some();
+    if recursion_desired
+        && forwarder.is_none()
+        && response_code.is_none()
+        && reply.answers.is_empty()
+    {
+        reply.set_rcode(ResponseCode::Refused);
+    }
+
+    let negative = match reply.rcode() {
+        ResponseCode::NameError => true,
+        ResponseCode::NoError => reply.answers.is_empty(),
+        _ => false,
+    };
+    if let Some(soa) = config.soa.as_ref().filter(|_| negative) {
+        reply
+            .authorities
+            .push(soa_record(&config.soa_owner, soa, config.default_ttl));
+        reply.header.authority_record_count = reply.authorities.len();
+    }
+
+    reply
+}
+
+/// Builds the SOA record of the negative answers, owned by `owner`.
+fn soa_record(owner: &DomainName, soa: &Soa, time_to_live: u32) -> DnsAnswerRecord {
+    let r_data = RData::from_soa(soa);
+
+    DnsAnswerRecord {
+        domain_name: owner.clone(),
+        record_type: RecordType::SOA,
+        class: Class::IN,
+        time_to_live,
+        r_data_length: r_data.0.len(),
+        r_data,
+    }
+}
+
+/// Builds the reply sent back when the query `buf` cannot be parsed, with the response code
+/// matching `error`: FORMERR for every parsing error.
+///
+/// The reply echoes the identifier of the query so that the client can still match it, and its
+/// question when that part of the packet is well formed.
+fn error_reply(buf: &[u8], error: &DnsError) -> DnsMessage {
+    println!("Failed query parsing: {}", error);
+
+    let mut reply = DnsMessage::build_format_error_reply(buf).unwrap_or_else(|_| {
+        let packet_identifier = match buf {
+            [first_byte, second_byte, ..] => u16::from_be_bytes([*first_byte, *second_byte]),
+            _ => 0,
+        };
+        let recursion_desired = buf.get(2).is_some_and(|flags| flags & 0b00000001 != 0);
+        DnsMessage::build_error_reply(packet_identifier, recursion_desired)
+    });
+    reply.set_rcode(error.response_code());
+
+    reply
+}
+
+/// Starts and runs the DNS server on the tokio runtime
+///
+/// Same as [`run`], except that each query is handled in its own task so that a slow query does
+/// not hold back the others.
+#[cfg(feature = "tokio")]
+pub async fn run_async(config: ServerConfig) -> std::io::Result<()> {
+    let udp_socket = tokio::net::UdpSocket::bind(config.listen).await?;
+    serve_async(udp_socket, config).await
+}
+
+/// What the tasks of [`serve_async`] share to answer queries.
+#[cfg(feature = "tokio")]
+struct AsyncAnswerers {
+    zone: Option<Zone>,
+    blocklist: Option<Blocklist>,
+    forwarder: Option<std::sync::Mutex<Forwarder>>,
+    config: ServerConfig,
+}
+
+/// Handles the queries received on an already bound socket, as configured by `config`, spawning
+/// a task per query.
+///
+/// Forwarded queries share a single forwarder and its cache, so they are resolved one at a time;
+/// queries answered from the zone, the blocklist or the cache do not wait for them.
+#[cfg(feature = "tokio")]
+pub async fn serve_async(
+    udp_socket: tokio::net::UdpSocket,
+    config: ServerConfig,
+) -> std::io::Result<()> {
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    let (zone, blocklist) = load_files(&config)?;
+    let answerers = Arc::new(AsyncAnswerers {
+        zone,
+        blocklist,
+        forwarder: Forwarder::from_config(&config).map(Mutex::new),
+        config,
+    });
+    let mut rate_limiter = answerers
+        .config
+        .rate_limit
+        .map(|max_queries| RateLimiter::new(max_queries, RATE_LIMIT_WINDOW));
+    let udp_socket = Arc::new(udp_socket);
+
+    loop {
+        let mut buf = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+        let (size, source) = udp_socket.recv_from(&mut buf).await?;
+        println!("Received {} bytes from {}", size, source);
+        if let Some(rate_limiter) = rate_limiter.as_mut() {
+            if !rate_limiter.allow(source.ip()) {
+                println!("Dropping query from rate limited {}", source);
+                continue;
+            }
+        }
+        if !fits_message_budget(&buf[..size], answerers.config.max_message_size) {
+            println!("Dropping oversized query from {}", source);
+            continue;
+        }
+
+        let datagram = buf[..size].to_vec();
+        let answerers = answerers.clone();
+        let udp_socket = udp_socket.clone();
+        tokio::spawn(async move {
+            // Forwarding blocks on the sockets of the resolver, so it is kept off the runtime
+            let answered = tokio::task::spawn_blocking(move || {
+                let AsyncAnswerers {
+                    zone,
+                    blocklist,
+                    forwarder,
+                    config,
+                } = &*answerers;
+                let mut response = Vec::new();
+                let answered = observe(
+                    &NoopObserver,
+                    source,
+                    &datagram,
+                    &mut response,
+                    |query, client| match (zone, blocklist, forwarder) {
+                        (None, None, None) => handle_query(query, client),
+                        (zone, blocklist, forwarder) => {
+                            let mut forwarder = forwarder.as_ref().map(|forwarder| {
+                                forwarder.lock().unwrap_or_else(PoisonError::into_inner)
+                            });
+                            handle_query_with(
+                                query,
+                                client,
+                                zone.as_ref(),
+                                blocklist.as_ref(),
+                                forwarder.as_deref_mut(),
+                                config,
+                                &NoopObserver,
+                            )
+                        }
+                    },
+                );
+                answered.then_some(response)
+            })
+            .await;
+
+            match answered {
+                Ok(Some(response)) => {
+                    if let Err(e) = udp_socket.send_to(&response, source).await {
+                        eprintln!("Error sending response to {}: {}", source, e);
+                    }
+                }
+                Ok(None) => println!("Ignoring response from {}", source),
+                Err(e) => eprintln!("Error handling query from {}: {}", source, e),
+            }
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::dns::{
+        answer_record::RData, opt_record::EdnsOption, Class, OptRecord, RecordType, ResponseCode,
+    };
+
+    use super::*;
+
+    /// Address of the client sending the queries of the tests.
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Collects the datagram the server sends back to [`CLIENT`] for the query `buf`, answered
+    /// with `handle`.
+    fn datagram(buf: &[u8], handle: impl FnOnce(&DnsMessage, IpAddr) -> DnsMessage) -> Vec<u8> {
+        let mut response = Vec::new();
+        observe(
+            &NoopObserver,
+            SocketAddr::new(CLIENT, 5353),
+            buf,
+            &mut response,
+            handle,
+        );
+        response
+    }
+
+    #[test]
+    fn test_server_config_from_args() {
+        assert_eq!(
+            ServerConfig::from_args(args(&[])),
+            Ok(ServerConfig::default())
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&[
+                "--resolver",
+                "8.8.8.8:53",
+                "--listen",
+                "0.0.0.0:53",
+                "--resolver",
+                "1.1.1.1:53",
+                "--round-robin"
+            ])),
+            Ok(ServerConfig {
+                listen: SocketAddr::from(([0, 0, 0, 0], 53)),
+                resolvers: vec![
+                    SocketAddr::from(([8, 8, 8, 8], 53)),
+                    SocketAddr::from(([1, 1, 1, 1], 53))
+                ],
+                upstream_policy: UpstreamPolicy::RoundRobin,
+                zone_file: None,
+                ttl_overrides: TtlOverrides::new(),
+                serve_stale: false,
+                rate_limit: None,
+                blocklist_file: None,
+                block_action: PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED),
+                #[cfg(feature = "regex")]
+                block_patterns: Vec::new(),
+                default_ttl: DEFAULT_TTL,
+                soa: None,
+                soa_owner: DomainName::root(),
+                max_message_size: usize::from(MAX_UDP_PAYLOAD_SIZE),
+            })
+        );
+
+        assert_eq!(
+            ServerConfig::from_args(args(&["--blocklist", "ads.txt", "--block-nxdomain"]))
+                .map(|config| (config.blocklist_file, config.block_action)),
+            Ok((Some(PathBuf::from("ads.txt")), PolicyAction::NameError))
+        );
+
+        #[cfg(feature = "regex")]
+        assert_eq!(
+            ServerConfig::from_args(args(&[
+                "--block-regex",
+                r"^ads\.",
+                "--block-regex",
+                "^tracker"
+            ]))
+            .map(|config| config.block_patterns),
+            Ok(vec![r"^ads\.".to_string(), "^tracker".to_string()])
+        );
+
+        assert_eq!(
+            ServerConfig::from_args(args(&["--serve-stale"])).map(|config| config.serve_stale),
+            Ok(true)
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--rate-limit", "20"])).map(|config| config.rate_limit),
+            Ok(Some(20))
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--rate-limit", "many"])),
+            Err(ArgsError::InvalidRateLimit("many".to_string()))
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--max-message-size", "1232"]))
+                .map(|config| config.max_message_size),
+            Ok(1232)
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--zone", "example.com.zone"]))
+                .map(|config| config.zone_file),
+            Ok(Some(PathBuf::from("example.com.zone")))
+        );
+
+        let mut ttl_overrides = TtlOverrides::new();
+        ttl_overrides.insert(&"volatile.example.com".parse().unwrap(), 5);
+        assert_eq!(
+            ServerConfig::from_args(args(&["--ttl-override", "volatile.example.com=5"]))
+                .map(|config| config.ttl_overrides),
+            Ok(ttl_overrides)
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--ttl-override", "volatile.example.com"])),
+            Err(ArgsError::InvalidTtlOverride(
+                "volatile.example.com".to_string()
+            ))
+        );
+
+        assert_eq!(
+            ServerConfig::from_args(args(&["--resolver", "8.8.8.8"])),
+            Err(ArgsError::InvalidAddress(
+                "--resolver".to_string(),
+                "8.8.8.8".to_string()
+            ))
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--listen"])),
+            Err(ArgsError::MissingValue("--listen".to_string()))
+        );
+        assert_eq!(
+            ServerConfig::from_args(args(&["--verbose"])),
+            Err(ArgsError::UnknownArgument("--verbose".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let now = Instant::now();
+        let flooding: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let mut rate_limiter = RateLimiter::new(3, Duration::from_secs(1));
+
+        let burst: Vec<bool> = (0..5)
+            .map(|_| rate_limiter.allow_at(flooding, now))
+            .collect();
+        assert_eq!(burst, [true, true, true, false, false]);
+        assert!(rate_limiter.allow_at(other, now));
+
+        // The next window starts afresh, and the sources that went quiet are forgotten
+        assert!(rate_limiter.allow_at(flooding, now + Duration::from_secs(1)));
+        assert!(rate_limiter.allow_at(flooding, now + Duration::from_secs(3)));
+        assert_eq!(rate_limiter.sources.len(), 1);
+    }
+
+    #[test]
+    fn test_observe() {
+        #[derive(Default)]
+        struct RecordingObserver {
+            events: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl QueryObserver for RecordingObserver {
+            fn on_query(&self, source: SocketAddr, query: &DnsMessage) {
+                let event = format!("query {} from {}", query.header().packet_identifier, source);
+                self.events.lock().unwrap().push(event);
+            }
+
+            fn on_response(&self, response: &DnsMessage, response_code: ResponseCode) {
+                let event = format!(
+                    "response {} {:?}",
+                    response.header().packet_identifier,
+                    response_code
+                );
+                self.events.lock().unwrap().push(event);
+            }
+        }
+
+        let observer = RecordingObserver::default();
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+        let mut query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        query.header.packet_identifier = 1234;
+
+        let mut response = Vec::new();
+        assert!(observe(
+            &observer,
+            source,
+            &query.to_bytes(),
+            &mut response,
+            handle_query
+        ));
+        assert_eq!(response, datagram(&query.to_bytes(), handle_query));
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            ["query 1234 from 192.0.2.1:5353", "response 1234 NoError"]
+        );
+
+        // A query that cannot be parsed is not seen, but its error reply is
+        let mut buf = [0; 512];
+        buf[..6].copy_from_slice(&[0x04, 0xD2, 0x01, 0x00, 0xFF, 0xFF]);
+        observe(&observer, source, &buf, &mut response, handle_query);
+        assert_eq!(
+            observer.events.lock().unwrap()[2..],
+            ["response 1234 FormatError"]
+        );
+    }
+
+    #[test]
+    fn test_observe_drops_responses() {
+        let metrics = crate::metrics::ServerMetrics::new();
+        let source = SocketAddr::from(([192, 0, 2, 1], 53));
+        let reply = DnsMessage::query("codecrafters.io", RecordType::A)
+            .unwrap()
+            .build_reply();
+        assert!(reply.is_response());
+
+        assert!(!observe(
+            &metrics,
+            source,
+            &reply.to_bytes(),
+            &mut Vec::new(),
+            handle_query
+        ));
+
+        // A response whose body cannot be parsed is not answered either
+        let mut malformed = [0; 512];
+        malformed[..6].copy_from_slice(&[0x04, 0xD2, 0x81, 0x00, 0xFF, 0xFF]);
+        assert!(!observe(
+            &metrics,
+            source,
+            &malformed,
+            &mut Vec::new(),
+            handle_query
+        ));
+        assert_eq!(metrics.snapshot(), Default::default());
+    }
+
+    #[test]
+    fn test_server_metrics() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // The upstream server answers a single query, the second one being answered from the cache
+        let upstream_handle = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let reply = DnsMessage::new(&buf).unwrap().build_reply();
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let metrics = crate::metrics::ServerMetrics::new();
+        let config = ServerConfig::default();
+        let mut forwarder = Forwarder::new(vec![upstream_address], false, UpstreamPolicy::Ordered);
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+        let query = DnsMessage::query("codecrafters.io", RecordType::A)
+            .unwrap()
+            .to_bytes();
+        let mut malformed = [0; 512];
+        malformed[..6].copy_from_slice(&[0x04, 0xD2, 0x01, 0x00, 0xFF, 0xFF]);
+
+        let mut response = Vec::new();
+        for buf in [query, query, malformed] {
+            observe(&metrics, source, &buf, &mut response, |query, client| {
+                let forwarder = Some(&mut forwarder);
+                handle_query_with(query, client, None, None, forwarder, &config, &metrics)
+            });
+        }
+        upstream_handle.join().unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.queries_received, 3);
+        assert_eq!(snapshot.parse_failures, 1);
+        assert_eq!(snapshot.responses_sent, 3);
+        assert_eq!(snapshot.responses_with(ResponseCode::NoError), 2);
+        assert_eq!(snapshot.responses_with(ResponseCode::FormatError), 1);
+        assert_eq!(snapshot.cache_misses, 1);
+        assert_eq!(snapshot.cache_hits, 1);
+    }
+
+    #[test]
+    fn test_handle_query_format_error() {
+        let mut buf = [0; 512];
+        // Header for ID 0xABCD with RD set, followed by a corrupt question section
+        buf[..12].copy_from_slice(&[0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        // The name of the question is a compression pointer to itself
+        buf[12..14].copy_from_slice(&[0xc0, 0x0c]);
+
+        let reply = datagram(&buf, handle_query);
+        let mut packet = [0; 512];
+        packet[..reply.len()].copy_from_slice(&reply);
+        let reply = DnsMessage::new(&packet).unwrap();
+
+        assert_eq!(reply.header.packet_identifier, 0xabcd);
+        assert!(reply.header.is_response());
+        assert_eq!(reply.rcode(), ResponseCode::FormatError);
+        assert!(reply.answers.is_empty());
+
+        // The second of two questions is cut short
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let question = query.first_question().unwrap().to_bytes();
+        let mut truncated = query.to_vec();
+        truncated[5] = 2;
+        truncated.extend_from_slice(&question[..question.len() - 3]);
+        let reply = datagram(&truncated, handle_query);
+        let reply = DnsMessage::try_parse(&reply).unwrap();
+        assert_eq!(
+            reply.header.packet_identifier,
+            query.header.packet_identifier
+        );
+        assert_eq!(reply.rcode(), ResponseCode::FormatError);
+
+        // Not even the header is whole
+        let reply = datagram(&[0xab, 0xcd, 0x01, 0x00], handle_query);
+        let reply = DnsMessage::try_parse(&reply).unwrap();
+        assert_eq!(reply.header.packet_identifier, 0xabcd);
+        assert!(reply.header.recursion_desired);
+        assert_eq!(reply.rcode(), ResponseCode::FormatError);
+    }
+
+    #[test]
+    fn test_serve_receives_queries_larger_than_512_bytes() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        std::thread::spawn(move || serve(server_socket, &ServerConfig::default(), &NoopObserver));
+
+        // EDNS padding (RFC 7830) takes the query past 512 bytes
+        let mut query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let mut opt = OptRecord::new(MAX_UDP_PAYLOAD_SIZE);
+        opt.options.push(EdnsOption {
+            code: 12,
+            data: vec![0; 700],
+        });
+        query.additionals.push(DnsAnswerRecord::from(&opt));
+        let query_bytes = query.to_vec();
+        assert!(query_bytes.len() > 512);
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(&query_bytes, server_address).unwrap();
+        let mut buf = [0; 512];
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        let reply = DnsMessage::try_parse(&buf[..size]).unwrap();
+
+        assert_eq!(
+            reply.header.packet_identifier,
+            query.header.packet_identifier
+        );
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert_eq!(reply.questions(), query.questions());
+        assert!(reply.opt().is_some());
+    }
+
+    #[test]
+    fn test_serve_drops_queries_over_message_budget() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        let config = ServerConfig {
+            max_message_size: 512,
+            ..ServerConfig::default()
+        };
+        std::thread::spawn(move || serve(server_socket, &config, &NoopObserver));
+
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let mut absurd = query.to_vec();
+        absurd[4..12].fill(0xff);
+        assert!(!fits_message_budget(&absurd, usize::MAX));
+        // A thousand questions could fit in a datagram, but not in the budget of the server
+        let mut many_questions = vec![0; 12 + 1000 * 5];
+        many_questions[4..6].copy_from_slice(&1000u16.to_be_bytes());
+        assert!(fits_message_budget(&many_questions, usize::MAX));
+        assert!(!fits_message_budget(&many_questions, 512));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(&absurd, server_address).unwrap();
+        client.send_to(&many_questions, server_address).unwrap();
+        client.send_to(&query.to_vec(), server_address).unwrap();
+
+        // Only the query within the budget is answered
+        let mut buf = [0; 512];
+        client.recv_from(&mut buf).unwrap();
+        assert_eq!(
+            DnsMessage::new(&buf).unwrap().header.packet_identifier,
+            query.header.packet_identifier
+        );
+    }
+
+    #[test]
+    fn test_serve_until_shutdown() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        let shutdown = std::sync::Arc::new(AtomicBool::new(false));
+        let server_handle = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || {
+                serve_until(
+                    server_socket,
+                    &ServerConfig::default(),
+                    &NoopObserver,
+                    &shutdown,
+                )
+            }
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        client.send_to(&query.to_vec(), server_address).unwrap();
+        let mut buf = [0; 512];
+        client.recv_from(&mut buf).unwrap();
+        assert_eq!(
+            DnsMessage::new(&buf).unwrap().header.packet_identifier,
+            query.header.packet_identifier
+        );
+
+        shutdown.store(true, Ordering::Relaxed);
+        assert!(server_handle.join().unwrap().is_ok());
+        // The socket was released along with the server
+        assert!(UdpSocket::bind(server_address).is_ok());
+    }
+
+    #[test]
+    fn test_server_bind_to_ephemeral_port() {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let server_address = server.local_addr();
+        assert_eq!(server_address.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_ne!(server_address.port(), 0);
+        std::thread::spawn(move || server.serve());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        client.send_to(&query.to_vec(), server_address).unwrap();
+        let mut buf = [0; 512];
+        let (_, source) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(source, server_address);
+    }
+
+    #[test]
+    fn test_handle_query_with_zone() {
+        let zone = Zone::load("www.example.com. 300 IN A 1.2.3.4").unwrap();
+        let query = |name: &[u8]| {
+            let mut buf = [0; 512];
+            let bytes = [
+                &[0x00, 0x2a, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0][..],
+                name,
+                &[0x00, 0x01, 0x00, 0x01],
+            ]
+            .concat();
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            buf
+        };
+        let reply = |bytes: Vec<u8>| {
+            let mut packet = [0; 512];
+            packet[..bytes.len()].copy_from_slice(&bytes);
+            DnsMessage::new(&packet).unwrap()
+        };
+        let www_example_com: DomainName = "www.example.com".parse().unwrap();
+        let ftp_example_com: DomainName = "ftp.example.com".parse().unwrap();
+
+        let answered = reply(datagram(
+            &query(&www_example_com.wire_format),
+            |query, client| {
+                handle_query_with(
+                    query,
+                    client,
+                    Some(&zone),
+                    None,
+                    None,
+                    &ServerConfig::default(),
+                    &NoopObserver,
+                )
+            },
+        ));
+        assert_eq!(
+            answered.answers,
+            zone.lookup(answered.first_question().unwrap())
+        );
+        assert_eq!(answered.answers[0].r_data, RData(vec![1, 2, 3, 4]));
+
+        assert!(!answered.header.recursion_available);
+        assert_eq!(answered.rcode(), ResponseCode::NoError);
+
+        // Recursion is refused for names outside of the zone when there is nothing to forward to
+        let mut ftp_query = query(&ftp_example_com.wire_format);
+        let refused = reply(datagram(&ftp_query, |query, client| {
+            handle_query_with(
+                query,
+                client,
+                Some(&zone),
+                None,
+                None,
+                &ServerConfig::default(),
+                &NoopObserver,
+            )
+        }));
+        assert!(refused.answers.is_empty());
+        assert_eq!(refused.rcode(), ResponseCode::Refused);
+
+        // Without RD the query is simply left unanswered
+        ftp_query[2] = 0x00;
+        let unanswered = reply(datagram(&ftp_query, |query, client| {
+            handle_query_with(
+                query,
+                client,
+                Some(&zone),
+                None,
+                None,
+                &ServerConfig::default(),
+                &NoopObserver,
+            )
+        }));
+        assert!(unanswered.answers.is_empty());
+        assert_eq!(unanswered.rcode(), ResponseCode::NoError);
+    }
+
+    #[test]
+    fn test_handle_query_with_blocklist() {
+        let zone = Zone::load("www.example.com. 300 IN A 1.2.3.4").unwrap();
+        let resolve = |blocklist: &Blocklist, name: &str| {
+            let query = DnsMessage::query(name, RecordType::A).unwrap();
+            DnsMessage::try_parse(&datagram(&query.to_bytes(), |query, client| {
+                handle_query_with(
+                    query,
+                    client,
+                    Some(&zone),
+                    Some(blocklist),
+                    None,
+                    &ServerConfig::default(),
+                    &NoopObserver,
+                )
+            }))
+            .unwrap()
+        };
+        let sinkhole = Blocklist::load(
+            "ads.example.com",
+            PolicyAction::Sinkhole(Ipv4Addr::UNSPECIFIED),
+        )
+        .unwrap();
+
+        let exact = resolve(&sinkhole, "ads.example.com");
+        assert_eq!(exact.rcode(), ResponseCode::NoError);
+        assert_eq!(
+            exact.answers()[0].r_data.as_a(),
+            Some(Ipv4Addr::UNSPECIFIED)
+        );
+
+        let subdomain = resolve(&sinkhole, "tracker.ads.example.com");
+        assert_eq!(
+            subdomain.answers()[0].to_zone_line(),
+            "tracker.ads.example.com. 60 IN A 0.0.0.0"
+        );
+
+        let passed_through = resolve(&sinkhole, "www.example.com");
+        assert_eq!(
+            passed_through.answers()[0].r_data.as_a(),
+            Some(Ipv4Addr::new(1, 2, 3, 4))
+        );
+
+        // Blocked names can also be reported as nonexistent
+        let name_error = Blocklist::load("ads.example.com", PolicyAction::NameError).unwrap();
+        let blocked = resolve(&name_error, "tracker.ads.example.com");
+        assert_eq!(blocked.rcode(), ResponseCode::NameError);
+        assert!(blocked.answers().is_empty());
+
+        // Without a zone or a resolver, the other names get the placeholder answer
+        let blocklist_only = |name: &str| {
+            let query = DnsMessage::query(name, RecordType::A).unwrap();
+            DnsMessage::try_parse(&datagram(&query.to_bytes(), |query, client| {
+                handle_query_with(
+                    query,
+                    client,
+                    None,
+                    Some(&name_error),
+                    None,
+                    &ServerConfig::default(),
+                    &NoopObserver,
+                )
+            }))
+            .unwrap()
+        };
+        let placeholder = blocklist_only("www.example.com");
+        assert_eq!(placeholder.rcode(), ResponseCode::NoError);
+        assert_eq!(
+            placeholder.answers()[0].r_data.as_a(),
+            Some(Ipv4Addr::new(8, 8, 8, 8))
+        );
+        assert_eq!(
+            blocklist_only("tracker.ads.example.com").rcode(),
+            ResponseCode::NameError
+        );
+
+        // Or caught by the patterns of the configuration
+        #[cfg(feature = "regex")]
+        {
+            let config = ServerConfig {
+                block_action: PolicyAction::NameError,
+                block_patterns: vec![r"^tracker\d*\.".to_string()],
+                ..ServerConfig::default()
+            };
+            let (_, patterns) = load_files(&config).unwrap();
+            let matched = resolve(&patterns.unwrap(), "tracker7.example.com");
+            assert_eq!(matched.rcode(), ResponseCode::NameError);
+
+            let invalid = ServerConfig {
+                block_patterns: vec!["(unclosed".to_string()],
+                ..config
+            };
+            assert!(load_files(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_handle_query_with_negative_soa() {
+        let blocklist = Blocklist::load("ads.example.com", PolicyAction::NameError).unwrap();
+        let soa = Soa {
+            primary_server: "ns.example.com".parse().unwrap(),
+            administrator: "admin.example.com".parse().unwrap(),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        };
+        let resolve = |config: &ServerConfig| {
+            let query = DnsMessage::query("ads.example.com", RecordType::A).unwrap();
+            DnsMessage::try_parse(&datagram(&query.to_bytes(), |query, client| {
+                handle_query_with(
+                    query,
+                    client,
+                    None,
+                    Some(&blocklist),
+                    None,
+                    config,
+                    &NoopObserver,
+                )
+            }))
+            .unwrap()
+        };
+
+        let without_soa = resolve(&ServerConfig::default());
+        assert_eq!(without_soa.rcode(), ResponseCode::NameError);
+        assert!(without_soa.authorities.is_empty());
+
+        let config = ServerConfig {
+            default_ttl: 900,
+            soa: Some(soa.clone()),
+            ..ServerConfig::default()
+        };
+        let with_soa = resolve(&config);
+        assert_eq!(with_soa.rcode(), ResponseCode::NameError);
+        assert_eq!(with_soa.authorities.len(), 1);
+        let authority = &with_soa.authorities[0];
+        assert!(authority.domain_name.is_root());
+        assert_eq!(authority.record_type, RecordType::SOA);
+        assert_eq!(authority.time_to_live, 900);
+        assert_eq!(authority.r_data.as_soa(), Some(soa.clone()));
+
+        // The record can be owned by the apex of the zone it describes
+        let example_com: DomainName = "example.com".parse().unwrap();
+        let config = ServerConfig {
+            soa_owner: example_com.clone(),
+            ..config
+        };
+        let with_owner = resolve(&config);
+        assert_eq!(with_owner.authorities.len(), 1);
+        assert_eq!(with_owner.authorities[0].domain_name, example_com);
+        assert_eq!(with_owner.authorities[0].r_data.as_soa(), Some(soa));
+    }
+
+    #[test]
+    fn test_handle_query_with_recursion_available() {
+        let query = DnsMessage::query("www.example.com", RecordType::A).unwrap();
+        let answer =
+            DnsAnswerRecord::new_a("www.example.com", Ipv4Addr::new(1, 2, 3, 4), 300).unwrap();
+        // The answer is cached, so the upstream server is never asked
+        let mut forwarder = Forwarder::new(
+            vec![SocketAddr::from(([127, 0, 0, 1], 9))],
+            false,
+            UpstreamPolicy::Ordered,
+        );
+        forwarder
+            .cache
+            .insert(query.first_question().unwrap(), vec![answer]);
+
+        let reply = DnsMessage::try_parse(&datagram(&query.to_bytes(), |query, client| {
+            handle_query_with(
+                query,
+                client,
+                None,
+                None,
+                Some(&mut forwarder),
+                &ServerConfig::default(),
+                &NoopObserver,
+            )
+        }))
+        .unwrap();
+        assert!(reply.header().recursion_desired);
+        assert!(reply.header().recursion_available);
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert_eq!(reply.answers().len(), 1);
+    }
+
+    #[test]
+    fn test_handle_query_without_recursion_desired() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        upstream.set_nonblocking(true).unwrap();
+        let mut forwarder = Forwarder::new(
+            vec![upstream.local_addr().unwrap()],
+            false,
+            UpstreamPolicy::Ordered,
+        );
+        let mut query = DnsMessage::query("www.example.com", RecordType::A).unwrap();
+        query.header.recursion_desired = false;
+
+        let reply = DnsMessage::try_parse(&datagram(&query.to_bytes(), |query, client| {
+            handle_query_with(
+                query,
+                client,
+                None,
+                None,
+                Some(&mut forwarder),
+                &ServerConfig::default(),
+                &NoopObserver,
+            )
+        }))
+        .unwrap();
+        assert!(!reply.header().recursion_desired);
+        assert_eq!(reply.rcode(), ResponseCode::NoError);
+        assert!(reply.answers().is_empty());
+
+        // The upstream server was never asked
+        let mut buf = [0; 512];
+        assert_eq!(
+            upstream.recv_from(&mut buf).map_err(|e| e.kind()),
+            Err(std::io::ErrorKind::WouldBlock)
+        );
+    }
+
+    #[test]
+    fn test_handle_query_with_notify() {
+        // ns.example.com. admin.example.com. 1 7200 3600 1209600 300, in the generic form
+        let zone = Zone::load(concat!(
+            "example.com. 300 IN SOA \\# 55 026e73076578616d706c6503636f6d000561646d696e07657861",
+            "6d706c6503636f6d000000000100001c2000000e10001275000000012c"
+        ))
+        .unwrap();
+        let notify = |name: &str| {
+            let mut notify = DnsMessage::query(name, RecordType::SOA).unwrap();
+            notify.header.operation_code = OpCode::Notify;
+            notify.header.recursion_desired = false;
+            notify
+        };
+        let acknowledge = |notify: &DnsMessage| {
+            DnsMessage::try_parse(&datagram(&notify.to_bytes(), |query, client| {
+                handle_query_with(
+                    query,
+                    client,
+                    Some(&zone),
+                    None,
+                    None,
+                    &ServerConfig::default(),
+                    &NoopObserver,
+                )
+            }))
+            .unwrap()
+        };
+
+        let example_com = notify("example.com");
+        let acknowledgment = acknowledge(&example_com);
+        assert_eq!(
+            acknowledgment.header().packet_identifier,
+            example_com.header().packet_identifier
+        );
+        assert!(acknowledgment.header().is_response());
+        assert_eq!(acknowledgment.header().operation_code, OpCode::Notify);
+        assert_eq!(acknowledgment.rcode(), ResponseCode::NoError);
+        assert!(acknowledgment.header().authoritative_answer);
+        assert_eq!(acknowledgment.questions(), example_com.questions());
+        assert!(acknowledgment.answers().is_empty());
+
+        // Zones we do not hold are acknowledged without authority
+        let acknowledgment = acknowledge(&notify("example.org"));
+        assert_eq!(acknowledgment.rcode(), ResponseCode::NoError);
+        assert!(!acknowledgment.header().authoritative_answer);
+    }
+
+    #[test]
+    fn test_forwarder_caches_answers_case_insensitively() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // The upstream server answers a single query, with the name in a different casing
+        let upstream_handle = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let reply = DnsMessage::new(&buf).unwrap().build_reply_with(|question| {
+                vec![DnsAnswerRecord {
+                    domain_name: "WWW.Example.COM".parse().unwrap(),
+                    record_type: question.record_type,
+                    class: question.class,
+                    time_to_live: 300,
+                    r_data_length: 4,
+                    r_data: RData(vec![1, 2, 3, 4]),
+                }]
+            });
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let question = |name: &str| DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let mut forwarder = Forwarder::new(vec![upstream_address], false, UpstreamPolicy::Ordered);
+
+        let answers = forwarder
+            .answers(&question("www.example.com"), &NoopObserver)
+            .unwrap();
+        upstream_handle.join().unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].r_data, RData(vec![1, 2, 3, 4]));
+
+        // The upstream server is gone, so these come from the cache
+        assert_eq!(
+            forwarder.answers(&question("www.example.com"), &NoopObserver),
+            Ok(answers.clone())
+        );
+        assert_eq!(
+            forwarder.answers(&question("WWW.EXAMPLE.com"), &NoopObserver),
+            Ok(answers)
+        );
+    }
+
+    #[test]
+    fn test_forwarder_keeps_only_the_answers_to_the_question() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // The upstream server follows an alias, and slips in a record for an unrelated name
+        let upstream_handle = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let reply = DnsMessage::new(&buf).unwrap().build_reply_with(|_| {
+                vec![
+                    DnsAnswerRecord::new_cname("www.example.com", "web.example.com", 300).unwrap(),
+                    DnsAnswerRecord::new_a("web.example.com", Ipv4Addr::new(1, 2, 3, 4), 300)
+                        .unwrap(),
+                    DnsAnswerRecord::new_a("bank.example.net", Ipv4Addr::new(6, 6, 6, 6), 300)
+                        .unwrap(),
+                ]
+            });
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let question = |name: &str| DnsQuestion {
+            domain_name: name.parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let mut forwarder = Forwarder::new(vec![upstream_address], false, UpstreamPolicy::Ordered);
+
+        let answers = forwarder
+            .answers(&question("www.example.com"), &NoopObserver)
+            .unwrap();
+        upstream_handle.join().unwrap();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].record_type, RecordType::CNAME);
+        assert_eq!(answers[1].r_data.as_a(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        // The unrelated record was not cached either
+        assert_eq!(
+            forwarder.cached_answers(&question("bank.example.net"), &NoopObserver),
+            None
+        );
+    }
+
+    #[test]
+    fn test_forwarder_serves_stale_answers_on_upstream_failure() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+
+        // The upstream server fails to answer two queries
+        let upstream_handle = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let mut buf = [0; 512];
+                let (_, source) = upstream.recv_from(&mut buf).unwrap();
+                let mut reply = DnsMessage::new(&buf)
+                    .unwrap()
+                    .build_reply_with(|_| Vec::new());
+                reply.set_rcode(ResponseCode::ServerFailure);
+                upstream.send_to(&reply.to_vec(), source).unwrap();
+            }
+        });
+
+        let question = DnsQuestion {
+            domain_name: "www.example.com".parse().unwrap(),
+            record_type: RecordType::A,
+            class: Class::IN,
+        };
+        let stale_answer = DnsAnswerRecord {
+            domain_name: question.domain_name.clone(),
+            record_type: RecordType::A,
+            class: Class::IN,
+            time_to_live: 0,
+            r_data_length: 4,
+            r_data: RData(vec![1, 2, 3, 4]),
+        };
+
+        // Without serve-stale the expired answer is not used, and the failure is passed on
+        let mut forwarder = Forwarder::new(vec![upstream_address], false, UpstreamPolicy::Ordered);
+        forwarder
+            .cache
+            .insert(&question, vec![stale_answer.clone()]);
+        assert_eq!(
+            forwarder.answers(&question, &NoopObserver),
+            Err(ResponseCode::ServerFailure)
+        );
+
+        forwarder.serve_stale = true;
+        let answers = forwarder.answers(&question, &NoopObserver).unwrap();
+        upstream_handle.join().unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].r_data, stale_answer.r_data);
+        assert!(answers[0].time_to_live > 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serve_async_answers_concurrent_queries() {
+        let server_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        tokio::spawn(serve_async(server_socket, ServerConfig::default()));
+
+        let clients = (0..8u16).map(|id| {
+            tokio::spawn(async move {
+                let query = [
+                    &id.to_be_bytes()[..],
+                    &[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                    &[0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01],
+                ]
+                .concat();
+
+                let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                socket.send_to(&query, server_address).await.unwrap();
+
+                let mut buf = [0; 512];
+                socket.recv_from(&mut buf).await.unwrap();
+                DnsMessage::new(&buf).unwrap()
+            })
+        });
+
+        for (id, client) in (0..8u16).zip(clients) {
+            let reply = client.await.unwrap();
+            assert_eq!(reply.header.packet_identifier, id);
+            assert_eq!(reply.answers.len(), 1);
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serve_async_forwards_as_configured() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_handle = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, source) = upstream.recv_from(&mut buf).unwrap();
+            let reply = DnsMessage::new(&buf).unwrap().build_reply_with(|question| {
+                vec![DnsAnswerRecord {
+                    domain_name: question.domain_name.clone(),
+                    record_type: RecordType::A,
+                    class: Class::IN,
+                    time_to_live: 60,
+                    r_data_length: 4,
+                    r_data: RData(vec![1, 2, 3, 4]),
+                }]
+            });
+            upstream.send_to(&reply.to_vec(), source).unwrap();
+        });
+
+        let server_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        let config = ServerConfig {
+            resolvers: vec![upstream_address],
+            ..ServerConfig::default()
+        };
+        tokio::spawn(serve_async(server_socket, config));
+
+        let query = DnsMessage::query("codecrafters.io", RecordType::A).unwrap();
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket
+            .send_to(&query.to_vec(), server_address)
+            .await
+            .unwrap();
+        let mut buf = [0; 512];
+        socket.recv_from(&mut buf).await.unwrap();
+        let reply = DnsMessage::new(&buf).unwrap();
+        upstream_handle.join().unwrap();
+
+        // The answer comes from the upstream server rather than the placeholder
+        assert!(reply.header.recursion_available);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(reply.answers[0].r_data, RData(vec![1, 2, 3, 4]));
+    }
 }